@@ -0,0 +1,14 @@
+use hot_cheese::HotCheeseClient;
+
+/// same as `pin_cert` but on tokio, the connection is shared between all calls
+/// `cargo run --example async_client <name>`
+#[tokio::main]
+async fn main() {
+    let name = std::env::args().nth(1).expect("missing key name");
+    let cert = include_bytes!("../src/conf/ssl-cert.pem");
+    let client = HotCheeseClient::new("https://localhost:5555", cert).unwrap();
+    println!("{}", client.health().await.unwrap());
+    println!("{}", client.address(&name).await.unwrap());
+    let (a, b) = tokio::join!(client.health(), client.health());
+    println!("{} {}", a.unwrap(), b.unwrap());
+}
@@ -40,28 +40,32 @@ impl HotCheeseAgent {
         let res = self
             .agent
             .get(format!("{}{}", self.base, "/health").as_str())
-            .call()?;
+            .call()
+            .map_err(Box::new)?;
         Ok(res.into_string().unwrap_or_default())
     }
     pub fn generate(&self, name: &str) -> Result<String, HotAgentErr> {
         let res = self
             .agent
             .get(format!("{}{}{}", self.base, "/evm_generate/", name).as_str())
-            .call()?;
+            .call()
+            .map_err(Box::new)?;
         Ok(res.into_string().unwrap_or_default())
     }
     pub fn address(&self, name: &str) -> Result<String, HotAgentErr> {
         let res = self
             .agent
             .get(format!("{}{}{}", self.base, "/evm_address/", name).as_str())
-            .call()?;
+            .call()
+            .map_err(Box::new)?;
         Ok(res.into_string().unwrap_or_default())
     }
     pub fn solana_address(&self, name: &str) -> Result<String, HotAgentErr> {
         let res = self
             .agent
             .get(format!("{}{}{}", self.base, "/solana_address/", name).as_str())
-            .call()?;
+            .call()
+            .map_err(Box::new)?;
         Ok(res.into_string().unwrap_or_default())
     }
     pub fn read(&self, name: &str) -> Result<Vec<u8>, HotAgentErr> {
@@ -71,7 +75,8 @@ impl HotCheeseAgent {
         let res = self
             .agent
             .get(format!("{}{}{}", self.base, "/read/", name).as_str())
-            .send_bytes(&serde_json::to_vec(&to_send)?)?;
+            .send_bytes(&serde_json::to_vec(&to_send)?)
+            .map_err(Box::new)?;
         let res_str = res.into_string()?;
         println!("received {}", res_str);
         let enc_res: ServerEncryptedRes = serde_json::from_str(res_str.as_str())?;
@@ -82,7 +87,7 @@ impl HotCheeseAgent {
 create_err_with_impls!(
     #[derive(Debug)]
     pub HotAgentErr,
-    Ureq(ureq::Error),
+    Ureq(Box<ureq::Error>),
     Unspecified(Unspecified),
     Serde(serde_json::Error),
     IO(std::io::Error)
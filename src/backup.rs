@@ -0,0 +1,190 @@
+//! Single-file backup bundles. Every keystore, the manifest and the config files are put in
+//! one JSON document that is sealed with XChaCha20-Poly1305 under a key derived from the master
+//! key, the unencrypted header is the associated data. A bundle can only be opened with the
+//! master key it was made with, and any edit to it makes opening fail.
+use crate::crypto::{bytes_hex, now_secs, open_aead, seal_aead, CryptoErr, KdfConfig, KdfParams};
+use err_mac::create_err_with_impls;
+use rand::{CryptoRng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zeroize::Zeroize;
+
+pub const BACKUP_FORMAT: &str = "hot_cheese-backup";
+const BACKUP_VERSION: u8 = 1;
+const BACKUP_CIPHER: &str = "xchacha20-poly1305";
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub BackupErr,
+    Serde(serde_json::Error),
+    Crypto(CryptoErr),
+    NotABackup,
+    UnsupportedVersion(u8),
+    UnsupportedCipher(String)
+    ;
+);
+
+/// bytes that are wiped when dropped
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Blob(#[serde(with = "bytes_hex")] pub Vec<u8>);
+
+impl Drop for Blob {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for Blob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Blob({} bytes)", self.0.len())
+    }
+}
+
+/// what a bundle holds once opened
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Contents {
+    /// keystores and the manifest, by their name in the store
+    pub store: BTreeMap<String, Blob>,
+    /// config files by file name
+    pub conf: BTreeMap<String, Blob>,
+}
+
+/// what `verify-backup` found in a bundle that opened and checked out
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupReport {
+    /// unix seconds
+    pub created_at: u64,
+    /// every one of them decrypted
+    pub keys: Vec<String>,
+    pub conf: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Header<'a> {
+    format: &'a str,
+    version: u8,
+    created_at: u64,
+    cipher: &'a str,
+    kdfparams: &'a KdfParams,
+}
+
+#[derive(Deserialize, Serialize)]
+struct BundleFile {
+    format: String,
+    version: u8,
+    /// unix seconds
+    created_at: u64,
+    cipher: String,
+    kdfparams: KdfParams,
+    #[serde(with = "bytes_hex")]
+    nonce: Vec<u8>,
+    #[serde(with = "bytes_hex")]
+    ciphertext: Vec<u8>,
+}
+
+impl BundleFile {
+    fn aad(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(&Header {
+            format: &self.format,
+            version: self.version,
+            created_at: self.created_at,
+            cipher: &self.cipher,
+            kdfparams: &self.kdfparams,
+        })
+    }
+}
+
+/// seals `contents` under `master`, stretched with `kdf`
+pub fn seal<R: Rng + CryptoRng>(
+    rng: &mut R,
+    master: &[u8],
+    kdf: &KdfConfig,
+    contents: &Contents,
+) -> Result<Vec<u8>, BackupErr> {
+    let kdfparams = KdfParams::new(rng, kdf);
+    let mut key = kdfparams.derive(master)?;
+    let mut bundle = BundleFile {
+        format: BACKUP_FORMAT.into(),
+        version: BACKUP_VERSION,
+        created_at: now_secs(),
+        cipher: BACKUP_CIPHER.into(),
+        kdfparams,
+        nonce: Vec::new(),
+        ciphertext: Vec::new(),
+    };
+    let mut plain = serde_json::to_vec(contents)?;
+    let res = seal_aead(rng, &key, &plain, &bundle.aad()?);
+    plain.zeroize();
+    key.zeroize();
+    (bundle.nonce, bundle.ciphertext) = res?;
+    Ok(serde_json::to_vec_pretty(&bundle)?)
+}
+
+/// when the bundle was made, readable without the master key
+pub fn created_at(bundle: &[u8]) -> Result<u64, BackupErr> {
+    Ok(parse(bundle)?.created_at)
+}
+
+fn parse(bundle: &[u8]) -> Result<BundleFile, BackupErr> {
+    let file: BundleFile = serde_json::from_slice(bundle).map_err(|_| BackupErr::NotABackup)?;
+    if file.format != BACKUP_FORMAT {
+        return Err(BackupErr::NotABackup);
+    }
+    if file.version != BACKUP_VERSION {
+        return Err(BackupErr::UnsupportedVersion(file.version));
+    }
+    if file.cipher != BACKUP_CIPHER {
+        return Err(BackupErr::UnsupportedCipher(file.cipher));
+    }
+    Ok(file)
+}
+
+/// authenticates and decrypts a bundle. a corrupted bundle and one made under another
+/// master key both fail with `CryptoErr::MacMismatch`
+pub fn open(master: &[u8], bundle: &[u8]) -> Result<Contents, BackupErr> {
+    let file = parse(bundle)?;
+    let mut key = file.kdfparams.derive(master)?;
+    let res = open_aead(&key, &file.nonce, &file.ciphertext, &file.aad()?);
+    key.zeroize();
+    let mut plain = res?;
+    let contents = serde_json::from_slice(&plain);
+    plain.zeroize();
+    Ok(contents?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seal_open() {
+        let kdf = KdfConfig::Argon2id {
+            m_cost: 256,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let mut contents = Contents::default();
+        contents.store.insert("A".into(), Blob(b"a".to_vec()));
+        contents.conf.insert("c.json".into(), Blob(b"{}".to_vec()));
+        let bundle = seal(&mut rand::thread_rng(), b"master", &kdf, &contents).unwrap();
+
+        let opened = open(b"master", &bundle).unwrap();
+        assert_eq!(opened.store, contents.store);
+        assert_eq!(opened.conf, contents.conf);
+        assert!(created_at(&bundle).unwrap() > 0);
+
+        assert!(matches!(
+            open(b"other", &bundle),
+            Err(BackupErr::Crypto(CryptoErr::MacMismatch))
+        ));
+        // the header is authenticated too
+        let mut file: serde_json::Value = serde_json::from_slice(&bundle).unwrap();
+        file["created_at"] = 1.into();
+        let edited = serde_json::to_vec(&file).unwrap();
+        assert!(matches!(
+            open(b"master", &edited),
+            Err(BackupErr::Crypto(CryptoErr::MacMismatch))
+        ));
+        assert!(matches!(open(b"master", b"{}"), Err(BackupErr::NotABackup)));
+    }
+}
@@ -0,0 +1,169 @@
+//! the external signer API of geth's Clef over JSON-RPC, so geth (`--signer`) and other
+//! tooling that speaks it can sign with the stored evm keys. accounts are addressed by
+//! address instead of key name, every signature still goes through the approval prompt
+use crate::crypto::to_vec;
+use crate::evm::{to_hex, Address, SignedTransaction, Transaction, TypedData};
+use crate::server::{ApiBackendErr, HotApi};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// the version of the Clef external API this speaks
+pub const VERSION: &str = "6.1.0";
+
+pub(crate) const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+/// what Clef answers when a request is refused or fails
+pub(crate) const DENIED: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+pub(crate) struct RpcErr(pub i64, pub String);
+
+impl From<ApiBackendErr> for RpcErr {
+    fn from(e: ApiBackendErr) -> Self {
+        RpcErr(DENIED, e.to_string())
+    }
+}
+
+pub(crate) fn invalid(e: impl std::fmt::Display) -> RpcErr {
+    RpcErr(INVALID_PARAMS, e.to_string())
+}
+
+pub(crate) fn param(params: &[Value], i: usize) -> Result<&Value, RpcErr> {
+    params
+        .get(i)
+        .ok_or(RpcErr(INVALID_PARAMS, format!("missing param {}", i)))
+}
+
+pub(crate) fn address(params: &[Value], i: usize) -> Result<Address, RpcErr> {
+    Address::deserialize(param(params, i)?).map_err(invalid)
+}
+
+/// geth sends the calldata as `input` and a null `data`, others send `data` only
+pub(crate) fn transaction(args: &Value) -> Result<Transaction, RpcErr> {
+    let mut args = args
+        .as_object()
+        .ok_or(invalid("expected a transaction"))?
+        .clone();
+    let input = args.remove("input").filter(|v| !v.is_null());
+    let data = args.remove("data").filter(|v| !v.is_null());
+    if let Some(data) = input.or(data) {
+        args.insert("data".into(), data);
+    }
+    serde_json::from_value(Value::Object(args)).map_err(invalid)
+}
+
+/// `n` as a JSON-RPC quantity, no leading zeros
+fn big_quantity(n: &[u8]) -> String {
+    let hex: String = n.iter().map(|b| format!("{:02x}", b)).collect();
+    match hex.trim_start_matches('0') {
+        "" => "0x0".into(),
+        digits => format!("0x{}", digits),
+    }
+}
+
+/// the signed transaction in the JSON geth decodes a `types.Transaction` from
+fn tx_json(tx: &Transaction, signed: &SignedTransaction) -> Result<Value, RpcErr> {
+    let tx_type = tx.validate().map_err(invalid)?;
+    let mut out = serde_json::to_value(tx).expect("plain struct");
+    let fields = out.as_object_mut().expect("a struct");
+    fields.remove("from");
+    let input = fields.remove("data").expect("always written");
+    fields.insert("input".into(), input);
+    fields.insert("type".into(), json!(format!("{:#x}", tx_type)));
+    if tx_type != 0 && tx.access_list.is_none() {
+        fields.insert("accessList".into(), json!([]));
+    }
+    if tx_type != 0 {
+        fields.insert("yParity".into(), json!(format!("{:#x}", signed.v)));
+    }
+    fields.insert("v".into(), json!(format!("{:#x}", signed.v)));
+    fields.insert("r".into(), json!(big_quantity(&signed.r.0)));
+    fields.insert("s".into(), json!(big_quantity(&signed.s.0)));
+    fields.insert("hash".into(), json!(signed.hash));
+    Ok(out)
+}
+
+fn call(hot: &HotApi, method: &str, params: &[Value]) -> Result<Value, RpcErr> {
+    match method {
+        "account_version" => Ok(json!(VERSION)),
+        "account_list" => {
+            let accounts: Vec<Address> = hot.evm_accounts()?.into_iter().map(|(_, a)| a).collect();
+            Ok(json!(accounts))
+        }
+        "account_signTransaction" => {
+            let tx = transaction(param(params, 0)?)?;
+            let from = tx.from.ok_or(invalid("missing from"))?;
+            let name = hot.account_name(&from)?;
+            let signed = hot.sign_transaction(&name, &tx)?;
+            Ok(json!({
+                "raw": to_hex(&signed.raw),
+                "tx": tx_json(&tx, &signed)?,
+            }))
+        }
+        "account_signData" => {
+            let content_type = param(params, 0)?.as_str().unwrap_or_default();
+            let name = hot.account_name(&address(params, 1)?)?;
+            let data = param(params, 2)?;
+            let signed = match content_type {
+                "text/plain" => {
+                    let message = data
+                        .as_str()
+                        .and_then(to_vec)
+                        .ok_or(invalid("expected hex data"))?;
+                    hot.personal_sign(&name, &message)?
+                }
+                "data/typed" => {
+                    let typed_data = TypedData::deserialize(data).map_err(invalid)?;
+                    hot.sign_typed_data(&name, &typed_data)?
+                }
+                other => {
+                    return Err(invalid(format!("unsupported content type '{}'", other)));
+                }
+            };
+            Ok(json!(to_hex(&signed.signature)))
+        }
+        "account_signTypedData" => {
+            let name = hot.account_name(&address(params, 0)?)?;
+            let typed_data = TypedData::deserialize(param(params, 1)?).map_err(invalid)?;
+            let signed = hot.sign_typed_data(&name, &typed_data)?;
+            Ok(json!(to_hex(&signed.signature)))
+        }
+        other => Err(RpcErr(
+            METHOD_NOT_FOUND,
+            format!("the method {} does not exist/is not available", other),
+        )),
+    }
+}
+
+/// answers one JSON-RPC request, errors included, the way Clef would
+pub fn handle(hot: &HotApi, body: &[u8]) -> Value {
+    let req = match serde_json::from_slice::<Request>(body) {
+        Ok(req) => req,
+        Err(e) => return response(Value::Null, Err(RpcErr(PARSE_ERROR, e.to_string()))),
+    };
+    let res = call(hot, &req.method, &req.params);
+    if let Err(RpcErr(_, message)) = res.as_ref() {
+        hot.backend().communicate_err(message.clone());
+    }
+    response(req.id, res)
+}
+
+pub(crate) fn response(id: Value, res: Result<Value, RpcErr>) -> Value {
+    match res {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(RpcErr(code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": code, "message": message},
+        }),
+    }
+}
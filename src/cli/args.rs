@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// flags that take a value, everything else starting with `--` is a switch
+const VALUE_FLAGS: &[&str] = &[
+    "config",
+    "type",
+    "kdf",
+    "target-ms",
+    "threshold",
+    "shares",
+    "upstream",
+    "listen",
+    "backup",
+];
+
+/// `hot_cheese <command> [positional..] [--flag value] [--switch]`
+pub struct Args {
+    pub cmd: String,
+    pub positional: Vec<String>,
+    flags: HashMap<String, Option<String>>,
+}
+
+impl Args {
+    pub fn parse(raw: Vec<String>) -> Result<Self, String> {
+        let mut cmd = None;
+        let mut positional = Vec::new();
+        let mut flags = HashMap::new();
+        let mut raw = raw.into_iter();
+        while let Some(arg) = raw.next() {
+            if let Some(flag) = arg.strip_prefix("--") {
+                let value = if VALUE_FLAGS.contains(&flag) {
+                    Some(raw.next().ok_or(format!("--{} needs a value", flag))?)
+                } else {
+                    None
+                };
+                flags.insert(flag.to_string(), value);
+            } else if cmd.is_none() {
+                cmd = Some(arg);
+            } else {
+                positional.push(arg);
+            }
+        }
+        Ok(Self {
+            cmd: cmd.unwrap_or("serve".into()),
+            positional,
+            flags,
+        })
+    }
+    pub fn switch(&self, name: &str) -> bool {
+        self.flags.contains_key(name)
+    }
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).and_then(|v| v.as_deref())
+    }
+    pub fn nth(&self, idx: usize, what: &str) -> Result<&str, String> {
+        self.positional
+            .get(idx)
+            .map(|s| s.as_str())
+            .ok_or(format!("missing <{}>", what))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Args {
+        Args::parse(s.split_whitespace().map(|s| s.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn parses() {
+        let args = parse("address TRADER --type solana --json");
+        assert_eq!(args.cmd, "address");
+        assert_eq!(args.nth(0, "name").unwrap(), "TRADER");
+        assert_eq!(args.value("type"), Some("solana"));
+        assert!(args.switch("json"));
+        assert!(args.nth(1, "other").is_err());
+
+        assert_eq!(parse("--json").cmd, "serve");
+        assert!(Args::parse(vec!["list".into(), "--config".into()]).is_err());
+    }
+}
@@ -0,0 +1,559 @@
+//! `hot_cheese <command>`, everything that used to live in examples and shell scripts
+use crate::crypto::calibrate;
+use crate::secure_fs::write_atomic;
+use crate::server::{is_valid_string_name, load_certs, load_private_key};
+use crate::{
+    resolve_path, run_proxy, run_server, ApiBackendErr, Config, ConfigErr, Engine, HotApi,
+    KdfConfig, KeyType, MacBackend, PaperKey,
+};
+use args::Args;
+use err_mac::create_err_with_impls;
+use rand::{rngs::OsRng, RngCore};
+use rpassword::read_password;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use zeroize::Zeroize;
+
+mod args;
+
+pub const EXIT_OK: i32 = 0;
+/// the operation itself failed
+pub const EXIT_FAILED: i32 = 1;
+/// bad arguments or unknown command
+pub const EXIT_USAGE: i32 = 2;
+/// the device owner did not approve
+pub const EXIT_DENIED: i32 = 3;
+
+const USAGE: &str = "usage: hot_cheese <command> [--config <path>] [--json]
+
+commands:
+  serve                              run the https server (default)
+  proxy [--upstream <url>] [--listen <addr>]
+                                     sign for dapps as a JSON-RPC node in front of <url>
+  init [--random]                    provision the master key, typed or randomly generated
+  import <name> [--type <t>]         encrypt an existing secret read from stdin
+  generate <name> [--type <t>]       generate a new key
+  address <name> [--type <t>]        print the address of a key
+  list                               list stored keys
+  delete <name>                      delete a key
+  backup <file>                      write an encrypted bundle of the store and src/conf
+  verify-backup <file>               check that every key in a bundle decrypts
+  restore <file>                     restore a bundle into an empty store and src/conf
+  upgrade [<name>..] [--type <t>]    reseal v3 or below-kdf keystores (all by default)
+  outdated                           list keystores that upgrade would reseal
+  manifest                           accept the current store contents as the integrity manifest
+  audit                              print the audit trail (sqlite engine)
+  migrate <engine>                   copy the store into another engine (dir, sqlite)
+  doctor [--fix]                     check config, store, master key and certs
+  export-paper <name> [--passphrase]
+                                     print a key encrypted under a passphrase for paper
+  import-paper [<name>] [--passphrase]
+                                     import a paper backup read from stdin
+  split [<name>] [--threshold <t>] [--shares <n>] [--passphrase]
+                                     SLIP-39 shares of the master key or of a key (2 of 3)
+  recover [<name>] [--type <t>] [--passphrase] [--backup <file>]
+                                     rebuild the master key or a key from shares on stdin
+  calibrate [--kdf <k>] [--target-ms <ms>]
+                                     find KDF parameters taking ~ms (1000) on this machine
+
+<t> is one of evm (default), solana, bytes
+<k> is one of argon2id (default), scrypt";
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub CliErr,
+    Usage(String),
+    Config(ConfigErr),
+    Api(ApiBackendErr),
+    IO(std::io::Error),
+    Checks(Value),
+    Refused(&'static str)
+    ;
+);
+
+fn key_type(args: &Args) -> Result<KeyType, CliErr> {
+    args.value("type")
+        .unwrap_or("evm")
+        .parse()
+        .map_err(CliErr::Usage)
+}
+
+/// runs one command and returns the process exit code
+pub fn run_cli(raw: Vec<String>) -> i32 {
+    let args = match Args::parse(raw) {
+        Ok(args) => args,
+        Err(e) => return report(&Err(CliErr::Usage(e)), false),
+    };
+    let json = args.switch("json");
+    let res = Config::load(args.value("config"))
+        .map_err(CliErr::from)
+        .and_then(|conf| dispatch(&args, &conf));
+    report(&res, json)
+}
+
+fn dispatch(args: &Args, conf: &Config) -> Result<Value, CliErr> {
+    let api = || HotApi::new(Box::new(backend(conf)));
+    match args.cmd.as_str() {
+        "serve" => serve(conf),
+        "proxy" => proxy(conf, args),
+        "init" => init(&api(), args.switch("random")),
+        "import" => import(&api(), name(args)?, key_type(args)?),
+        "generate" => generate(&api(), name(args)?, key_type(args)?),
+        "address" => address(&api(), name(args)?, key_type(args)?),
+        "list" => Ok(json!(api().list()?)),
+        "delete" => {
+            let name = name(args)?;
+            api().delete(name)?;
+            Ok(json!(format!("deleted '{}'", name)))
+        }
+        "backup" => backup(&api(), args.nth(0, "file").map_err(CliErr::Usage)?),
+        "verify-backup" => verify_backup(&api(), args.nth(0, "file").map_err(CliErr::Usage)?),
+        "restore" => restore(&api(), args.nth(0, "file").map_err(CliErr::Usage)?),
+        "upgrade" => upgrade(&api(), args),
+        "outdated" => Ok(json!(api().outdated()?)),
+        "manifest" => Ok(json!(api().rebuild_manifest()?)),
+        "audit" => Ok(json!(api().audit_log()?)),
+        "migrate" => migrate(&api(), conf, args),
+        "doctor" => doctor(&api(), args.switch("fix")),
+        "export-paper" => export_paper(&api(), name(args)?),
+        "import-paper" => import_paper(&api(), args),
+        "split" => split(&api(), args),
+        "recover" => recover(&api(), args),
+        "calibrate" => calibrate_kdf(args),
+        "help" => Ok(json!(USAGE)),
+        other => Err(CliErr::Usage(format!("unknown command '{}'", other))),
+    }
+}
+
+/// prints the outcome (human or `--json`) and maps it to an exit code
+fn report(res: &Result<Value, CliErr>, json: bool) -> i32 {
+    let code = match res {
+        Ok(_) => EXIT_OK,
+        Err(CliErr::Usage(_)) => EXIT_USAGE,
+        Err(CliErr::Api(ApiBackendErr::NotDeviceOwner)) => EXIT_DENIED,
+        Err(_) => EXIT_FAILED,
+    };
+    match (res, json) {
+        (Ok(v), true) => println!("{}", json!({ "ok": true, "result": v })),
+        (Ok(v), false) => print_human(v),
+        (Err(CliErr::Checks(v)), true) => println!("{}", json!({ "ok": false, "result": v })),
+        (Err(CliErr::Checks(v)), false) => print_human(v),
+        (Err(e), true) => println!("{}", json!({ "ok": false, "error": e.to_string() })),
+        (Err(CliErr::Usage(e)), false) => eprintln!("{}\n\n{}", e, USAGE),
+        (Err(e), false) => eprintln!("{}", e),
+    }
+    code
+}
+
+fn print_human(v: &Value) {
+    match v {
+        Value::String(s) => println!("{}", s),
+        Value::Array(items) => items.iter().for_each(print_human),
+        Value::Null => {}
+        other => println!(
+            "{}",
+            serde_json::to_string_pretty(other).unwrap_or_default()
+        ),
+    }
+}
+
+fn name(args: &Args) -> Result<&str, CliErr> {
+    let name = args.nth(0, "name").map_err(CliErr::Usage)?;
+    if !is_valid_string_name(name) {
+        return Err(CliErr::Usage(format!("invalid key name '{}'", name)));
+    }
+    Ok(name)
+}
+
+fn backend(conf: &Config) -> MacBackend {
+    MacBackend::new(&conf.service, &conf.account, &conf.store)
+        .with_kdf(conf.kdf.clone())
+        .with_engine(conf.engine)
+        .with_policies(conf.policies.clone())
+}
+
+fn serve(conf: &Config) -> Result<Value, CliErr> {
+    run_server(Box::new(backend(conf))).map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok(Value::Null)
+}
+
+/// the `proxy` section of the config, `--upstream` and `--listen` override it
+fn proxy(conf: &Config, args: &Args) -> Result<Value, CliErr> {
+    let mut proxy = conf.proxy.clone().ok_or(CliErr::Usage(
+        "no \"proxy\" section with the keys to sign with in the config".into(),
+    ))?;
+    if let Some(upstream) = args.value("upstream") {
+        proxy.upstream = upstream.into();
+    }
+    if let Some(listen) = args.value("listen") {
+        proxy.listen = listen.into();
+    }
+    run_proxy(Box::new(backend(conf)), proxy).map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok(Value::Null)
+}
+
+/// provisions the master key through the backend, a random one is 32 bytes from the OS rng
+fn init(api: &HotApi, random: bool) -> Result<Value, CliErr> {
+    let backend = api.backend();
+    if backend.has_encryption_key() {
+        return Err(CliErr::Refused(
+            "master key already exists, replacing it would orphan every stored key",
+        ));
+    }
+    let mut master = if random {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let hex = bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        bytes.zeroize();
+        hex
+    } else {
+        eprintln!("reading password");
+        let mut master_password = read_password()?;
+        eprintln!("repeat password");
+        let mut rep_master_password = read_password()?;
+        let matches = master_password == rep_master_password;
+        rep_master_password.zeroize();
+        if !matches {
+            master_password.zeroize();
+            return Err(CliErr::Usage("passwords don't match".into()));
+        }
+        master_password
+    };
+    if !backend.is_device_owner("provision master key") {
+        master.zeroize();
+        return Err(ApiBackendErr::NotDeviceOwner.into());
+    }
+    let added = backend.set_encryption_key(master.as_bytes());
+    master.zeroize();
+    if !added {
+        return Err(CliErr::Refused("backend failed to store the master key"));
+    }
+    Ok(json!("master key added, back up the keychain item"))
+}
+
+fn import(api: &HotApi, name: &str, key_type: KeyType) -> Result<Value, CliErr> {
+    let prompt = match key_type {
+        KeyType::Evm => "provide pk as hex str with 0x or without 0x",
+        KeyType::Solana => "provide pk as base58 encoded string",
+        KeyType::Bytes => "provide utf8 string to save",
+    };
+    eprintln!("{}", prompt);
+    let mut pks = read_password()?;
+    let pk = match key_type {
+        KeyType::Evm => df_share::from_hex_str(&pks).ok(),
+        KeyType::Solana => bs58::decode(&pks).into_vec().ok(),
+        KeyType::Bytes => Some(pks.as_bytes().to_vec()),
+    };
+    pks.zeroize();
+    let mut pk = pk.ok_or(CliErr::Usage("could not decode the key".into()))?;
+    let res = api.import(name, key_type, &pk);
+    pk.zeroize();
+    res?;
+    Ok(json!(format!("imported '{}'", name)))
+}
+
+fn generate(api: &HotApi, name: &str, key_type: KeyType) -> Result<Value, CliErr> {
+    match key_type {
+        KeyType::Evm => api.generate(name)?,
+        KeyType::Solana => api.generate_solana(name)?,
+        KeyType::Bytes => return Err(CliErr::Usage("can only generate evm or solana keys".into())),
+    }
+    address(api, name, key_type)
+}
+
+fn address(api: &HotApi, name: &str, key_type: KeyType) -> Result<Value, CliErr> {
+    match key_type {
+        KeyType::Evm => Ok(json!(api.address(name)?)),
+        KeyType::Solana => Ok(json!(api.address_solana(name)?)),
+        KeyType::Bytes => Err(CliErr::Usage("bytes keys have no address".into())),
+    }
+}
+
+fn upgrade(api: &HotApi, args: &Args) -> Result<Value, CliErr> {
+    let names = if args.positional.is_empty() {
+        api.list()?
+    } else {
+        args.positional.clone()
+    };
+    let key_type = match args.value("type") {
+        Some(_) => Some(key_type(args)?),
+        None => None,
+    };
+    Ok(json!(api.upgrade(&names, key_type)?))
+}
+
+/// prints a `"kdf"` entry for `cheese_config.json`, nothing is written
+fn calibrate_kdf(args: &Args) -> Result<Value, CliErr> {
+    let start = match args.value("kdf").unwrap_or("argon2id") {
+        "argon2id" => KdfConfig::default(),
+        "scrypt" => KdfConfig::Scrypt {
+            log_n: 14,
+            r: 8,
+            p: 1,
+        },
+        other => return Err(CliErr::Usage(format!("unknown kdf '{}'", other))),
+    };
+    let target_ms = match args.value("target-ms") {
+        Some(ms) => ms
+            .parse()
+            .map_err(|_| CliErr::Usage(format!("invalid --target-ms '{}'", ms)))?,
+        None => 1000,
+    };
+    let (kdf, took) =
+        calibrate(&start, Duration::from_millis(target_ms)).map_err(ApiBackendErr::from)?;
+    Ok(json!({ "kdf": kdf, "measured_ms": took.as_millis() as u64 }))
+}
+
+/// the old store is left as is, switching `"engine"` is up to the user
+fn migrate(api: &HotApi, conf: &Config, args: &Args) -> Result<Value, CliErr> {
+    let name = args.nth(0, "engine").map_err(CliErr::Usage)?;
+    let engine: Engine = name.parse().map_err(CliErr::Usage)?;
+    if engine == conf.engine {
+        return Err(CliErr::Usage(format!("already using the {} engine", name)));
+    }
+    let target = engine.key_store(&resolve_path(&conf.store));
+    let copied = api.migrate(target.as_ref())?;
+    Ok(json!(format!(
+        "copied {} keys, set \"engine\": \"{}\" in the config to use them",
+        copied.len(),
+        name
+    )))
+}
+
+fn count_flag(args: &Args, flag: &str, default: u8) -> Result<u8, CliErr> {
+    match args.value(flag) {
+        Some(v) => v
+            .parse()
+            .map_err(|_| CliErr::Usage(format!("--{} takes a number", flag))),
+        None => Ok(default),
+    }
+}
+
+/// empty unless `--passphrase` is set
+fn optional_passphrase(args: &Args, confirm: bool) -> Result<String, CliErr> {
+    match args.switch("passphrase") {
+        true => passphrase(confirm),
+        false => Ok(String::new()),
+    }
+}
+
+/// typed twice when `confirm`
+fn passphrase(confirm: bool) -> Result<String, CliErr> {
+    eprintln!("reading passphrase");
+    let mut passphrase = read_password()?;
+    if confirm {
+        eprintln!("repeat passphrase");
+        let mut repeated = read_password()?;
+        let matches = passphrase == repeated;
+        repeated.zeroize();
+        if !matches {
+            passphrase.zeroize();
+            return Err(CliErr::Usage("passphrases don't match".into()));
+        }
+    }
+    Ok(passphrase)
+}
+
+/// without a name the master key is split
+fn split(api: &HotApi, args: &Args) -> Result<Value, CliErr> {
+    let threshold = count_flag(args, "threshold", 2)?;
+    let count = count_flag(args, "shares", 3)?;
+    let mut passphrase = optional_passphrase(args, true)?;
+    let res = match args.positional.first() {
+        Some(name) => api.split_key(name, threshold, count, passphrase.as_bytes()),
+        None => api.split_master(threshold, count, passphrase.as_bytes()),
+    };
+    passphrase.zeroize();
+    Ok(json!(res?))
+}
+
+/// one share per line on stdin
+fn recover(api: &HotApi, args: &Args) -> Result<Value, CliErr> {
+    // the bundle the recovered master key has to open, for an empty store
+    let backup = args.value("backup").map(std::fs::read).transpose()?;
+    eprintln!("enter shares, one per line, then an empty line");
+    let mut shares = read_lines()?;
+    let mut passphrase = optional_passphrase(args, false)?;
+    let res = match args.positional.first() {
+        Some(name) => api
+            .recover_key(name, key_type(args)?, &shares, passphrase.as_bytes())
+            .map(|_| format!("recovered '{}'", name)),
+        None => api
+            .recover_master(&shares, passphrase.as_bytes(), backup.as_deref())
+            .map(|_| "master key recovered".to_string()),
+    };
+    passphrase.zeroize();
+    shares.iter_mut().for_each(|s| s.zeroize());
+    Ok(json!(res?))
+}
+
+/// lines from stdin up to an empty line or the end of input
+fn read_lines() -> Result<Vec<String>, CliErr> {
+    let mut lines = Vec::new();
+    for line in std::io::stdin().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+/// the passphrase is always asked for, a paper backup without one would be the key in clear
+fn export_paper(api: &HotApi, name: &str) -> Result<Value, CliErr> {
+    let mut passphrase = passphrase(true)?;
+    let res = api.export_paper(name, passphrase.as_bytes());
+    passphrase.zeroize();
+    Ok(json!(res?.to_text()))
+}
+
+/// without a name the one printed on the backup is used
+fn import_paper(api: &HotApi, args: &Args) -> Result<Value, CliErr> {
+    eprintln!("enter the paper backup or just its key, then an empty line");
+    let mut text = read_lines()?.join("\n");
+    let name = match args.positional.first() {
+        Some(name) => name.clone(),
+        None => PaperKey::parse(&text)
+            .map_err(ApiBackendErr::from)?
+            .name
+            .ok_or(CliErr::Usage("no name on the backup, pass <name>".into()))?,
+    };
+    let mut passphrase = passphrase(false)?;
+    let res = api.import_paper(&name, &text, passphrase.as_bytes());
+    passphrase.zeroize();
+    text.zeroize();
+    res?;
+    Ok(json!(format!("imported '{}'", name)))
+}
+
+/// config files a backup carries along, they are compiled in from here
+const CONF_DIR: &str = "src/conf";
+
+/// the bundle is verified right after it is written
+fn backup(api: &HotApi, path: &str) -> Result<Value, CliErr> {
+    let mut conf = BTreeMap::new();
+    if Path::new(CONF_DIR).is_dir() {
+        for entry in std::fs::read_dir(CONF_DIR)? {
+            let entry = entry?;
+            if let (true, Some(name)) = (entry.file_type()?.is_file(), entry.file_name().to_str()) {
+                conf.insert(name.to_string(), std::fs::read(entry.path())?);
+            }
+        }
+    }
+    let bundle = api.backup(conf)?;
+    write_atomic(Path::new(path), &bundle)?;
+    let report = api.verify_backup(&bundle)?;
+    Ok(json!(format!(
+        "backed up {} keys and {} config files to {}",
+        report.keys.len(),
+        report.conf.len(),
+        path
+    )))
+}
+
+fn verify_backup(api: &HotApi, path: &str) -> Result<Value, CliErr> {
+    Ok(json!(api.verify_backup(&std::fs::read(path)?)?))
+}
+
+/// keys go into the configured store, which has to be empty, config files to `src/conf`
+fn restore(api: &HotApi, path: &str) -> Result<Value, CliErr> {
+    let (report, conf) = api.restore(&std::fs::read(path)?)?;
+    std::fs::create_dir_all(CONF_DIR)?;
+    for (name, mut contents) in conf {
+        // plain file names only, the bundle decides nothing about where files go
+        if Path::new(&name).file_name().and_then(|n| n.to_str()) == Some(name.as_str()) {
+            write_atomic(&Path::new(CONF_DIR).join(&name), &contents)?;
+        }
+        contents.zeroize();
+    }
+    Ok(json!({
+        "store": api.backend().store(),
+        "keys": report.keys,
+        "conf": report.conf,
+    }))
+}
+
+/// `fix` tightens store permissions, nothing else is changed
+fn doctor(api: &HotApi, fix: bool) -> Result<Value, CliErr> {
+    let backend = api.backend();
+    let mut checks = Vec::new();
+    let mut check = |name: &str, res: Result<String, String>| {
+        let (ok, detail) = match res {
+            Ok(d) => (true, d),
+            Err(d) => (false, d),
+        };
+        checks.push(json!({ "check": name, "ok": ok, "detail": detail }));
+    };
+
+    let store = resolve_path(backend.store());
+    check(
+        "store",
+        if Path::new(&store).is_dir() {
+            Ok(store.display().to_string())
+        } else {
+            Err(format!("{} is not a directory", store.display()))
+        },
+    );
+    check(
+        "permissions",
+        match api.check_permissions(fix) {
+            Ok(findings) if findings.is_empty() => Ok("owner only".into()),
+            Ok(findings) if fix => Ok(findings.join("; ")),
+            Ok(findings) => Err(format!("{}, run with --fix", findings.join("; "))),
+            Err(e) => Err(e.to_string()),
+        },
+    );
+    check(
+        "keys",
+        api.list()
+            .map(|names| format!("{} keys", names.len()))
+            .map_err(|e| e.to_string()),
+    );
+    // informational, they catch up on their next read
+    check(
+        "outdated keystores",
+        api.outdated()
+            .map(|names| match names.len() {
+                0 => "none".into(),
+                n => format!("{} ({}), run `hot_cheese upgrade`", n, names.join(", ")),
+            })
+            .map_err(|e| e.to_string()),
+    );
+    check(
+        "master key",
+        match backend.get_encryption_key() {
+            Some(mut key) => {
+                key.zeroize();
+                Ok("present in keychain".into())
+            }
+            None => Err("missing, run `hot_cheese init`".into()),
+        },
+    );
+    check(
+        "manifest",
+        api.verify_manifest()
+            .map(|_| "store matches".into())
+            .map_err(|e| format!("{}, check the store then run `hot_cheese manifest`", e)),
+    );
+    check(
+        "tls",
+        match (load_certs(), load_private_key()) {
+            (Ok(certs), Ok(_)) if !certs.is_empty() => Ok(format!("{} certs", certs.len())),
+            _ => Err("src/conf/ssl-cert.pem or ssl-key.pem unusable".into()),
+        },
+    );
+
+    let all_ok = checks.iter().all(|c| c["ok"] == json!(true));
+    let res = json!(checks);
+    if all_ok {
+        Ok(res)
+    } else {
+        Err(CliErr::Checks(res))
+    }
+}
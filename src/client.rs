@@ -0,0 +1,441 @@
+//! async counterpart of the blocking `HotCheeseAgent` from `examples/pin_cert.rs`
+//! for consumers that already run on tokio, with failover across several servers
+use crate::evm::{
+    EthSignature, SignedTransaction, Transaction, TypedData, Verification, VerifyRequest, B256,
+};
+use crate::solana::{SolanaPayload, SolanaSignature};
+use df_share::error::Unspecified;
+use df_share::{EphemeralClient, ServerEncryptedRes};
+use err_mac::create_err_with_impls;
+use http::{Method, Request, StatusCode, Uri};
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::client::conn::http2::{self, SendRequest};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use pki_types::ServerName;
+use rustls::{ClientConfig, RootCertStore};
+use std::io::{BufReader, Cursor};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_rustls::TlsConnector;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub ClientErr,
+    InvalidBase(String),
+    InvalidUri(http::uri::InvalidUri),
+    Http(http::Error),
+    Hyper(hyper::Error),
+    Tls(rustls::Error),
+    IO(std::io::Error),
+    Serde(serde_json::Error),
+    Unspecified(Unspecified),
+    Status(StatusCode),
+    Unreachable(Box<ClientErr>),
+    NoEndpoints
+    ;
+);
+
+impl ClientErr {
+    /// worth trying again (possibly on another server) for idempotent operations
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            ClientErr::Unreachable(_) | ClientErr::Hyper(_) | ClientErr::IO(_) => true,
+            // a refused handshake or a cert that doesn't match the pin won't fix itself
+            ClientErr::Tls(_) => false,
+            ClientErr::Status(s) => matches!(
+                *s,
+                StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// How often to go around the list of servers before giving up
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// rounds over all endpoints, 1 means every server is tried once
+    pub rounds: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// how long a server gets to accept the connection before the next one is tried
+    pub connect_timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            rounds: 5,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+/// Talks to an ordered list of hot_cheese servers, each with its own pinned cert.
+///
+/// Requests go to the first server that answers and are multiplexed over one
+/// HTTP/2 connection per server, reused until the server closes it.
+/// Idempotent calls are retried with backoff on connection errors and 502/503/504,
+/// `generate` and the signing calls only fail over when the request never reached a
+/// server, they may prompt for approval and signed transactions count against the
+/// daily limit.
+/// TLS failures are returned right away, a server that fails the pin is never
+/// silently skipped.
+/// All methods are cancellation safe: dropping a future only resets its own
+/// stream, the shared connections are driven by separate tasks.
+pub struct HotCheeseClient {
+    endpoints: Vec<Endpoint>,
+    retry: RetryPolicy,
+}
+
+/// one server and its (lazily opened) connection
+struct Endpoint {
+    host: String,
+    port: u16,
+    authority: String,
+    server_name: ServerName<'static>,
+    connector: TlsConnector,
+    conn: Mutex<Option<SendRequest<Full<Bytes>>>>,
+}
+
+impl HotCheeseClient {
+    /// `base` like `https://localhost:5555`, `cert_pem` is the server `ssl-cert.pem` to pin
+    pub fn new(base: &str, cert_pem: &[u8]) -> Result<Self, ClientErr> {
+        Self::with_endpoints(&[(base, cert_pem)])
+    }
+    /// servers in order of preference as `(base, cert_pem)`
+    pub fn with_endpoints(endpoints: &[(&str, &[u8])]) -> Result<Self, ClientErr> {
+        if endpoints.is_empty() {
+            return Err(ClientErr::NoEndpoints);
+        }
+        Ok(Self {
+            endpoints: endpoints
+                .iter()
+                .map(|(base, cert_pem)| Endpoint::new(base, cert_pem))
+                .collect::<Result<_, _>>()?,
+            retry: RetryPolicy::default(),
+        })
+    }
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub async fn health(&self) -> Result<String, ClientErr> {
+        self.get_string("/health", true).await
+    }
+    pub async fn generate(&self, name: &str) -> Result<String, ClientErr> {
+        self.get_string(&format!("/evm_generate/{}", name), false)
+            .await
+    }
+    pub async fn address(&self, name: &str) -> Result<String, ClientErr> {
+        self.get_string(&format!("/evm_address/{}", name), true)
+            .await
+    }
+    pub async fn generate_solana(&self, name: &str) -> Result<String, ClientErr> {
+        self.get_string(&format!("/solana_generate/{}", name), false)
+            .await
+    }
+    pub async fn solana_address(&self, name: &str) -> Result<String, ClientErr> {
+        self.get_string(&format!("/solana_address/{}", name), true)
+            .await
+    }
+    /// same DH exchange as `HotCheeseAgent::read`, the secret is only decryptable by this call
+    pub async fn read(&self, name: &str) -> Result<Vec<u8>, ClientErr> {
+        let client = EphemeralClient::new()?;
+        let (to_send, decryptor) = client.sendable();
+        let body = serde_json::to_vec(&to_send)?;
+        let res = self.send(&format!("/read/{}", name), body, true).await?;
+        let enc_res: ServerEncryptedRes = serde_json::from_slice(&res)?;
+        Ok(decryptor.decrypt(&enc_res)?)
+    }
+    /// counted against the key's daily limit, so never sent twice
+    pub async fn sign_transaction(
+        &self,
+        name: &str,
+        tx: &Transaction,
+    ) -> Result<SignedTransaction, ClientErr> {
+        let body = serde_json::to_vec(tx)?;
+        let res = self
+            .send(&format!("/evm_sign_tx/{}", name), body, false)
+            .await?;
+        Ok(serde_json::from_slice(&res)?)
+    }
+    /// EIP-191 `personal_sign` of `message`
+    pub async fn personal_sign(
+        &self,
+        name: &str,
+        message: &[u8],
+    ) -> Result<EthSignature, ClientErr> {
+        let res = self
+            .send(
+                &format!("/evm_personal_sign/{}", name),
+                message.to_vec(),
+                false,
+            )
+            .await?;
+        Ok(serde_json::from_slice(&res)?)
+    }
+    /// EIP-712, `typed_data` as `eth_signTypedData_v4` takes it
+    pub async fn sign_typed_data(
+        &self,
+        name: &str,
+        typed_data: &TypedData,
+    ) -> Result<EthSignature, ClientErr> {
+        let body = serde_json::to_vec(typed_data)?;
+        let res = self
+            .send(&format!("/evm_sign_typed_data/{}", name), body, false)
+            .await?;
+        Ok(serde_json::from_slice(&res)?)
+    }
+    /// only for keys whose policy sets `allow_raw_hash`
+    pub async fn sign_hash(&self, name: &str, hash: &B256) -> Result<EthSignature, ClientErr> {
+        let res = self
+            .send(
+                &format!("/evm_sign_hash/{}", name),
+                hash.to_string().into_bytes(),
+                false,
+            )
+            .await?;
+        Ok(serde_json::from_slice(&res)?)
+    }
+    /// the `X-Flashbots-Signature` header value for a relay request with `body`
+    pub async fn flashbots_signature(&self, name: &str, body: &[u8]) -> Result<String, ClientErr> {
+        let res = self
+            .send(&format!("/flashbots_sign/{}", name), body.to_vec(), false)
+            .await?;
+        Ok(String::from_utf8_lossy(&res).to_string())
+    }
+    pub async fn verify(&self, name: &str, req: &VerifyRequest) -> Result<Verification, ClientErr> {
+        let body = serde_json::to_vec(req)?;
+        let res = self
+            .send(&format!("/evm_verify/{}", name), body, true)
+            .await?;
+        Ok(serde_json::from_slice(&res)?)
+    }
+    /// signs a Solana transaction or off-chain message
+    pub async fn sign_solana(
+        &self,
+        name: &str,
+        payload: &SolanaPayload,
+    ) -> Result<SolanaSignature, ClientErr> {
+        let body = serde_json::to_vec(payload)?;
+        let res = self
+            .send(&format!("/solana_sign/{}", name), body, false)
+            .await?;
+        Ok(serde_json::from_slice(&res)?)
+    }
+
+    async fn get_string(&self, path: &str, idempotent: bool) -> Result<String, ClientErr> {
+        let res = self.send(path, Vec::new(), idempotent).await?;
+        Ok(String::from_utf8_lossy(&res).to_string())
+    }
+
+    async fn send(&self, path: &str, body: Vec<u8>, idempotent: bool) -> Result<Bytes, ClientErr> {
+        let body = Bytes::from(body);
+        let mut backoff = self.retry.initial_backoff;
+        let mut last_err = ClientErr::NoEndpoints;
+        for round in 0..self.retry.rounds.max(1) {
+            if round > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(self.retry.max_backoff);
+            }
+            for endpoint in &self.endpoints {
+                let timeout = self.retry.connect_timeout;
+                match endpoint.send(path, body.clone(), timeout).await {
+                    Ok(res) => return Ok(res),
+                    // never reached the server, always safe to go to the next one
+                    Err(e @ ClientErr::Unreachable(_)) => last_err = e,
+                    Err(e) if idempotent && e.is_retriable() => last_err = e,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl Endpoint {
+    fn new(base: &str, cert_pem: &[u8]) -> Result<Self, ClientErr> {
+        let uri: Uri = base.parse()?;
+        if uri.scheme_str() != Some("https") {
+            return Err(ClientErr::InvalidBase(format!(
+                "expected https base, got '{}'",
+                base
+            )));
+        }
+        let host = uri
+            .host()
+            .ok_or(ClientErr::InvalidBase(format!("no host in '{}'", base)))?
+            .to_string();
+        let port = uri.port_u16().unwrap_or(443);
+        let server_name = ServerName::try_from(host.clone())
+            .map_err(|e| ClientErr::InvalidBase(e.to_string()))?;
+
+        let mut root_store = RootCertStore::empty();
+        let mut reader = BufReader::new(Cursor::new(cert_pem));
+        for cert in rustls_pemfile::certs(&mut reader) {
+            root_store.add(cert?)?;
+        }
+        let mut tls_config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(root_store)
+                .with_no_client_auth();
+        tls_config.alpn_protocols = vec![b"h2".to_vec()];
+
+        Ok(Self {
+            authority: format!("{}:{}", host, port),
+            host,
+            port,
+            server_name,
+            connector: TlsConnector::from(Arc::new(tls_config)),
+            conn: Mutex::new(None),
+        })
+    }
+
+    async fn send(
+        &self,
+        path: &str,
+        body: Bytes,
+        connect_timeout: Duration,
+    ) -> Result<Bytes, ClientErr> {
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(format!("https://{}{}", self.authority, path))
+            .body(Full::new(body))?;
+        let mut sender = self.sender(connect_timeout).await.map_err(|e| match e {
+            ClientErr::Tls(_) => e,
+            e => ClientErr::Unreachable(Box::new(e)),
+        })?;
+        // a dead connection here means nothing was sent yet
+        sender
+            .ready()
+            .await
+            .map_err(|e| ClientErr::Unreachable(Box::new(e.into())))?;
+        let res = sender.send_request(req).await?;
+        let status = res.status();
+        let body = res.into_body().collect().await?.to_bytes();
+        if status != StatusCode::OK {
+            return Err(ClientErr::Status(status));
+        }
+        Ok(body)
+    }
+
+    /// hands out a handle to the live connection, connecting first if there is none
+    async fn sender(&self, timeout: Duration) -> Result<SendRequest<Full<Bytes>>, ClientErr> {
+        let mut conn = self.conn.lock().await;
+        if let Some(sender) = conn.as_ref() {
+            if !sender.is_closed() {
+                return Ok(sender.clone());
+            }
+        }
+        // the lock is held meanwhile, a blackholed server mustn't stall everyone
+        let connect = TcpStream::connect((self.host.as_str(), self.port));
+        let tcp = tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        let tls = self
+            .connector
+            .connect(self.server_name.clone(), tcp)
+            .await
+            .map_err(handshake_err)?;
+        let (sender, connection) =
+            http2::handshake(TokioExecutor::new(), TokioIo::new(tls)).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                eprintln!("hot_cheese connection closed: {err:#}");
+            }
+        });
+        *conn = Some(sender.clone());
+        Ok(sender)
+    }
+}
+
+/// tokio-rustls hands back rustls failures wrapped in `io::Error`
+fn handshake_err(e: std::io::Error) -> ClientErr {
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    {
+        Some(tls) => ClientErr::Tls(tls.clone()),
+        None => ClientErr::IO(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn fails_over_unreachable_servers() {
+        let client = HotCheeseClient::with_endpoints(&[
+            ("https://127.0.0.1:1", b""),
+            ("https://127.0.0.1:2", b""),
+        ])
+        .unwrap()
+        .with_retry(RetryPolicy {
+            rounds: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            ..Default::default()
+        });
+        // connection refused everywhere, non idempotent calls also fail over
+        assert!(matches!(
+            client.health().await,
+            Err(ClientErr::Unreachable(_))
+        ));
+        assert!(matches!(
+            client.generate("x").await,
+            Err(ClientErr::Unreachable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn tls_failures_are_final() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::AsyncWriteExt;
+
+        // answers every handshake with plain http
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n").await;
+            }
+        });
+        let base = format!("https://127.0.0.1:{}", port);
+        let client = HotCheeseClient::with_endpoints(&[(&base, b""), ("https://127.0.0.1:1", b"")])
+            .unwrap()
+            .with_retry(RetryPolicy {
+                rounds: 3,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+                ..Default::default()
+            });
+        // neither retried nor passed on to the next server
+        let err = client.health().await.unwrap_err();
+        assert!(matches!(err, ClientErr::Tls(_)), "{:?}", err);
+        assert!(!err.is_retriable());
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn retriable() {
+        assert!(ClientErr::Status(StatusCode::SERVICE_UNAVAILABLE).is_retriable());
+        // touch id rejection and friends come back as 500, prompting again would be wrong
+        assert!(!ClientErr::Status(StatusCode::INTERNAL_SERVER_ERROR).is_retriable());
+        assert!(HotCheeseClient::with_endpoints(&[]).is_err());
+        assert!(HotCheeseClient::new("http://localhost:5555", b"").is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use crate::crypto::KdfConfig;
+use crate::policy::KeyPolicy;
+use crate::proxy::ProxyConfig;
+use crate::store::Engine;
+use err_mac::create_err_with_impls;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub ConfigErr,
+    IO(std::io::Error),
+    Serde(serde_json::Error)
+    ;
+);
+
+#[derive(Deserialize, Serialize)]
+pub struct Config {
+    pub service: String,
+    pub account: String,
+    pub store: String,
+    /// used for newly written keystores, Argon2id 64 MiB when left out
+    #[serde(default)]
+    pub kdf: KdfConfig,
+    /// how keys are laid out in `store`, a file per key when left out
+    #[serde(default)]
+    pub engine: Engine,
+    /// by key name, keys without one get the defaults
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub policies: BTreeMap<String, KeyPolicy>,
+    /// for `hot_cheese proxy`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+}
+
+impl Config {
+    /// so everybody can customize the storage and name of service and account
+    /// and embed it in the binary
+    pub fn embedded() -> Result<Self, ConfigErr> {
+        let bytes = include_bytes!("conf/cheese_config.json");
+        Ok(serde_json::from_slice(bytes.as_slice())?)
+    }
+    /// `path`, else `HOT_CHEESE_CONFIG` if set, else the embedded config
+    pub fn load(path: Option<&str>) -> Result<Self, ConfigErr> {
+        let path = path
+            .map(|p| p.to_string())
+            .or_else(|| std::env::var("HOT_CHEESE_CONFIG").ok());
+        match path {
+            Some(path) => {
+                let bytes = std::fs::read(crate::resolve_path(&path))?;
+                Ok(serde_json::from_slice(&bytes)?)
+            }
+            None => Self::embedded(),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Key derivation for v4 keystores, scrypt or Argon2id with configurable cost
+use super::{bytes_hex, CryptoErr, KdfparamsType};
+use argon2::{Algorithm, Argon2, Params as Argon2Params, Version};
+use rand::{CryptoRng, Rng};
+use scrypt::{scrypt, Params as ScryptParams};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 32;
+
+/// What new keystores are encrypted with, lives in `cheese_config.json` under `"kdf"`.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(tag = "algorithm", rename_all = "lowercase")]
+pub enum KdfConfig {
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+    },
+    /// `m_cost` in KiB
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+}
+
+impl Default for KdfConfig {
+    /// 64 MiB, 3 passes
+    fn default() -> Self {
+        Self::Argon2id {
+            m_cost: 64 * 1024,
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+/// Argon2id parameters as stored in a keystore, always derives 32 bytes
+pub struct Argon2idParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    #[serde(with = "bytes_hex")]
+    pub salt: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(untagged)]
+/// The `kdfparams` of a v4 keystore, told apart by their fields so scrypt ones read like v3
+pub enum KdfParams {
+    Scrypt(KdfparamsType),
+    Argon2id(Argon2idParams),
+}
+
+impl KdfParams {
+    pub fn new<R: Rng + CryptoRng>(rng: &mut R, conf: &KdfConfig) -> Self {
+        let mut salt = vec![0u8; SALT_SIZE];
+        rng.fill_bytes(salt.as_mut_slice());
+        Self::with_salt(conf, salt)
+    }
+
+    /// `conf` with a given salt, for formats that store a shorter one
+    pub fn with_salt(conf: &KdfConfig, salt: Vec<u8>) -> Self {
+        match *conf {
+            KdfConfig::Scrypt { log_n, r, p } => Self::Scrypt(KdfparamsType {
+                dklen: KEY_SIZE as u8,
+                n: 2u32.pow(log_n as u32),
+                p,
+                r,
+                salt,
+            }),
+            KdfConfig::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } => Self::Argon2id(Argon2idParams {
+                m_cost,
+                t_cost,
+                p_cost,
+                salt,
+            }),
+        }
+    }
+
+    /// at least as costly as `conf` in every parameter, a different algorithm never is
+    pub fn meets(&self, conf: &KdfConfig) -> bool {
+        match (self, conf) {
+            (Self::Scrypt(params), KdfConfig::Scrypt { log_n, r, p }) => {
+                params.n as u64 >= 1u64 << (*log_n).min(63) && params.r >= *r && params.p >= *p
+            }
+            (
+                Self::Argon2id(params),
+                KdfConfig::Argon2id {
+                    m_cost,
+                    t_cost,
+                    p_cost,
+                },
+            ) => params.m_cost >= *m_cost && params.t_cost >= *t_cost && params.p_cost >= *p_cost,
+            _ => false,
+        }
+    }
+
+    /// 32 byte key
+    pub fn derive<S: AsRef<[u8]>>(&self, password: S) -> Result<Vec<u8>, CryptoErr> {
+        match self {
+            Self::Scrypt(params) => {
+                if params.dklen as usize != KEY_SIZE {
+                    return Err(CryptoErr::UnsupportedKdfLength);
+                }
+                derive_scrypt(password, params)
+            }
+            Self::Argon2id(params) => {
+                let mut key = vec![0u8; KEY_SIZE];
+                let argon = Argon2::new(
+                    Algorithm::Argon2id,
+                    Version::V0x13,
+                    Argon2Params::new(params.m_cost, params.t_cost, params.p_cost, Some(KEY_SIZE))?,
+                );
+                argon.hash_password_into(password.as_ref(), &params.salt, &mut key)?;
+                Ok(key)
+            }
+        }
+    }
+}
+
+pub fn derive_scrypt<S: AsRef<[u8]>>(
+    password: S,
+    kdf: &KdfparamsType,
+) -> Result<Vec<u8>, CryptoErr> {
+    let mut key = vec![0u8; kdf.dklen as usize];
+    // TODO: use int_log https://github.com/rust-lang/rust/issues/70887
+    // TODO: when it is stable
+    let log_n = (kdf.n as f32).log2().ceil() as u8;
+    let scrypt_params = ScryptParams::new(log_n, kdf.r, kdf.p)?;
+    scrypt(
+        password.as_ref(),
+        &kdf.salt,
+        &scrypt_params,
+        key.as_mut_slice(),
+    )?;
+    Ok(key)
+}
+
+fn time_derive(conf: &KdfConfig) -> Result<Duration, CryptoErr> {
+    let mut rng = rand::rngs::OsRng;
+    let params = KdfParams::new(&mut rng, conf);
+    let start = Instant::now();
+    params.derive("calibration")?;
+    Ok(start.elapsed())
+}
+
+/// Finds the cheapest parameters of the same algorithm as `conf` that take at least `target`
+/// to derive a key on this machine. Argon2id grows memory first (up to 1 GiB) and then passes,
+/// scrypt grows `n` (up to 2^22).
+pub fn calibrate(conf: &KdfConfig, target: Duration) -> Result<(KdfConfig, Duration), CryptoErr> {
+    let mut conf = match conf {
+        KdfConfig::Scrypt { r, p, .. } => KdfConfig::Scrypt {
+            log_n: 14,
+            r: *r,
+            p: *p,
+        },
+        KdfConfig::Argon2id { p_cost, .. } => KdfConfig::Argon2id {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: *p_cost,
+        },
+    };
+    loop {
+        let took = time_derive(&conf)?;
+        if took >= target {
+            return Ok((conf, took));
+        }
+        conf = match conf {
+            KdfConfig::Scrypt { log_n, r, p } if log_n < 22 => KdfConfig::Scrypt {
+                log_n: log_n + 1,
+                r,
+                p,
+            },
+            KdfConfig::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } if m_cost < 1024 * 1024 => KdfConfig::Argon2id {
+                m_cost: m_cost * 2,
+                t_cost,
+                p_cost,
+            },
+            KdfConfig::Argon2id {
+                m_cost,
+                t_cost,
+                p_cost,
+            } if t_cost < 64 => KdfConfig::Argon2id {
+                m_cost,
+                t_cost: t_cost + 1,
+                p_cost,
+            },
+            // can't get slower than this
+            _ => return Ok((conf, took)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn argon2id_roundtrip() {
+        let conf = KdfConfig::Argon2id {
+            m_cost: 256,
+            t_cost: 1,
+            p_cost: 1,
+        };
+        let params = KdfParams::new(&mut rand::thread_rng(), &conf);
+        let json = serde_json::to_string(&params).unwrap();
+        let back: KdfParams = serde_json::from_str(&json).unwrap();
+        assert_eq!(params, back);
+        assert_eq!(back.derive("pw").unwrap(), params.derive("pw").unwrap());
+        assert_ne!(back.derive("pw").unwrap(), params.derive("pv").unwrap());
+    }
+
+    #[test]
+    fn meets_policy() {
+        let mut rng = rand::thread_rng();
+        let policy = KdfConfig::Argon2id {
+            m_cost: 512,
+            t_cost: 2,
+            p_cost: 1,
+        };
+        assert!(KdfParams::new(&mut rng, &policy).meets(&policy));
+        let cheaper = KdfConfig::Argon2id {
+            m_cost: 256,
+            t_cost: 3,
+            p_cost: 1,
+        };
+        assert!(!KdfParams::new(&mut rng, &cheaper).meets(&policy));
+        let scrypt = KdfConfig::Scrypt {
+            log_n: 20,
+            r: 8,
+            p: 1,
+        };
+        assert!(!KdfParams::new(&mut rng, &scrypt).meets(&policy));
+        assert!(KdfParams::new(&mut rng, &scrypt).meets(&KdfConfig::Scrypt {
+            log_n: 13,
+            r: 8,
+            p: 1
+        }));
+    }
+
+    #[test]
+    fn calibrate_reaches_target() {
+        let (conf, took) = calibrate(
+            &KdfConfig::Scrypt {
+                log_n: 0,
+                r: 1,
+                p: 1,
+            },
+            Duration::from_millis(1),
+        )
+        .unwrap();
+        assert!(took >= Duration::from_millis(1));
+        assert!(matches!(conf, KdfConfig::Scrypt { log_n, .. } if log_n >= 14));
+    }
+}
@@ -1,4 +1,4 @@
-use super::bytes_hex;
+use super::{bytes_hex, KdfParams};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -29,7 +29,7 @@ pub struct CipherparamsJson {
 }
 
 // ONLY SCRYPT
-#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 /// Defines the various parameters used in the supported KDFs.
 pub struct KdfparamsType {
     pub dklen: u8,
@@ -40,6 +40,71 @@ pub struct KdfparamsType {
     pub salt: Vec<u8>,
 }
 
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    Evm,
+    Solana,
+    Bytes,
+}
+
+impl std::str::FromStr for KeyType {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "evm" => Ok(Self::Evm),
+            "solana" => Ok(Self::Solana),
+            "bytes" => Ok(Self::Bytes),
+            other => Err(format!("unknown key type '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+/// What a v4 keystore says about the secret it holds, authenticated but not encrypted.
+pub struct KeyMeta {
+    pub name: String,
+    pub key_type: KeyType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// unix seconds
+    pub created_at: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+/// Native keystore format. The secret is sealed with XChaCha20-Poly1305 and everything
+/// else in the file (meta, kdf, cipher) is the associated data, see [`KeystoreV4::aad`].
+pub struct KeystoreV4 {
+    pub version: u8,
+    pub meta: KeyMeta,
+    pub cipher: String,
+    pub kdfparams: KdfParams,
+    #[serde(with = "bytes_hex")]
+    pub nonce: Vec<u8>,
+    /// includes the poly1305 tag
+    #[serde(with = "bytes_hex")]
+    pub ciphertext: Vec<u8>,
+}
+
+impl KeystoreV4 {
+    /// canonical serialization of the header, any edit to it breaks decryption
+    pub fn aad(&self) -> Result<Vec<u8>, serde_json::Error> {
+        #[derive(Serialize)]
+        struct Header<'a> {
+            version: u8,
+            meta: &'a KeyMeta,
+            cipher: &'a str,
+            kdfparams: &'a KdfParams,
+        }
+        serde_json::to_vec(&Header {
+            version: self.version,
+            meta: &self.meta,
+            cipher: &self.cipher,
+            kdfparams: &self.kdfparams,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
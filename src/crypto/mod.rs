@@ -2,44 +2,44 @@
 //! EVEN MORE MINIMALIST
 //! A minimalist library to interact with encrypted JSON keystores as per the
 //! [Web3 Secret Storage Definition](https://github.com/ethereum/wiki/wiki/Web3-Secret-Storage-Definition).
+use crate::secure_fs::write_atomic;
 use aes::{
     cipher::{self, InnerIvInit, KeyInit, StreamCipherCore},
     Aes128,
 };
+use chacha20poly1305::{aead::Aead, aead::Payload, Key, XChaCha20Poly1305, XNonce};
 use err_mac::create_err_with_impls;
 use k256::ecdsa::SigningKey;
 use rand::{CryptoRng, Rng};
-use scrypt::{scrypt, Params as ScryptParams};
+use serde::Deserialize;
 use std::{
     array::TryFromSliceError,
-    fs::File,
-    io::{Read, Write},
     path::Path,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use tiny_keccak::{Hasher, Keccak};
-mod bytes_hex;
+pub(crate) mod bytes_hex;
+mod kdf;
 mod keystore;
-pub use keystore::{CipherparamsJson, CryptoJson, EthKeystore, KdfparamsType};
+pub use kdf::{calibrate, KdfConfig, KdfParams};
+pub use keystore::{EthKeystore, KdfparamsType, KeyMeta, KeyType, KeystoreV4};
 
 pub fn random_pk<R: Rng + CryptoRng>(rng: &mut R) -> SigningKey {
     SigningKey::random(rng)
 }
 
-
-/// convert hex str to a vec of bytes
+/// convert hex str to a vec of bytes, `None` for anything that isn't hex
 pub fn to_vec(mut s: &str) -> Option<Vec<u8>> {
     if s.starts_with("0x") {
         s = &s[2..]
     }
-    if s.len() % 2 != 0 {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
         return None;
     }
-    Some(
-        (0..s.len())
-            .step_by(2)
-            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
-            .collect(),
-    )
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
 }
 
 pub fn keccak256(slice: Vec<u8>) -> [u8; 32] {
@@ -60,50 +60,116 @@ create_err_with_impls!(
     ScryptInvalidOuputLen(scrypt::errors::InvalidOutputLen),
     AesInvalidKeyNonceLength(aes::cipher::InvalidLength),
     Ecdsa(k256::ecdsa::Error),
-    InvalidSlice(TryFromSliceError)
+    InvalidSlice(TryFromSliceError),
+    Argon2(argon2::Error),
+    UnsupportedVersion(u8),
+    UnsupportedKdfLength,
+    InvalidNonce,
+    AeadFailure,
+    NameMismatch(String),
+    KeyTypeMismatch(KeyType)
     ;
 );
 
-const DEFAULT_CIPHER: &str = "aes-128-ctr";
-const DEFAULT_KEY_SIZE: usize = 32usize;
-const DEFAULT_IV_SIZE: usize = 16usize;
-const DEFAULT_KDF_PARAMS_DKLEN: u8 = 32u8;
-const DEFAULT_KDF_PARAMS_LOG_N: u8 = 13u8;
-const DEFAULT_KDF_PARAMS_R: u32 = 8u32;
-const DEFAULT_KDF_PARAMS_P: u32 = 1u32;
+const V4_CIPHER: &str = "xchacha20-poly1305";
+const V4_NONCE_SIZE: usize = 24usize;
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Any keystore version `decrypt_key` can open, v3 is the Ethereum format, v4 the native one.
+pub enum Keystore {
+    V3(EthKeystore),
+    V4(KeystoreV4),
+}
+
+impl Keystore {
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, CryptoErr> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u8,
+        }
+        match serde_json::from_slice::<Version>(bytes)?.version {
+            3 => Ok(Self::V3(serde_json::from_slice(bytes)?)),
+            4 => Ok(Self::V4(serde_json::from_slice(bytes)?)),
+            v => Err(CryptoErr::UnsupportedVersion(v)),
+        }
+    }
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, CryptoErr> {
+        Self::from_slice(&std::fs::read(path)?)
+    }
+    pub fn version(&self) -> u8 {
+        match self {
+            Self::V3(_) => 3,
+            Self::V4(_) => 4,
+        }
+    }
+    /// v3 files carry no metadata
+    pub fn meta(&self) -> Option<&KeyMeta> {
+        match self {
+            Self::V3(_) => None,
+            Self::V4(k) => Some(&k.meta),
+        }
+    }
+    /// a v4 keystore only opens under the name it was written for, the name in the
+    /// error is the one it is bound to. v3 files name nothing and always pass, so one
+    /// copied over another key's file is only caught by the manifest, which records each
+    /// file's hash under its name. they are bound when they are upgraded
+    pub fn check_name(&self, name: &str) -> Result<(), CryptoErr> {
+        match self.meta() {
+            Some(meta) if meta.name != name => Err(CryptoErr::NameMismatch(meta.name.clone())),
+            _ => Ok(()),
+        }
+    }
+    pub fn check_key_type(&self, key_type: KeyType) -> Result<(), CryptoErr> {
+        match self.meta() {
+            Some(meta) if meta.key_type != key_type => {
+                Err(CryptoErr::KeyTypeMismatch(meta.key_type))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// written by an older version or with a cheaper KDF than `kdf`
+    pub fn is_outdated(&self, kdf: &KdfConfig) -> bool {
+        match self {
+            Self::V3(_) => true,
+            Self::V4(k) => k.cipher != V4_CIPHER || !k.kdfparams.meets(kdf),
+        }
+    }
+    pub fn decrypt<S: AsRef<[u8]>>(self, password: S) -> Result<Vec<u8>, CryptoErr> {
+        match self {
+            Self::V3(k) => decrypt_v3(k, password),
+            Self::V4(k) => decrypt_v4(k, password),
+        }
+    }
+}
 
 /// Decrypts an encrypted JSON keystore at the provided `path` using the provided `password`.
-/// Decryption supports the [Scrypt](https://tools.ietf.org/html/rfc7914.html) and
-/// [PBKDF2](https://ietf.org/rfc/rfc2898.txt) key derivation functions.
+/// Decryption supports the v3 [Scrypt](https://tools.ietf.org/html/rfc7914.html) Ethereum
+/// keystores and the native v4 AEAD keystores, the latter only under the file name they
+/// were written for.
 pub fn decrypt_key<P, S>(path: P, password: S) -> Result<Vec<u8>, CryptoErr>
 where
     P: AsRef<Path>,
     S: AsRef<[u8]>,
 {
-    // Read the file contents as string and deserialize it.
-    let mut file = File::open(path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    let keystore: EthKeystore = serde_json::from_str(&contents)?;
+    let keystore = Keystore::read(&path)?;
+    let name = path
+        .as_ref()
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    keystore.check_name(name)?;
+    keystore.decrypt(password)
+}
 
+fn decrypt_v3<S: AsRef<[u8]>>(keystore: EthKeystore, password: S) -> Result<Vec<u8>, CryptoErr> {
     // Derive the key.
-    let key = match keystore.crypto.kdfparams {
-        KdfparamsType {
-            dklen,
-            n,
-            p,
-            r,
-            salt,
-        } => {
-            let mut key = vec![0u8; dklen as usize];
-            // TODO: use int_log https://github.com/rust-lang/rust/issues/70887
-            // TODO: when it is stable
-            let log_n = (n as f32).log2().ceil() as u8;
-            let scrypt_params = ScryptParams::new(log_n, r, p)?;
-            scrypt(password.as_ref(), &salt, &scrypt_params, key.as_mut_slice())?;
-            key
-        }
-    };
+    let key = kdf::derive_scrypt(password, &keystore.crypto.kdfparams)?;
 
     // Derive the MAC from the derived key and ciphertext.
     let mut pld = Vec::new();
@@ -125,15 +191,100 @@ where
     Ok(pk)
 }
 
-/// Encrypts the given private key using the [Scrypt](https://tools.ietf.org/html/rfc7914.html)
-/// password-based key derivation function, and stores it in the provided directory. On success, it
-/// returns the `id` (Uuid) generated for this keystore.
+fn decrypt_v4<S: AsRef<[u8]>>(keystore: KeystoreV4, password: S) -> Result<Vec<u8>, CryptoErr> {
+    if keystore.nonce.len() != V4_NONCE_SIZE {
+        return Err(CryptoErr::InvalidNonce);
+    }
+    let key = keystore.kdfparams.derive(password)?;
+    open_aead(
+        &key,
+        &keystore.nonce,
+        &keystore.ciphertext,
+        &keystore.aad()?,
+    )
+}
+
+/// XChaCha20-Poly1305 with a fresh random nonce, returns `(nonce, ciphertext)`
+pub(crate) fn seal_aead<R: Rng + CryptoRng>(
+    rng: &mut R,
+    key: &[u8],
+    msg: &[u8],
+    aad: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), CryptoErr> {
+    let mut nonce = vec![0u8; V4_NONCE_SIZE];
+    rng.fill_bytes(nonce.as_mut_slice());
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg, aad })
+        .map_err(|_| CryptoErr::AeadFailure)?;
+    Ok((nonce, ciphertext))
+}
+
+/// inverse of [`seal_aead`], a wrong key and tampered data look the same
+pub(crate) fn open_aead(
+    key: &[u8],
+    nonce: &[u8],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>, CryptoErr> {
+    if nonce.len() != V4_NONCE_SIZE {
+        return Err(CryptoErr::InvalidNonce);
+    }
+    XChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoErr::MacMismatch)
+}
+
+/// Encrypts the given private key into a v4 keystore, the key is derived from `password`
+/// with the KDF in `kdf` ([Scrypt](https://tools.ietf.org/html/rfc7914.html) or
+/// [Argon2id](https://datatracker.ietf.org/doc/html/rfc9106)) and `meta` is bound to the
+/// ciphertext as associated data.
+pub fn encrypt_keystore<R, B, S>(
+    rng: &mut R,
+    pk: B,
+    password: S,
+    meta: KeyMeta,
+    kdf: &KdfConfig,
+) -> Result<Vec<u8>, CryptoErr>
+where
+    R: Rng + CryptoRng,
+    B: AsRef<[u8]>,
+    S: AsRef<[u8]>,
+{
+    // Generate a random salt and derive the key.
+    let kdfparams = KdfParams::new(rng, kdf);
+    let key = kdfparams.derive(password)?;
+
+    let mut keystore = KeystoreV4 {
+        version: 4,
+        meta,
+        cipher: String::from(V4_CIPHER),
+        kdfparams,
+        nonce: Vec::new(),
+        ciphertext: Vec::new(),
+    };
+    // the nonce is not part of the header
+    let (nonce, ciphertext) = seal_aead(rng, &key, pk.as_ref(), &keystore.aad()?)?;
+    keystore.nonce = nonce;
+    keystore.ciphertext = ciphertext;
+
+    Ok(serde_json::to_vec(&keystore)?)
+}
+
+/// Encrypts the given private key with [`encrypt_keystore`] and atomically stores it in the
+/// provided directory under `meta.name`.
 pub fn encrypt_key<P, R, B, S>(
     dir: P,
     rng: &mut R,
     pk: B,
     password: S,
-    name: &str,
+    meta: KeyMeta,
+    kdf: &KdfConfig,
 ) -> Result<(), CryptoErr>
 where
     P: AsRef<Path>,
@@ -141,58 +292,11 @@ where
     B: AsRef<[u8]>,
     S: AsRef<[u8]>,
 {
-    // Generate a random salt.
-    let mut salt = vec![0u8; DEFAULT_KEY_SIZE];
-    rng.fill_bytes(salt.as_mut_slice());
-
-    // Derive the key.
-    let mut key = vec![0u8; DEFAULT_KDF_PARAMS_DKLEN as usize];
-    let scrypt_params = ScryptParams::new(
-        DEFAULT_KDF_PARAMS_LOG_N,
-        DEFAULT_KDF_PARAMS_R,
-        DEFAULT_KDF_PARAMS_P,
-    )?;
-    scrypt(password.as_ref(), &salt, &scrypt_params, key.as_mut_slice())?;
-
-    // Encrypt the private key using AES-128-CTR.
-    let mut iv = vec![0u8; DEFAULT_IV_SIZE];
-    rng.fill_bytes(iv.as_mut_slice());
-
-    let encryptor = Aes128Ctr::new(&key[..16], &iv[..16]).expect("invalid length");
-
-    let mut ciphertext = pk.as_ref().to_vec();
-    encryptor.apply_keystream(&mut ciphertext);
-
-    // Calculate the MAC.
-    let mut pld = Vec::new();
-    pld.extend(&key[16..32]);
-    pld.extend(&ciphertext);
-    let mac = keccak256(pld);
-
-    let name = name.to_string();
-
-    // Construct and serialize the encrypted JSON keystore.
-    let keystore = EthKeystore {
-        version: 3,
-        crypto: CryptoJson {
-            cipher: String::from(DEFAULT_CIPHER),
-            cipherparams: CipherparamsJson { iv },
-            ciphertext: ciphertext.to_vec(),
-            kdfparams: KdfparamsType {
-                dklen: DEFAULT_KDF_PARAMS_DKLEN,
-                n: 2u32.pow(DEFAULT_KDF_PARAMS_LOG_N as u32),
-                p: DEFAULT_KDF_PARAMS_P,
-                r: DEFAULT_KDF_PARAMS_R,
-                salt,
-            },
-            mac: mac.to_vec(),
-        },
-    };
-    let contents = serde_json::to_string(&keystore)?;
+    let path = dir.as_ref().join(&meta.name);
+    let contents = encrypt_keystore(rng, pk, password, meta, kdf)?;
 
-    // Create a file in write-only mode, to store the encrypted JSON keystore.
-    let mut file = File::create(dir.as_ref().join(name))?;
-    file.write_all(contents.as_bytes())?;
+    // owner only, a crash leaves the previous file or none
+    write_atomic(&path, &contents)?;
 
     Ok(())
 }
@@ -217,6 +321,22 @@ impl Aes128Ctr {
 mod tests {
     use super::*;
 
+    #[test]
+    fn hex() {
+        assert_eq!(to_vec("0x00ff").unwrap(), vec![0, 0xff]);
+        assert_eq!(to_vec("").unwrap(), Vec::<u8>::new());
+        for bad in [
+            "0xzz",
+            "0x0",
+            "+1",
+            "0x\u{e9}",
+            "\u{e9}\u{e9}",
+            "0x\u{1f600}",
+        ] {
+            assert_eq!(to_vec(bad), None, "{}", bad);
+        }
+    }
+
     #[test]
     fn test_decrypt_scrypt() {
         let secret =
@@ -226,6 +346,22 @@ mod tests {
         assert!(decrypt_key(keypath, "thisisnotrandom").is_err());
     }
 
+    /// cheap enough for debug builds
+    const TEST_KDF: KdfConfig = KdfConfig::Argon2id {
+        m_cost: 256,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    fn meta(name: &str) -> KeyMeta {
+        KeyMeta {
+            name: name.into(),
+            key_type: KeyType::Evm,
+            address: None,
+            created_at: now_secs(),
+        }
+    }
+
     #[test]
     fn test_encrypt_decrypt_key() {
         let secret =
@@ -233,11 +369,62 @@ mod tests {
         let dir = Path::new("./test-keys");
         let mut rng = rand::thread_rng();
         let name = "hehe";
-        encrypt_key(dir, &mut rng, &secret, "newpassword", name).unwrap();
+        encrypt_key(dir, &mut rng, &secret, "newpassword", meta(name), &TEST_KDF).unwrap();
 
         let keypath = dir.join(name);
+        let keystore = Keystore::read(&keypath).unwrap();
+        assert_eq!(keystore.version(), 4);
+        assert_eq!(keystore.meta().map(|m| m.name.as_str()), Some(name));
         assert_eq!(decrypt_key(&keypath, "newpassword").unwrap(), secret);
         assert!(decrypt_key(&keypath, "notanewpassword").is_err());
-        assert!(std::fs::remove_file(&keypath).is_ok());
+
+        // renamed files are refused even with the right password
+        let renamed = dir.join("hehe_renamed");
+        std::fs::rename(&keypath, &renamed).unwrap();
+        assert!(matches!(
+            decrypt_key(&renamed, "newpassword"),
+            Err(CryptoErr::NameMismatch(n)) if n == name
+        ));
+        assert!(std::fs::remove_file(&renamed).is_ok());
+    }
+
+    #[test]
+    fn test_v4_header_is_authenticated() {
+        let secret = vec![1, 2, 3];
+        let mut rng = rand::thread_rng();
+        let bytes = encrypt_keystore(&mut rng, &secret, "pw", meta("A"), &TEST_KDF).unwrap();
+        assert_eq!(
+            Keystore::from_slice(&bytes).unwrap().decrypt("pw").unwrap(),
+            secret
+        );
+
+        let mut keystore: KeystoreV4 = serde_json::from_slice(&bytes).unwrap();
+        keystore.meta.key_type = KeyType::Solana;
+        let tampered = serde_json::to_vec(&keystore).unwrap();
+        assert!(matches!(
+            Keystore::from_slice(&tampered).unwrap().decrypt("pw"),
+            Err(CryptoErr::MacMismatch)
+        ));
+        let mut keystore: KeystoreV4 = serde_json::from_slice(&bytes).unwrap();
+        keystore.nonce.truncate(12);
+        let truncated = serde_json::to_vec(&keystore).unwrap();
+        assert!(matches!(
+            Keystore::from_slice(&truncated).unwrap().decrypt("pw"),
+            Err(CryptoErr::InvalidNonce)
+        ));
+
+        // v4 written with scrypt, as before argon2id was available
+        let scrypt = KdfConfig::Scrypt {
+            log_n: 13,
+            r: 8,
+            p: 1,
+        };
+        let bytes = encrypt_keystore(&mut rng, &secret, "pw", meta("A"), &scrypt).unwrap();
+        let keystore: KeystoreV4 = serde_json::from_slice(&bytes).unwrap();
+        assert!(matches!(keystore.kdfparams, KdfParams::Scrypt(_)));
+        assert_eq!(
+            Keystore::from_slice(&bytes).unwrap().decrypt("pw").unwrap(),
+            secret
+        );
     }
 }
@@ -0,0 +1,185 @@
+//! what a call does in a few words, for approval prompts. only calls that are common and
+//! unambiguous are decoded, anything else is called out as unknown
+use super::typed_data::decimal;
+use super::{to_hex, Address};
+
+/// `transfer(address,uint256)`
+pub const TRANSFER: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+/// `approve(address,uint256)`
+pub const APPROVE: [u8; 4] = [0x09, 0x5e, 0xa7, 0xb3];
+/// `transferFrom(address,address,uint256)`
+pub const TRANSFER_FROM: [u8; 4] = [0x23, 0xb8, 0x72, 0xdd];
+
+pub fn selector(data: &[u8]) -> Option<[u8; 4]> {
+    data.get(..4).map(|s| s.try_into().expect("4 bytes"))
+}
+
+pub fn word_u128(v: u128) -> [u8; 32] {
+    let mut w = [0u8; 32];
+    w[16..].copy_from_slice(&v.to_be_bytes());
+    w
+}
+
+/// the `n` ABI words after the selector, `None` when the data is too short
+fn args(data: &[u8], n: usize) -> Option<Vec<[u8; 32]>> {
+    let words = data.get(4..4 + 32 * n)?;
+    Some(
+        words
+            .chunks(32)
+            .map(|w| w.try_into().expect("32 bytes"))
+            .collect(),
+    )
+}
+
+/// `None` when the upper bytes aren't zero, which no honest encoder produces
+fn address(word: &[u8; 32]) -> Option<Address> {
+    match word[..12].iter().all(|b| *b == 0) {
+        true => Some(Address::from(
+            <[u8; 20]>::try_from(&word[12..]).expect("20 bytes"),
+        )),
+        false => None,
+    }
+}
+
+fn token_call(token: Address, data: &[u8]) -> Option<String> {
+    let call = match selector(data)? {
+        TRANSFER => {
+            let a = args(data, 2)?;
+            format!(
+                "transfer {} of token {} to {}",
+                decimal(&a[1]),
+                token,
+                address(&a[0])?
+            )
+        }
+        TRANSFER_FROM => {
+            let a = args(data, 3)?;
+            format!(
+                "transfer {} of token {} from {} to {}",
+                decimal(&a[2]),
+                token,
+                address(&a[0])?,
+                address(&a[1])?
+            )
+        }
+        APPROVE => {
+            let a = args(data, 2)?;
+            let spender = address(&a[0])?;
+            match a[1] {
+                amount if amount == [0xff; 32] => format!(
+                    "WARNING: UNLIMITED approval of token {} to {}",
+                    token, spender
+                ),
+                amount if amount == [0; 32] => {
+                    format!("revoke the approval of token {} to {}", token, spender)
+                }
+                amount => format!(
+                    "approve {} to spend {} of token {}",
+                    spender,
+                    decimal(&amount),
+                    token
+                ),
+            }
+        }
+        _ => return None,
+    };
+    Some(call)
+}
+
+/// `value` in wei, `to` is `None` for contract creation
+pub fn describe(to: Option<Address>, value: &[u8; 32], data: &[u8]) -> String {
+    let has_value = value.iter().any(|b| *b != 0);
+    let Some(to) = to else {
+        let mut out = format!("deploy a contract with {} bytes of init code", data.len());
+        if has_value {
+            out.push_str(&format!(", sending {} wei", decimal(value)));
+        }
+        return out;
+    };
+    if data.is_empty() {
+        return format!("send {} wei to {}", decimal(value), to);
+    }
+    let mut out = match (selector(data), token_call(to, data)) {
+        (_, Some(call)) => call,
+        (Some(selector), None) => format!(
+            "WARNING: unknown call {} on {} with {} bytes of data",
+            to_hex(&selector),
+            to,
+            data.len()
+        ),
+        (None, None) => format!(
+            "WARNING: {} bytes of data to {} that are not a call",
+            data.len(),
+            to
+        ),
+    };
+    if has_value {
+        out.push_str(&format!(", also sending {} wei", decimal(value)));
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crypto::to_vec;
+
+    const TOKEN: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    const OTHER: &str = "0x3535353535353535353535353535353535353535";
+
+    fn call(selector: [u8; 4], words: &[&str]) -> Vec<u8> {
+        let mut data = selector.to_vec();
+        for w in words {
+            let bytes = to_vec(w).unwrap();
+            data.extend_from_slice(&[0u8; 32][..32 - bytes.len()]);
+            data.extend_from_slice(&bytes);
+        }
+        data
+    }
+
+    #[test]
+    fn describes() {
+        let token = Some(TOKEN.parse().unwrap());
+        let zero = [0u8; 32];
+        assert_eq!(
+            describe(token, &zero, &call(TRANSFER, &[OTHER, "0x0f4240"])),
+            format!("transfer 1000000 of token {} to {}", TOKEN, OTHER)
+        );
+        assert_eq!(
+            describe(token, &zero, &call(TRANSFER_FROM, &[OTHER, OTHER, "0x01"])),
+            format!("transfer 1 of token {} from {} to {}", TOKEN, OTHER, OTHER)
+        );
+        let max = format!("0x{}", "ff".repeat(32));
+        assert_eq!(
+            describe(token, &zero, &call(APPROVE, &[OTHER, &max])),
+            format!(
+                "WARNING: UNLIMITED approval of token {} to {}",
+                TOKEN, OTHER
+            )
+        );
+        assert_eq!(
+            describe(token, &zero, &call(APPROVE, &[OTHER, "0x00"])),
+            format!("revoke the approval of token {} to {}", TOKEN, OTHER)
+        );
+        assert_eq!(
+            describe(token, &word_u128(5), &[]),
+            format!("send 5 wei to {}", TOKEN)
+        );
+        assert_eq!(
+            describe(None, &zero, &[0x60, 0x80]),
+            "deploy a contract with 2 bytes of init code"
+        );
+        assert_eq!(
+            describe(token, &word_u128(1), &[0x12, 0x34, 0x56, 0x78, 0]),
+            format!(
+                "WARNING: unknown call 0x12345678 on {} with 5 bytes of data, also sending 1 wei",
+                TOKEN
+            )
+        );
+        // too short or a dirty address word is not decoded
+        let short = call(TRANSFER, &[OTHER]);
+        assert!(describe(token, &zero, &short).starts_with("WARNING: unknown call"));
+        let dirty = call(TRANSFER, &[&max, "0x01"]);
+        assert!(describe(token, &zero, &dirty).starts_with("WARNING: unknown call"));
+    }
+}
@@ -0,0 +1,347 @@
+//! typed data we can read back to the human approving it: CoW Protocol (GPv2) orders and
+//! Safe multisig transactions
+use super::typed_data::{decimal, word};
+use super::{calldata, Address, EvmErr, TypedData};
+use crate::crypto::to_vec;
+use serde_json::Value;
+
+/// `domain.name` of CoW Protocol orders
+pub const COW_DOMAIN: &str = "Gnosis Protocol";
+/// the GPv2Settlement contract, the same on every chain CoW runs on
+pub const COW_SETTLEMENT: &str = "0x9008D19f58AAbD9eD0D60971565AA8510560ab41";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CowOrder {
+    pub sell_token: Address,
+    pub buy_token: Address,
+    /// the zero address pays the owner
+    pub receiver: Address,
+    pub sell_amount: [u8; 32],
+    pub buy_amount: [u8; 32],
+    pub fee_amount: [u8; 32],
+    /// unix seconds
+    pub valid_to: u64,
+    /// `sell` or `buy`
+    pub kind: String,
+    pub partially_fillable: bool,
+    /// the settlement contract from the domain
+    pub settlement: Option<Address>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafeTx {
+    /// the Safe itself, the domain's verifying contract
+    pub safe: Option<Address>,
+    pub to: Address,
+    pub value: [u8; 32],
+    pub data: Vec<u8>,
+    /// 0 call, 1 delegatecall
+    pub operation: u8,
+    pub safe_tx_gas: [u8; 32],
+    pub base_gas: [u8; 32],
+    /// non-zero pays the executor back out of the Safe, per gas used
+    pub gas_price: [u8; 32],
+    /// the zero address refunds in ether
+    pub gas_token: Address,
+    /// the zero address refunds whoever executes the transaction
+    pub refund_receiver: Address,
+    pub nonce: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Known {
+    CowOrder(CowOrder),
+    SafeTx(SafeTx),
+}
+
+fn field<'a>(message: &'a Value, name: &'static str) -> Result<&'a Value, EvmErr> {
+    message.get(name).ok_or(EvmErr::MissingField(name))
+}
+
+fn address(message: &Value, name: &'static str) -> Result<Address, EvmErr> {
+    field(message, name)?
+        .as_str()
+        .ok_or(EvmErr::InvalidField(name.into()))?
+        .parse()
+        .map_err(EvmErr::InvalidField)
+}
+
+fn uint(message: &Value, name: &'static str) -> Result<[u8; 32], EvmErr> {
+    word(field(message, name)?)
+}
+
+fn small(message: &Value, name: &'static str) -> Result<u64, EvmErr> {
+    let w = uint(message, name)?;
+    match w[..24].iter().all(|b| *b == 0) {
+        true => Ok(u64::from_be_bytes(w[24..].try_into().expect("8 bytes"))),
+        false => Err(EvmErr::InvalidField(name.into())),
+    }
+}
+
+fn string(message: &Value, name: &'static str) -> Result<String, EvmErr> {
+    Ok(field(message, name)?
+        .as_str()
+        .ok_or(EvmErr::InvalidField(name.into()))?
+        .to_string())
+}
+
+impl Known {
+    /// `None` for typed data that is neither
+    pub fn recognise(typed_data: &TypedData) -> Result<Option<Self>, EvmErr> {
+        let message = &typed_data.message;
+        let domain_name = typed_data.domain.get("name").and_then(|n| n.as_str());
+        match typed_data.primary_type.as_str() {
+            "Order" if domain_name == Some(COW_DOMAIN) => Ok(Some(Known::CowOrder(CowOrder {
+                sell_token: address(message, "sellToken")?,
+                buy_token: address(message, "buyToken")?,
+                receiver: address(message, "receiver")?,
+                sell_amount: uint(message, "sellAmount")?,
+                buy_amount: uint(message, "buyAmount")?,
+                fee_amount: uint(message, "feeAmount")?,
+                valid_to: small(message, "validTo")?,
+                kind: string(message, "kind")?,
+                partially_fillable: field(message, "partiallyFillable")?
+                    .as_bool()
+                    .ok_or(EvmErr::InvalidField("partiallyFillable".into()))?,
+                settlement: typed_data.verifying_contract(),
+            }))),
+            "SafeTx" => Ok(Some(Known::SafeTx(SafeTx {
+                safe: typed_data.verifying_contract(),
+                to: address(message, "to")?,
+                value: uint(message, "value")?,
+                data: match field(message, "data")? {
+                    Value::Null => Vec::new(),
+                    data => data
+                        .as_str()
+                        .and_then(to_vec)
+                        .ok_or(EvmErr::InvalidField("data".into()))?,
+                },
+                operation: u8::try_from(small(message, "operation")?)
+                    .map_err(|_| EvmErr::InvalidField("operation".into()))?,
+                safe_tx_gas: uint(message, "safeTxGas")?,
+                base_gas: uint(message, "baseGas")?,
+                gas_price: uint(message, "gasPrice")?,
+                gas_token: address(message, "gasToken")?,
+                refund_receiver: address(message, "refundReceiver")?,
+                nonce: uint(message, "nonce")?,
+            }))),
+            _ => Ok(None),
+        }
+    }
+
+    /// one line for the approval prompt, amounts in the token's base units
+    pub fn describe(&self) -> String {
+        match self {
+            Known::CowOrder(order) => {
+                let sell = format!("{} of {}", decimal(&order.sell_amount), order.sell_token);
+                let buy = format!("{} of {}", decimal(&order.buy_amount), order.buy_token);
+                let mut out = match order.kind.as_str() {
+                    "buy" => format!("CoW order: buy {} for at most {}", buy, sell),
+                    _ => format!("CoW order: sell {} for at least {}", sell, buy),
+                };
+                if order.fee_amount.iter().any(|b| *b != 0) {
+                    out.push_str(&format!(", fee {}", decimal(&order.fee_amount)));
+                }
+                match order.receiver.is_zero() {
+                    true => out.push_str(", paid to the signer"),
+                    false => out.push_str(&format!(", paid to {}", order.receiver)),
+                }
+                out.push_str(&format!(", valid until {} (unix)", order.valid_to));
+                if order.partially_fillable {
+                    out.push_str(", partially fillable");
+                }
+                let settlement = COW_SETTLEMENT.parse().ok();
+                if order.settlement.is_some() && order.settlement != settlement {
+                    out.push_str(", NOT the CoW settlement contract");
+                }
+                out
+            }
+            Known::SafeTx(tx) => {
+                let safe = tx.safe.map(|s| s.to_string()).unwrap_or("?".into());
+                let call = match tx.operation {
+                    0 => calldata::describe(Some(tx.to), &tx.value, &tx.data),
+                    // the target's code runs as the Safe, decoding it as a call would mislead
+                    1 => format!(
+                        "WARNING: DELEGATECALL to {} with {} bytes of data",
+                        tx.to,
+                        tx.data.len()
+                    ),
+                    other => format!("WARNING: unknown operation {} to {}", other, tx.to),
+                };
+                let mut out = format!("Safe {} tx #{}: {}", safe, decimal(&tx.nonce), call);
+                if tx.safe_tx_gas.iter().any(|b| *b != 0) {
+                    out.push_str(&format!(", safeTxGas {}", decimal(&tx.safe_tx_gas)));
+                }
+                if tx.gas_price.iter().any(|b| *b != 0) {
+                    let token = match tx.gas_token.is_zero() {
+                        true => "ether".to_string(),
+                        false => tx.gas_token.to_string(),
+                    };
+                    let receiver = match tx.refund_receiver.is_zero() {
+                        true => "the executor".to_string(),
+                        false => tx.refund_receiver.to_string(),
+                    };
+                    out.push_str(&format!(
+                        ", refunds gas at {} of {} per gas plus {} base gas to {}",
+                        decimal(&tx.gas_price),
+                        token,
+                        decimal(&tx.base_gas),
+                        receiver
+                    ));
+                }
+                out
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub fn cow_order(receiver: &str) -> TypedData {
+        serde_json::from_value(serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    {"name": "name", "type": "string"},
+                    {"name": "version", "type": "string"},
+                    {"name": "chainId", "type": "uint256"},
+                    {"name": "verifyingContract", "type": "address"}
+                ],
+                "Order": [
+                    {"name": "sellToken", "type": "address"},
+                    {"name": "buyToken", "type": "address"},
+                    {"name": "receiver", "type": "address"},
+                    {"name": "sellAmount", "type": "uint256"},
+                    {"name": "buyAmount", "type": "uint256"},
+                    {"name": "validTo", "type": "uint32"},
+                    {"name": "appData", "type": "bytes32"},
+                    {"name": "feeAmount", "type": "uint256"},
+                    {"name": "kind", "type": "string"},
+                    {"name": "partiallyFillable", "type": "bool"},
+                    {"name": "sellTokenBalance", "type": "string"},
+                    {"name": "buyTokenBalance", "type": "string"}
+                ]
+            },
+            "primaryType": "Order",
+            "domain": {
+                "name": "Gnosis Protocol",
+                "version": "v2",
+                "chainId": 100,
+                "verifyingContract": COW_SETTLEMENT
+            },
+            "message": {
+                "sellToken": "0xe91d153e0b41518a2ce8dd3d7944fa863463a97d",
+                "buyToken": "0x6a023ccd1ff6f2045c3309768ead9e68f978f6e1",
+                "receiver": receiver,
+                "sellAmount": "1000000000000000000",
+                "buyAmount": "300000000000000",
+                "validTo": 1760000000,
+                "appData": format!("0x{}", "0".repeat(64)),
+                "feeAmount": "0",
+                "kind": "sell",
+                "partiallyFillable": false,
+                "sellTokenBalance": "erc20",
+                "buyTokenBalance": "erc20"
+            }
+        }))
+        .unwrap()
+    }
+
+    pub fn safe_tx(operation: u8) -> TypedData {
+        let zero = "0x0000000000000000000000000000000000000000";
+        safe_tx_refund(operation, 0, zero, zero)
+    }
+
+    /// a Safe transaction that refunds `gas_price` per gas in `gas_token` to `receiver`
+    pub fn safe_tx_refund(
+        operation: u8,
+        gas_price: u64,
+        gas_token: &str,
+        receiver: &str,
+    ) -> TypedData {
+        serde_json::from_value(serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    {"name": "chainId", "type": "uint256"},
+                    {"name": "verifyingContract", "type": "address"}
+                ],
+                "SafeTx": [
+                    {"name": "to", "type": "address"},
+                    {"name": "value", "type": "uint256"},
+                    {"name": "data", "type": "bytes"},
+                    {"name": "operation", "type": "uint8"},
+                    {"name": "safeTxGas", "type": "uint256"},
+                    {"name": "baseGas", "type": "uint256"},
+                    {"name": "gasPrice", "type": "uint256"},
+                    {"name": "gasToken", "type": "address"},
+                    {"name": "refundReceiver", "type": "address"},
+                    {"name": "nonce", "type": "uint256"}
+                ]
+            },
+            "primaryType": "SafeTx",
+            "domain": {
+                "chainId": 1,
+                "verifyingContract": "0x1111111111111111111111111111111111111111"
+            },
+            "message": {
+                "to": "0x2222222222222222222222222222222222222222",
+                "value": "0",
+                "data": "0xa9059cbb0000",
+                "operation": operation,
+                "safeTxGas": 0,
+                "baseGas": 0,
+                "gasPrice": gas_price,
+                "gasToken": gas_token,
+                "refundReceiver": receiver,
+                "nonce": 7
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn recognises() {
+        let zero = "0x0000000000000000000000000000000000000000";
+        let order = cow_order(zero);
+        order.signing_hash().unwrap();
+        let Some(Known::CowOrder(decoded)) = Known::recognise(&order).unwrap() else {
+            panic!("not an order")
+        };
+        assert_eq!(decoded.valid_to, 1760000000);
+        assert!(decoded.receiver.is_zero());
+        assert_eq!(
+            Known::CowOrder(decoded).describe(),
+            "CoW order: sell 1000000000000000000 of 0xe91d153e0b41518a2ce8dd3d7944fa863463a97d \
+             for at least 300000000000000 of 0x6a023ccd1ff6f2045c3309768ead9e68f978f6e1, \
+             paid to the signer, valid until 1760000000 (unix)"
+        );
+
+        let tx = safe_tx(1);
+        tx.signing_hash().unwrap();
+        let known = Known::recognise(&tx).unwrap().unwrap();
+        assert_eq!(
+            known.describe(),
+            "Safe 0x1111111111111111111111111111111111111111 tx #7: WARNING: DELEGATECALL \
+             to 0x2222222222222222222222222222222222222222 with 6 bytes of data"
+        );
+        let call = Known::recognise(&safe_tx(0)).unwrap().unwrap();
+        assert_eq!(
+            call.describe(),
+            "Safe 0x1111111111111111111111111111111111111111 tx #7: WARNING: unknown call \
+             0xa9059cbb on 0x2222222222222222222222222222222222222222 with 6 bytes of data"
+        );
+        let refund = "0x3333333333333333333333333333333333333333";
+        let refunded = Known::recognise(&safe_tx_refund(0, 9, refund, zero))
+            .unwrap()
+            .unwrap();
+        assert!(refunded.describe().ends_with(&format!(
+            ", refunds gas at 9 of {} per gas plus 0 base gas to the executor",
+            refund
+        )));
+
+        let mut other = order;
+        other.domain["name"] = "Other".into();
+        assert_eq!(Known::recognise(&other).unwrap(), None);
+    }
+}
@@ -0,0 +1,158 @@
+//! EIP-191 `personal_sign` messages and bare 32 byte digests, and checking signatures over them
+use super::{printable, recover, to_hex, Address, EvmErr, RecoverableSignature, TypedData, B256};
+use crate::crypto::{bytes_hex, keccak256};
+use serde::{Deserialize, Serialize};
+
+/// the hash `personal_sign` signs, the message behind the EIP-191 `0x45` prefix
+pub fn eip191_hash(message: &[u8]) -> [u8; 32] {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    keccak256(prefixed)
+}
+
+/// what `X-Flashbots-Signature` signs: the `personal_sign` hash of the body's keccak,
+/// written out as `0x` hex text
+pub fn flashbots_hash(body: &[u8]) -> [u8; 32] {
+    eip191_hash(to_hex(&keccak256(body.to_vec())).as_bytes())
+}
+
+/// the message as text when it is text, SIWE and most logins are, hex otherwise. line
+/// breaks and invisible characters are escaped, see [`printable`]
+pub fn describe(message: &[u8]) -> String {
+    let text_only = |c: char| !c.is_control() || matches!(c, '\n' | '\r' | '\t');
+    match std::str::from_utf8(message) {
+        Ok(text) if text.chars().all(text_only) => format!("\"{}\"", printable(text)),
+        _ => to_hex(message),
+    }
+}
+
+/// a signature over `hash` in the shapes callers want it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EthSignature {
+    pub hash: B256,
+    pub r: B256,
+    pub s: B256,
+    /// 27 or 28
+    pub v: u8,
+    /// `r || s || v`
+    #[serde(with = "bytes_hex")]
+    pub signature: Vec<u8>,
+    pub from: Address,
+}
+
+impl EthSignature {
+    pub fn new(hash: [u8; 32], sig: &RecoverableSignature, from: Address) -> Self {
+        Self {
+            hash: B256::from(hash),
+            r: B256::from(sig.r),
+            s: B256::from(sig.s),
+            v: 27 + sig.y_parity,
+            signature: sig.to_rsv(),
+            from,
+        }
+    }
+}
+
+/// what was signed, `{"message": "text"}`, `{"data": "0x.."}` for binary messages,
+/// `{"typedData": {..}}` for EIP-712 or `{"hash": "0x.."}` for a bare digest
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Payload {
+    Message(String),
+    Data(#[serde(with = "bytes_hex")] Vec<u8>),
+    TypedData(TypedData),
+    Hash(B256),
+}
+
+impl Payload {
+    /// the digest the signature is over
+    pub fn hash(&self) -> Result<[u8; 32], EvmErr> {
+        match self {
+            Payload::Message(text) => Ok(eip191_hash(text.as_bytes())),
+            Payload::Data(data) => Ok(eip191_hash(data)),
+            Payload::TypedData(typed_data) => typed_data.signing_hash(),
+            Payload::Hash(hash) => Ok(hash.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VerifyRequest {
+    #[serde(flatten)]
+    pub payload: Payload,
+    /// 65 bytes `r || s || v`
+    #[serde(with = "bytes_hex")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Verification {
+    /// `signer` is `address`
+    pub valid: bool,
+    pub signer: Address,
+    /// of the key checked against
+    pub address: Address,
+}
+
+impl VerifyRequest {
+    pub fn verify(&self, address: Address) -> Result<Verification, EvmErr> {
+        let signer = recover(&self.payload.hash()?, &self.signature)?;
+        Ok(Verification {
+            valid: signer == address,
+            signer,
+            address,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::sign_hash;
+    use super::*;
+
+    #[test]
+    fn personal_sign() {
+        assert_eq!(
+            to_hex(&eip191_hash(b"Hello World")),
+            "0xa1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+        );
+        let key = [0x46; 32];
+        let hash = eip191_hash(b"Hello World");
+        let (sig, from) = sign_hash(&key, &hash).unwrap();
+        let signed = EthSignature::new(hash, &sig, from);
+        assert_eq!(signed.signature.len(), 65);
+
+        let req: VerifyRequest = serde_json::from_value(serde_json::json!({
+            "message": "Hello World",
+            "signature": to_hex(&signed.signature),
+        }))
+        .unwrap();
+        assert!(req.verify(from).unwrap().valid);
+        // the same bytes as data, and the bare digest
+        let as_data = VerifyRequest {
+            payload: Payload::Data(b"Hello World".to_vec()),
+            ..req.clone()
+        };
+        assert!(as_data.verify(from).unwrap().valid);
+        let as_hash = VerifyRequest {
+            payload: Payload::Hash(B256::from(hash)),
+            ..req.clone()
+        };
+        assert!(as_hash.verify(from).unwrap().valid);
+
+        let other = VerifyRequest {
+            payload: Payload::Message("Hello World!".into()),
+            ..req
+        };
+        let res = other.verify(from).unwrap();
+        assert!(!res.valid);
+        assert_ne!(res.signer, from);
+        assert_eq!(describe(b"Hello World"), "\"Hello World\"");
+        assert_eq!(describe(&[0, 1]), "0x0001");
+        // nothing a dapp sends can start a line of its own or flip the text around
+        assert_eq!(
+            describe("Sign in\n\u{202e}to pay".as_bytes()),
+            "\"Sign in\\n\\u{202e}to pay\""
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Signing with the stored secp256k1 keys so EVM consumers get signatures instead of the
+//! secret. Everything is JSON the way Ethereum JSON-RPC writes it: hex quantities and data.
+use crate::crypto::{keccak256, to_vec};
+use err_mac::create_err_with_impls;
+use k256::ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+pub mod calldata;
+pub mod known;
+pub mod message;
+pub mod quantity;
+mod rlp;
+mod tx;
+pub mod typed_data;
+pub use known::Known;
+pub use message::{
+    eip191_hash, flashbots_hash, EthSignature, Payload, Verification, VerifyRequest,
+};
+pub use tx::{AccessListItem, SignedTransaction, Transaction};
+pub use typed_data::TypedData;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub EvmErr,
+    Ecdsa(k256::ecdsa::Error),
+    MissingField(&'static str),
+    InvalidField(String),
+    FromMismatch
+    ;
+);
+
+/// `0x` prefixed lowercase hex
+pub fn to_hex(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("0x{}", hex)
+}
+
+/// caller supplied text for the approval prompt, on one line: control characters and the
+/// invisible format ones (bidi overrides, zero width, line separators) are escaped so a
+/// dapp can't add lines to the prompt or reorder what it shows
+pub fn printable(text: &str) -> String {
+    let invisible = |c: char| {
+        matches!(c,
+            '\u{ad}' | '\u{600}'..='\u{605}' | '\u{61c}' | '\u{6dd}' | '\u{70f}' | '\u{180e}'
+            | '\u{200b}'..='\u{200f}' | '\u{2028}'..='\u{202e}' | '\u{2060}'..='\u{206f}'
+            | '\u{feff}' | '\u{fff9}'..='\u{fffb}' | '\u{e0000}'..='\u{e007f}')
+    };
+    text.chars()
+        .map(|c| match c.is_control() || invisible(c) {
+            true => c.escape_default().to_string(),
+            false => c.to_string(),
+        })
+        .collect()
+}
+
+/// `N` bytes written as `0x` prefixed hex, addresses and hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FixedBytes<const N: usize>(pub [u8; N]);
+
+pub type Address = FixedBytes<20>;
+pub type B256 = FixedBytes<32>;
+
+impl<const N: usize> std::fmt::Display for FixedBytes<N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&to_hex(&self.0))
+    }
+}
+
+impl<const N: usize> FixedBytes<N> {
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|b| *b == 0)
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for FixedBytes<N> {
+    fn from(bytes: [u8; N]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl<const N: usize> std::str::FromStr for FixedBytes<N> {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        to_vec(s)
+            .and_then(|v| v.try_into().ok())
+            .map(Self)
+            .ok_or(format!("expected {} hex bytes, got '{}'", N, s))
+    }
+}
+
+impl<const N: usize> Serialize for FixedBytes<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for FixedBytes<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = std::borrow::Cow::<str>::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+/// the address of a public key, last 20 bytes of the keccak of its uncompressed point
+pub fn address(key: &VerifyingKey) -> Address {
+    let point = key.to_encoded_point(false);
+    let hash = keccak256(point.as_bytes()[1..].to_vec());
+    let mut out = [0u8; 20];
+    out.copy_from_slice(&hash[12..]);
+    FixedBytes(out)
+}
+
+/// a low-s signature over a 32 byte hash with the y parity needed to recover the signer
+pub struct RecoverableSignature {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    pub y_parity: u8,
+}
+
+impl RecoverableSignature {
+    /// `r || s || v` with `v` 27 or 28, what `eth_sign` style APIs return
+    pub fn to_rsv(&self) -> Vec<u8> {
+        [&self.r[..], &self.s[..], &[27 + self.y_parity]].concat()
+    }
+}
+
+/// signs `hash` with the secp256k1 secret `key`, returns the signature and the signer
+pub fn sign_hash(key: &[u8], hash: &[u8; 32]) -> Result<(RecoverableSignature, Address), EvmErr> {
+    let sk = SigningKey::from_slice(key)?;
+    let (sig, recid) = sk.sign_prehash_recoverable(hash)?;
+    let (r, s) = sig.split_bytes();
+    Ok((
+        RecoverableSignature {
+            r: r.into(),
+            s: s.into(),
+            y_parity: recid.is_y_odd() as u8,
+        },
+        address(sk.verifying_key()),
+    ))
+}
+
+/// the signer of `hash` from a 65 byte `r || s || v`, `v` may be 0, 1, 27 or 28
+pub fn recover(hash: &[u8; 32], signature: &[u8]) -> Result<Address, EvmErr> {
+    let [rs @ .., v]: [u8; 65] = signature
+        .try_into()
+        .map_err(|_| EvmErr::InvalidField("signature is not 65 bytes".into()))?;
+    let recid = match v {
+        0 | 1 => v,
+        27 | 28 => v - 27,
+        other => return Err(EvmErr::InvalidField(format!("v {}", other))),
+    };
+    let mut sig = Signature::from_slice(&rs)?;
+    let mut recid = RecoveryId::from_byte(recid).expect("0 or 1");
+    // ecrecover takes high-s signatures, k256 only verifies low-s ones
+    if let Some(low) = sig.normalize_s() {
+        sig = low;
+        recid = RecoveryId::new(!recid.is_y_odd(), recid.is_x_reduced());
+    }
+    Ok(address(&VerifyingKey::recover_from_prehash(
+        hash, &sig, recid,
+    )?))
+}
@@ -0,0 +1,59 @@
+//! serde for JSON-RPC quantities. written as `0x` hex, read from hex, decimal strings or
+//! JSON numbers since wallets and scripts send all three
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Raw {
+    Num(u64),
+    Str(String),
+}
+
+impl Raw {
+    fn value<T: TryFrom<u128>>(self) -> Result<T, String> {
+        let v = match self {
+            Raw::Num(n) => n as u128,
+            Raw::Str(s) => parse(&s).ok_or(format!("invalid quantity '{}'", s))?,
+        };
+        T::try_from(v).map_err(|_| format!("quantity {} out of range", v))
+    }
+}
+
+/// `0x` hex or decimal
+pub fn parse(s: &str) -> Option<u128> {
+    match s.strip_prefix("0x") {
+        Some("") => Some(0),
+        Some(hex) => u128::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+pub fn serialize<S: Serializer, T: Into<u128> + Copy>(v: &T, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("{:#x}", (*v).into()))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<u128>>(d: D) -> Result<T, D::Error> {
+    Raw::deserialize(d)?.value().map_err(D::Error::custom)
+}
+
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer, T: Into<u128> + Copy>(
+        v: &Option<T>,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        match v {
+            Some(v) => super::serialize(v, s),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: TryFrom<u128>>(
+        d: D,
+    ) -> Result<Option<T>, D::Error> {
+        Option::<Raw>::deserialize(d)?
+            .map(|raw| raw.value().map_err(D::Error::custom))
+            .transpose()
+    }
+}
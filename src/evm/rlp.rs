@@ -0,0 +1,70 @@
+//! the RLP encoding, only what transactions need. lists take already encoded items
+
+fn header(len: usize, short: u8, long: u8) -> Vec<u8> {
+    if len <= 55 {
+        return vec![short + len as u8];
+    }
+    let len_bytes = trim(&(len as u64).to_be_bytes()).to_vec();
+    let mut out = vec![long + len_bytes.len() as u8];
+    out.extend_from_slice(&len_bytes);
+    out
+}
+
+/// big endian without leading zeros
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+pub fn bytes(b: &[u8]) -> Vec<u8> {
+    if b.len() == 1 && b[0] < 0x80 {
+        return b.to_vec();
+    }
+    let mut out = header(b.len(), 0x80, 0xb7);
+    out.extend_from_slice(b);
+    out
+}
+
+/// integers are minimal big endian strings, zero is the empty string
+pub fn uint(v: u128) -> Vec<u8> {
+    bytes(trim(&v.to_be_bytes()))
+}
+
+/// a 256 bit integer given as 32 big endian bytes, like a signature's r and s
+pub fn uint_bytes(v: &[u8]) -> Vec<u8> {
+    bytes(trim(v))
+}
+
+pub fn list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload: Vec<u8> = items.concat();
+    let mut out = header(payload.len(), 0xc0, 0xf7);
+    out.extend_from_slice(&payload);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encodes() {
+        assert_eq!(bytes(b"dog"), b"\x83dog");
+        assert_eq!(list(&[bytes(b"cat"), bytes(b"dog")]), b"\xc8\x83cat\x83dog");
+        assert_eq!(bytes(b""), vec![0x80]);
+        assert_eq!(list(&[]), vec![0xc0]);
+        assert_eq!(uint(0), vec![0x80]);
+        assert_eq!(uint(15), vec![0x0f]);
+        assert_eq!(uint(1024), vec![0x82, 0x04, 0x00]);
+        let long = [b'a'; 56];
+        assert_eq!(bytes(&long)[..2], [0xb8, 56]);
+        // [ [], [[]], [ [], [[]] ] ]
+        assert_eq!(
+            list(&[
+                list(&[]),
+                list(&[list(&[])]),
+                list(&[list(&[]), list(&[list(&[])])])
+            ]),
+            vec![0xc7, 0xc0, 0xc1, 0xc0, 0xc3, 0xc0, 0xc1, 0xc0]
+        );
+    }
+}
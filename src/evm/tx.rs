@@ -0,0 +1,454 @@
+//! unsigned transactions as JSON-RPC writes them (`eth_signTransaction`), signed into the raw
+//! form `eth_sendRawTransaction` takes. legacy ones are always EIP-155 replay protected
+use super::{
+    calldata, quantity, rlp, sign_hash, to_hex, Address, EvmErr, FixedBytes, RecoverableSignature,
+    B256,
+};
+use crate::crypto::{bytes_hex, keccak256};
+use serde::{Deserialize, Serialize};
+
+pub const LEGACY: u8 = 0;
+pub const EIP2930: u8 = 1;
+pub const EIP1559: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListItem {
+    pub address: Address,
+    pub storage_keys: Vec<B256>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transaction {
+    /// 0 legacy, 1 EIP-2930, 2 EIP-1559. guessed from the fee fields when missing
+    #[serde(
+        rename = "type",
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tx_type: Option<u8>,
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub chain_id: Option<u64>,
+    /// when given it has to be the signing key's address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<Address>,
+    #[serde(with = "quantity")]
+    pub nonce: u64,
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub gas_price: Option<u128>,
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_priority_fee_per_gas: Option<u128>,
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_fee_per_gas: Option<u128>,
+    #[serde(alias = "gasLimit", with = "quantity")]
+    pub gas: u64,
+    /// `None` creates a contract
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<Address>,
+    #[serde(default, with = "quantity")]
+    pub value: u128,
+    #[serde(default, alias = "input", with = "bytes_hex")]
+    pub data: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_list: Option<Vec<AccessListItem>>,
+}
+
+/// what `eth_sendRawTransaction` takes and the hash it will be known by
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SignedTransaction {
+    #[serde(with = "bytes_hex")]
+    pub raw: Vec<u8>,
+    pub hash: B256,
+    pub from: Address,
+    /// the signature as the transaction carries it, `v` is EIP-155 for legacy transactions
+    /// and the y parity for typed ones
+    #[serde(with = "quantity")]
+    pub v: u128,
+    pub r: B256,
+    pub s: B256,
+}
+
+impl Transaction {
+    /// the type, once the fields it needs are all there
+    pub fn validate(&self) -> Result<u8, EvmErr> {
+        let tx_type = match self.tx_type {
+            Some(t) => t,
+            None if self.max_fee_per_gas.is_some() || self.max_priority_fee_per_gas.is_some() => {
+                EIP1559
+            }
+            None if self.access_list.is_some() => EIP2930,
+            None => LEGACY,
+        };
+        if self.chain_id.is_none() {
+            return Err(EvmErr::MissingField("chainId"));
+        }
+        match tx_type {
+            LEGACY | EIP2930 => {
+                if self.gas_price.is_none() {
+                    return Err(EvmErr::MissingField("gasPrice"));
+                }
+                if self.max_fee_per_gas.is_some() || self.max_priority_fee_per_gas.is_some() {
+                    return Err(EvmErr::InvalidField(format!(
+                        "type {} has no maxFeePerGas or maxPriorityFeePerGas",
+                        tx_type
+                    )));
+                }
+                if tx_type == LEGACY && self.access_list.is_some() {
+                    return Err(EvmErr::InvalidField("type 0 has no accessList".into()));
+                }
+            }
+            EIP1559 => {
+                if self.gas_price.is_some() {
+                    return Err(EvmErr::InvalidField("type 2 has no gasPrice".into()));
+                }
+                let max_fee = self
+                    .max_fee_per_gas
+                    .ok_or(EvmErr::MissingField("maxFeePerGas"))?;
+                let tip = self
+                    .max_priority_fee_per_gas
+                    .ok_or(EvmErr::MissingField("maxPriorityFeePerGas"))?;
+                if tip > max_fee {
+                    return Err(EvmErr::InvalidField(
+                        "maxPriorityFeePerGas above maxFeePerGas".into(),
+                    ));
+                }
+            }
+            other => return Err(EvmErr::InvalidField(format!("type {}", other))),
+        }
+        Ok(tx_type)
+    }
+
+    /// the most it can cost in fees, for policies and the approval prompt
+    pub fn max_fee_per_gas(&self) -> u128 {
+        self.gas_price.or(self.max_fee_per_gas).unwrap_or_default()
+    }
+
+    /// one line for the approval prompt, with what the call does
+    pub fn describe(&self) -> String {
+        let kind = match self.validate() {
+            Ok(EIP2930) => "EIP-2930",
+            Ok(EIP1559) => "EIP-1559",
+            _ => "legacy",
+        };
+        format!(
+            "{} transaction on chain {}: {}; nonce {}, gas {} at up to {} wei",
+            kind,
+            self.chain_id.unwrap_or_default(),
+            calldata::describe(self.to, &calldata::word_u128(self.value), &self.data),
+            self.nonce,
+            self.gas,
+            self.max_fee_per_gas(),
+        )
+    }
+
+    fn fields(&self, tx_type: u8) -> Vec<Vec<u8>> {
+        let chain_id = self.chain_id.unwrap_or_default() as u128;
+        let mut fields = Vec::new();
+        if tx_type != LEGACY {
+            fields.push(rlp::uint(chain_id));
+        }
+        fields.push(rlp::uint(self.nonce as u128));
+        if tx_type == EIP1559 {
+            fields.push(rlp::uint(self.max_priority_fee_per_gas.unwrap_or_default()));
+            fields.push(rlp::uint(self.max_fee_per_gas.unwrap_or_default()));
+        } else {
+            fields.push(rlp::uint(self.gas_price.unwrap_or_default()));
+        }
+        fields.push(rlp::uint(self.gas as u128));
+        fields.push(match self.to {
+            Some(to) => rlp::bytes(&to.0),
+            None => rlp::bytes(&[]),
+        });
+        fields.push(rlp::uint(self.value));
+        fields.push(rlp::bytes(&self.data));
+        if tx_type != LEGACY {
+            let items: Vec<Vec<u8>> = self
+                .access_list
+                .iter()
+                .flatten()
+                .map(|item| {
+                    let keys: Vec<Vec<u8>> =
+                        item.storage_keys.iter().map(|k| rlp::bytes(&k.0)).collect();
+                    rlp::list(&[rlp::bytes(&item.address.0), rlp::list(&keys)])
+                })
+                .collect();
+            fields.push(rlp::list(&items));
+        }
+        fields
+    }
+
+    /// typed transactions are their type byte followed by the RLP list
+    fn envelope(tx_type: u8, fields: &[Vec<u8>]) -> Vec<u8> {
+        let list = rlp::list(fields);
+        if tx_type == LEGACY {
+            return list;
+        }
+        [&[tx_type][..], &list].concat()
+    }
+
+    /// the hash the key signs
+    pub fn signing_hash(&self) -> Result<[u8; 32], EvmErr> {
+        let tx_type = self.validate()?;
+        let mut fields = self.fields(tx_type);
+        if tx_type == LEGACY {
+            // EIP-155
+            fields.push(rlp::uint(self.chain_id.unwrap_or_default() as u128));
+            fields.push(rlp::uint(0));
+            fields.push(rlp::uint(0));
+        }
+        Ok(keccak256(Self::envelope(tx_type, &fields)))
+    }
+
+    /// the raw transaction with `sig` attached
+    pub fn encode_signed(&self, sig: &RecoverableSignature) -> Result<Vec<u8>, EvmErr> {
+        let tx_type = self.validate()?;
+        let mut fields = self.fields(tx_type);
+        fields.push(rlp::uint(self.v(tx_type, sig)));
+        fields.push(rlp::uint_bytes(&sig.r));
+        fields.push(rlp::uint_bytes(&sig.s));
+        Ok(Self::envelope(tx_type, &fields))
+    }
+
+    fn v(&self, tx_type: u8, sig: &RecoverableSignature) -> u128 {
+        match tx_type {
+            LEGACY => self.chain_id.unwrap_or_default() as u128 * 2 + 35 + sig.y_parity as u128,
+            _ => sig.y_parity as u128,
+        }
+    }
+
+    /// signs with the secp256k1 secret `key`
+    pub fn sign(&self, key: &[u8]) -> Result<SignedTransaction, EvmErr> {
+        let (sig, from) = sign_hash(key, &self.signing_hash()?)?;
+        if self.from.is_some_and(|expected| expected != from) {
+            return Err(EvmErr::FromMismatch);
+        }
+        let raw = self.encode_signed(&sig)?;
+        Ok(SignedTransaction {
+            hash: FixedBytes(keccak256(raw.clone())),
+            raw,
+            from,
+            v: self.v(self.validate()?, &sig),
+            r: FixedBytes(sig.r),
+            s: FixedBytes(sig.s),
+        })
+    }
+}
+
+impl std::fmt::Display for SignedTransaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.hash, to_hex(&self.raw))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::FixedBytes;
+    use super::*;
+    use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+
+    const KEY: [u8; 32] = [0x46; 32];
+
+    #[test]
+    fn eip155_vector() {
+        let tx: Transaction = serde_json::from_str(
+            r#"{
+                "chainId": 1,
+                "nonce": "0x9",
+                "gasPrice": "20000000000",
+                "gas": 21000,
+                "to": "0x3535353535353535353535353535353535353535",
+                "value": "0xde0b6b3a7640000"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(tx.validate().unwrap(), LEGACY);
+        assert_eq!(
+            tx.describe(),
+            "legacy transaction on chain 1: send 1000000000000000000 wei to \
+             0x3535353535353535353535353535353535353535; nonce 9, gas 21000 at up to \
+             20000000000 wei"
+        );
+        assert_eq!(
+            to_hex(&tx.signing_hash().unwrap()),
+            "0xdaf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53"
+        );
+        let signed = tx.sign(&KEY).unwrap();
+        assert_eq!(
+            to_hex(&signed.raw),
+            "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+        assert_eq!(
+            signed.from.to_string(),
+            "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+        assert_eq!(signed.v, 37);
+        assert_eq!(
+            signed.r.to_string(),
+            "0x28ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276"
+        );
+
+        let wrong_from = Transaction {
+            from: Some(FixedBytes([1; 20])),
+            ..tx
+        };
+        assert!(matches!(wrong_from.sign(&KEY), Err(EvmErr::FromMismatch)));
+    }
+
+    fn b32(hex: &str) -> [u8; 32] {
+        crate::crypto::to_vec(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn published_vectors() {
+        // go-ethereum core/types emptyEip2718Tx, signed by its testAddr key
+        let key = b32("b71c71a67e1177ad4e901695e1b4b9ee17ae16c6668d313eac2f96dbcda3f291");
+        let access: Transaction = serde_json::from_value(serde_json::json!({
+            "type": "0x1",
+            "chainId": 1,
+            "nonce": 3,
+            "to": "0xb94f5374fce5edbc8e2a8697c15331677e6ebf0b",
+            "value": 10,
+            "gas": 25000,
+            "gasPrice": 1,
+            "data": "0x5544",
+            "accessList": []
+        }))
+        .unwrap();
+        assert_eq!(
+            to_hex(&access.signing_hash().unwrap()),
+            "0x49b486f0ec0a60dfbbca2d30cb07c9e8ffb2a2ff41f29a1ab6737475f6ff69f3"
+        );
+        let sig = RecoverableSignature {
+            r: b32("c9519f4f2b30335884581971573fadf60c6204f59a911df35ee8a540456b2660"),
+            s: b32("32f1e8e2c5dd761f9e4f88f41c8310aeaba26a8bfcdacfedfa12ec3862d37521"),
+            y_parity: 1,
+        };
+        assert_eq!(
+            to_hex(&access.encode_signed(&sig).unwrap()),
+            "0x01f8630103018261a894b94f5374fce5edbc8e2a8697c15331677e6ebf0b0a825544c001a0c9519f4f\
+             2b30335884581971573fadf60c6204f59a911df35ee8a540456b2660a032f1e8e2c5dd761f9e4f88f41c\
+             8310aeaba26a8bfcdacfedfa12ec3862d37521"
+        );
+        assert_eq!(
+            access.sign(&key).unwrap().from.to_string(),
+            "0x71562b71999873db5b286df957af199ec94617f7"
+        );
+
+        // a mainnet EIP-1559 transaction, reth's and alloy's recover_signer_eip1559
+        let dynamic: Transaction = serde_json::from_value(serde_json::json!({
+            "chainId": 1,
+            "nonce": "0x42",
+            "gas": 44386,
+            "to": "0x6069a6c32cf691f5982febae4faf8a6f3ab2f0f6",
+            "value": 0,
+            "data": "0xa22cb4650000000000000000000000005eee75727d804a2b13038928d36f8b188945a57a\
+                     0000000000000000000000000000000000000000000000000000000000000000",
+            "maxFeePerGas": "0x4a817c800",
+            "maxPriorityFeePerGas": "0x3b9aca00",
+            "accessList": []
+        }))
+        .unwrap();
+        let sig = RecoverableSignature {
+            r: b32("840cfc572845f5786e702984c2a582528cad4b49b2a10b9db1be7fca90058565"),
+            s: b32("25e7109ceb98168d95b09b18bbf6b685130e0562f233877d492b94eee0c5b6d1"),
+            y_parity: 0,
+        };
+        let raw = dynamic.encode_signed(&sig).unwrap();
+        assert_eq!(
+            to_hex(&keccak256(raw)),
+            "0x0ec0b6a2df4d87424e5f6ad2a654e27aaeb7dac20ae9e8385cc09087ad532ee0"
+        );
+        let hash = dynamic.signing_hash().unwrap();
+        assert_eq!(
+            super::super::recover(&hash, &sig.to_rsv())
+                .unwrap()
+                .to_string(),
+            "0xdd6b8b3dc6b7ad97db52f08a275ff4483e024cea"
+        );
+    }
+
+    #[test]
+    fn typed_transactions() {
+        let base = Transaction {
+            chain_id: Some(5),
+            nonce: 1,
+            gas: 50_000,
+            to: Some(FixedBytes([0x11; 20])),
+            value: 7,
+            data: vec![0xa9, 0x05, 0x9c, 0xbb],
+            access_list: Some(vec![AccessListItem {
+                address: FixedBytes([0x22; 20]),
+                storage_keys: vec![FixedBytes([0x33; 32])],
+            }]),
+            ..Default::default()
+        };
+        let access = Transaction {
+            gas_price: Some(1_000_000_000),
+            ..base.clone()
+        };
+        let dynamic = Transaction {
+            max_fee_per_gas: Some(30_000_000_000),
+            max_priority_fee_per_gas: Some(1_000_000_000),
+            ..base.clone()
+        };
+        for (tx, tx_type) in [(access, EIP2930), (dynamic, EIP1559)] {
+            assert_eq!(tx.validate().unwrap(), tx_type);
+            let signed = tx.sign(&KEY).unwrap();
+            assert_eq!(signed.raw[0], tx_type);
+            assert_eq!(signed.hash.0, keccak256(signed.raw.clone()));
+            // the signature is the last three items, parity and two 32 byte integers
+            let hash = tx.signing_hash().unwrap();
+            let (sig, from) = sign_hash(&KEY, &hash).unwrap();
+            assert_eq!(from, signed.from);
+            assert!(signed.raw.ends_with(&rlp::uint_bytes(&sig.s)));
+            assert_eq!((signed.v, signed.s.0), (sig.y_parity as u128, sig.s));
+            let recovered = VerifyingKey::recover_from_prehash(
+                &hash,
+                &Signature::from_scalars(sig.r, sig.s).unwrap(),
+                RecoveryId::from_byte(sig.y_parity).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(super::super::address(&recovered), from);
+            // and it is the same with the JSON the client sends
+            let json = serde_json::to_string(&tx).unwrap();
+            assert_eq!(serde_json::from_str::<Transaction>(&json).unwrap(), tx);
+        }
+
+        assert!(matches!(
+            Transaction {
+                chain_id: None,
+                ..base.clone()
+            }
+            .validate(),
+            Err(EvmErr::MissingField("chainId"))
+        ));
+        assert!(matches!(
+            Transaction {
+                tx_type: Some(2),
+                max_fee_per_gas: Some(1),
+                max_priority_fee_per_gas: Some(2),
+                ..base
+            }
+            .validate(),
+            Err(EvmErr::InvalidField(_))
+        ));
+    }
+}
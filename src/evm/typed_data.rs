@@ -0,0 +1,503 @@
+//! EIP-712 typed structured data, the JSON `eth_signTypedData_v4` takes
+use super::{printable, quantity, Address, EvmErr};
+use crate::crypto::{keccak256, to_vec};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+const DOMAIN: &str = "EIP712Domain";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<Field>>,
+    pub primary_type: String,
+    pub domain: Value,
+    #[serde(default)]
+    pub message: Value,
+}
+
+fn invalid(msg: String) -> EvmErr {
+    EvmErr::InvalidField(msg)
+}
+
+/// the sign and magnitude of a JSON number or a decimal or hex string, up to 256 bits
+fn magnitude(value: &Value) -> Result<(bool, [u8; 32]), EvmErr> {
+    let text = match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.trim().to_string(),
+        other => return Err(invalid(format!("expected an integer, got {}", other))),
+    };
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.as_str()),
+    };
+    let mut out = [0u8; 32];
+    let (radix, digits) = match digits.strip_prefix("0x") {
+        Some(hex) => (16, hex),
+        None => (10, digits),
+    };
+    if digits.is_empty() && radix == 10 {
+        return Err(invalid(format!("invalid integer '{}'", text)));
+    }
+    for c in digits.chars() {
+        let digit = c
+            .to_digit(radix)
+            .ok_or(invalid(format!("invalid integer '{}'", text)))?;
+        // out = out * radix + digit
+        let mut carry = digit;
+        for byte in out.iter_mut().rev() {
+            let v = *byte as u32 * radix + carry;
+            *byte = v as u8;
+            carry = v >> 8;
+        }
+        if carry != 0 {
+            return Err(invalid(format!("integer '{}' over 256 bits", text)));
+        }
+    }
+    Ok((negative, out))
+}
+
+/// an unsigned 256 bit word, negative numbers are refused
+pub(super) fn word(value: &Value) -> Result<[u8; 32], EvmErr> {
+    match magnitude(value)? {
+        (true, _) => Err(invalid(format!("{} is negative", value))),
+        (false, out) => Ok(out),
+    }
+}
+
+/// a 256 bit two's complement word
+fn signed_word(value: &Value) -> Result<[u8; 32], EvmErr> {
+    let (negative, mut out) = magnitude(value)?;
+    if !negative || out.iter().all(|b| *b == 0) {
+        return match out[0] & 0x80 {
+            0 => Ok(out),
+            _ => Err(invalid(format!("{} over 255 bits", value))),
+        };
+    }
+    // flip and add one
+    let mut carry = 1u16;
+    for byte in out.iter_mut().rev() {
+        let v = (!*byte) as u16 + carry;
+        *byte = v as u8;
+        carry = v >> 8;
+    }
+    match out[0] & 0x80 {
+        0 => Err(invalid(format!("{} under -2^255", value))),
+        _ => Ok(out),
+    }
+}
+
+/// an unsigned word in decimal
+pub(super) fn decimal(word: &[u8; 32]) -> String {
+    let mut n = *word;
+    let mut digits = Vec::new();
+    while n.iter().any(|b| *b != 0) || digits.is_empty() {
+        // n, rem = n / 10, n % 10
+        let mut rem = 0u32;
+        for byte in n.iter_mut() {
+            let v = (rem << 8) | *byte as u32;
+            *byte = (v / 10) as u8;
+            rem = v % 10;
+        }
+        digits.push(char::from(b'0' + rem as u8));
+    }
+    digits.iter().rev().collect()
+}
+
+/// whether `word` fits `uint<bits>` or `int<bits>`
+fn fits(word: &[u8; 32], bits: usize, signed: bool) -> bool {
+    let bytes = bits / 8;
+    let (high, low) = word.split_at(32 - bytes);
+    if !signed {
+        return high.iter().all(|b| *b == 0);
+    }
+    let fill = if low[0] & 0x80 != 0 { 0xff } else { 0 };
+    high.iter().all(|b| *b == fill)
+}
+
+fn hex_bytes(value: &Value) -> Result<Vec<u8>, EvmErr> {
+    value
+        .as_str()
+        .and_then(to_vec)
+        .ok_or(invalid(format!("expected hex bytes, got {}", value)))
+}
+
+/// `T[]` and `T[n]` to `T`
+fn array_item(ty: &str) -> Option<&str> {
+    ty.strip_suffix(']')
+        .and_then(|t| t.rsplit_once('['))
+        .map(|(item, _)| item)
+}
+
+/// `n` of `T[n]`, `None` for `T[]`
+fn array_len(ty: &str) -> Result<Option<usize>, EvmErr> {
+    match ty.strip_suffix(']').and_then(|t| t.rsplit_once('[')) {
+        Some((_, "")) | None => Ok(None),
+        Some((_, n)) => n
+            .parse()
+            .map(Some)
+            .map_err(|_| invalid(format!("invalid array length in '{}'", ty))),
+    }
+}
+
+fn size(ty: &str, prefix: &str) -> Option<usize> {
+    ty.strip_prefix(prefix).map(|n| match n {
+        "" => 256,
+        n => n.parse().unwrap_or(0),
+    })
+}
+
+impl TypedData {
+    fn fields(&self, ty: &str) -> Result<&[Field], EvmErr> {
+        self.types
+            .get(ty)
+            .map(|fields| fields.as_slice())
+            .ok_or(invalid(format!("unknown type '{}'", ty)))
+    }
+
+    /// the domain type is implied by the domain when not spelled out
+    fn domain_fields(&self) -> Vec<Field> {
+        if let Some(fields) = self.types.get(DOMAIN) {
+            return fields.clone();
+        }
+        [
+            ("name", "string"),
+            ("version", "string"),
+            ("chainId", "uint256"),
+            ("verifyingContract", "address"),
+            ("salt", "bytes32"),
+        ]
+        .into_iter()
+        .filter(|(name, _)| self.domain.get(name).is_some())
+        .map(|(name, ty)| Field {
+            name: name.into(),
+            ty: ty.into(),
+        })
+        .collect()
+    }
+
+    fn struct_fields(&self, ty: &str) -> Result<Vec<Field>, EvmErr> {
+        match ty {
+            DOMAIN => Ok(self.domain_fields()),
+            _ => Ok(self.fields(ty)?.to_vec()),
+        }
+    }
+
+    /// `ty` and the struct types it refers to, at any depth
+    fn dependencies(&self, ty: &str, found: &mut BTreeSet<String>) -> Result<(), EvmErr> {
+        let mut base = ty;
+        while let Some(item) = array_item(base) {
+            base = item;
+        }
+        if found.contains(base) || !(self.types.contains_key(base) || base == DOMAIN) {
+            return Ok(());
+        }
+        found.insert(base.to_string());
+        for field in self.struct_fields(base)? {
+            self.dependencies(&field.ty, found)?;
+        }
+        Ok(())
+    }
+
+    /// `Mail(Person from,Person to,string contents)Person(string name,address wallet)`
+    pub fn encode_type(&self, ty: &str) -> Result<String, EvmErr> {
+        let mut deps = BTreeSet::new();
+        self.dependencies(ty, &mut deps)?;
+        deps.remove(ty);
+        let mut out = String::new();
+        for name in std::iter::once(ty).chain(deps.iter().map(|s| s.as_str())) {
+            let fields: Vec<String> = self
+                .struct_fields(name)?
+                .iter()
+                .map(|f| format!("{} {}", f.ty, f.name))
+                .collect();
+            out.push_str(&format!("{}({})", name, fields.join(",")));
+        }
+        Ok(out)
+    }
+
+    fn encode_value(&self, ty: &str, value: &Value) -> Result<[u8; 32], EvmErr> {
+        if let Some(item) = array_item(ty) {
+            let items = value
+                .as_array()
+                .ok_or(invalid(format!("expected an array for '{}'", ty)))?;
+            if let Some(n) = array_len(ty)?.filter(|n| *n != items.len()) {
+                return Err(invalid(format!(
+                    "expected {} items for '{}', got {}",
+                    n,
+                    ty,
+                    items.len()
+                )));
+            }
+            let mut encoded = Vec::new();
+            for item_value in items {
+                encoded.extend_from_slice(&self.encode_value(item, item_value)?);
+            }
+            return Ok(keccak256(encoded));
+        }
+        if self.types.contains_key(ty) || ty == DOMAIN {
+            return self.hash_struct(ty, value);
+        }
+        let mut out = [0u8; 32];
+        match ty {
+            "address" => {
+                let address: Address = value
+                    .as_str()
+                    .ok_or(invalid(format!("expected an address, got {}", value)))?
+                    .parse()
+                    .map_err(invalid)?;
+                out[12..].copy_from_slice(&address.0);
+            }
+            "bool" => {
+                let b = match value {
+                    Value::Bool(b) => *b,
+                    Value::String(s) if s == "true" || s == "false" => s == "true",
+                    other => return Err(invalid(format!("expected a bool, got {}", other))),
+                };
+                out[31] = b as u8;
+            }
+            "string" => {
+                let s = value
+                    .as_str()
+                    .ok_or(invalid(format!("expected a string, got {}", value)))?;
+                out = keccak256(s.as_bytes().to_vec());
+            }
+            "bytes" => out = keccak256(hex_bytes(value)?),
+            _ => {
+                if let Some(n) = size(ty, "bytes").filter(|n| (1..=32).contains(n)) {
+                    let bytes = hex_bytes(value)?;
+                    if bytes.len() != n {
+                        return Err(invalid(format!("expected {} bytes for '{}'", n, ty)));
+                    }
+                    out[..n].copy_from_slice(&bytes);
+                } else if let Some((bits, signed)) = size(ty, "uint")
+                    .map(|n| (n, false))
+                    .or(size(ty, "int").map(|n| (n, true)))
+                    .filter(|(n, _)| *n > 0 && *n <= 256 && n.is_multiple_of(8))
+                {
+                    out = match signed {
+                        true => signed_word(value)?,
+                        false => word(value)?,
+                    };
+                    if !fits(&out, bits, signed) {
+                        return Err(invalid(format!("{} does not fit '{}'", value, ty)));
+                    }
+                } else {
+                    return Err(invalid(format!("unknown type '{}'", ty)));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// `keccak256(typeHash || encodeData(value))`
+    pub fn hash_struct(&self, ty: &str, value: &Value) -> Result<[u8; 32], EvmErr> {
+        let mut encoded = keccak256(self.encode_type(ty)?.into_bytes()).to_vec();
+        for field in self.struct_fields(ty)? {
+            let field_value = value
+                .get(&field.name)
+                .ok_or(invalid(format!("'{}' has no '{}'", ty, field.name)))?;
+            encoded.extend_from_slice(&self.encode_value(&field.ty, field_value)?);
+        }
+        Ok(keccak256(encoded))
+    }
+
+    pub fn domain_separator(&self) -> Result<[u8; 32], EvmErr> {
+        self.hash_struct(DOMAIN, &self.domain)
+    }
+
+    /// the hash that is signed, `keccak256(0x1901 || domainSeparator || hashStruct(message))`
+    pub fn signing_hash(&self) -> Result<[u8; 32], EvmErr> {
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend_from_slice(&self.domain_separator()?);
+        if self.primary_type != DOMAIN {
+            encoded.extend_from_slice(&self.hash_struct(&self.primary_type, &self.message)?);
+        }
+        Ok(keccak256(encoded))
+    }
+
+    /// the domain's chain id, when it has one
+    pub fn chain_id(&self) -> Option<u64> {
+        match self.domain.get("chainId")? {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => quantity::parse(s).and_then(|n| u64::try_from(n).ok()),
+            _ => None,
+        }
+    }
+
+    /// the verifying contract, when the domain has a valid one
+    pub fn verifying_contract(&self) -> Option<Address> {
+        self.domain.get("verifyingContract")?.as_str()?.parse().ok()
+    }
+
+    /// for the approval prompt, what is signed and for whom
+    pub fn describe(&self) -> String {
+        let field = |name: &str| match self.domain.get(name) {
+            Some(Value::String(s)) => printable(s),
+            Some(other) => printable(&other.to_string()),
+            None => "-".into(),
+        };
+        format!(
+            "{} for '{}' (chain {}, verifying contract {})",
+            printable(&self.primary_type),
+            field("name"),
+            field("chainId"),
+            field("verifyingContract"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{recover, sign_hash, to_hex};
+    use super::*;
+
+    /// the example from the EIP
+    fn mail() -> TypedData {
+        serde_json::from_value(serde_json::json!({
+            "types": {
+                "EIP712Domain": [
+                    {"name": "name", "type": "string"},
+                    {"name": "version", "type": "string"},
+                    {"name": "chainId", "type": "uint256"},
+                    {"name": "verifyingContract", "type": "address"}
+                ],
+                "Person": [
+                    {"name": "name", "type": "string"},
+                    {"name": "wallet", "type": "address"}
+                ],
+                "Mail": [
+                    {"name": "from", "type": "Person"},
+                    {"name": "to", "type": "Person"},
+                    {"name": "contents", "type": "string"}
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+                "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+                "contents": "Hello, Bob!"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn eip712_vector() {
+        let data = mail();
+        assert_eq!(
+            data.encode_type("Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            to_hex(&data.domain_separator().unwrap()),
+            "0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            to_hex(&data.hash_struct("Mail", &data.message).unwrap()),
+            "0xc52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        let hash = data.signing_hash().unwrap();
+        assert_eq!(
+            to_hex(&hash),
+            "0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+        let (sig, from) = sign_hash(&keccak256(b"cow".to_vec()), &hash).unwrap();
+        assert_eq!(
+            from.to_string(),
+            "0xcd2a3d9f938e13cd947ec05abc7fe734df8dd826"
+        );
+        assert_eq!(
+            to_hex(&sig.r),
+            "0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d"
+        );
+        assert_eq!(
+            to_hex(&sig.s),
+            "0x07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562"
+        );
+        assert_eq!(recover(&hash, &sig.to_rsv()).unwrap(), from);
+        assert_eq!(
+            data.describe(),
+            "Mail for 'Ether Mail' (chain 1, verifying contract 0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC)"
+        );
+        assert_eq!(data.chain_id(), Some(1));
+        let mut forged = data.clone();
+        forged.domain["name"] = "Ether Mail'\nsend all to 0x00\u{2066}".into();
+        assert!(forged
+            .describe()
+            .starts_with(r"Mail for 'Ether Mail'\nsend all to 0x00\u{2066}' (chain 1"));
+
+        // the domain type is implied when left out
+        let mut implied = data.clone();
+        implied.types.remove(DOMAIN);
+        assert_eq!(implied.signing_hash().unwrap(), hash);
+        let mut missing = data;
+        missing.message["to"]
+            .as_object_mut()
+            .unwrap()
+            .remove("wallet");
+        assert!(missing.signing_hash().is_err());
+    }
+
+    #[test]
+    fn fixed_arrays() {
+        let pair = |xs: Value| -> TypedData {
+            serde_json::from_value(serde_json::json!({
+                "types": {"Pair": [{"name": "xs", "type": "uint8[2]"}]},
+                "primaryType": "Pair",
+                "domain": {"name": "app"},
+                "message": {"xs": xs}
+            }))
+            .unwrap()
+        };
+        pair(serde_json::json!([1, 2])).signing_hash().unwrap();
+        assert!(pair(serde_json::json!([1])).signing_hash().is_err());
+        assert!(pair(serde_json::json!([1, 2, 3])).signing_hash().is_err());
+        assert!(pair(serde_json::json!([1, -2])).signing_hash().is_err());
+        assert!(array_len("uint8[x]").is_err());
+        assert_eq!(array_len("uint8[]").unwrap(), None);
+    }
+
+    #[test]
+    fn integers() {
+        let w = word(&Value::from(1024)).unwrap();
+        assert_eq!(w[30..], [4, 0]);
+        assert_eq!(word(&Value::from("0x400")).unwrap(), w);
+        assert_eq!(signed_word(&Value::from("-1")).unwrap(), [0xff; 32]);
+        assert_eq!(signed_word(&Value::from(-1)).unwrap(), [0xff; 32]);
+        assert!(fits(&signed_word(&Value::from("-128")).unwrap(), 8, true));
+        assert!(!fits(&signed_word(&Value::from("-129")).unwrap(), 8, true));
+        assert!(!fits(&word(&Value::from("256")).unwrap(), 8, false));
+        // a negative number is no uint, however it is spelled
+        assert!(word(&Value::from("-1")).is_err());
+        assert!(word(&Value::from(-1)).is_err());
+        assert!(word(&Value::from(" -0x1")).is_err());
+        // int256 stops at -2^255 and 2^255 - 1
+        let min = format!("-0x8{}", "0".repeat(63));
+        assert!(signed_word(&Value::from(min.as_str())).is_ok());
+        assert!(signed_word(&Value::from(format!("-0x8{}1", "0".repeat(62)))).is_err());
+        assert!(signed_word(&Value::from(format!("0x8{}", "0".repeat(63)))).is_err());
+        let max = format!("0x{}", "f".repeat(64));
+        assert!(word(&Value::from(max.as_str())).is_ok());
+        assert!(word(&Value::from(format!("{}0", max))).is_err());
+        assert!(word(&Value::from("12a")).is_err());
+        let big = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(decimal(&word(&Value::from(big)).unwrap()), big);
+        assert_eq!(decimal(&[0; 32]), "0");
+        assert_eq!(decimal(&w), "1024");
+    }
+}
@@ -1,17 +1,42 @@
-pub use crypto::encrypt_key;
+pub use backup::{BackupErr, BackupReport};
+pub use cli::run_cli;
+pub use client::{ClientErr, HotCheeseClient, RetryPolicy};
+pub use config::{Config, ConfigErr};
+pub use crypto::{
+    decrypt_key, encrypt_key, encrypt_keystore, KdfConfig, KeyMeta, KeyType, Keystore,
+};
+pub use evm::{
+    AccessListItem, Address, EthSignature, EvmErr, Payload, SignedTransaction, Transaction,
+    TypedData, Verification, VerifyRequest, B256,
+};
 pub use mac::MacBackend;
+pub use manifest::{Change, ManifestErr};
+pub use paper::{PaperErr, PaperKey};
+pub use policy::{KeyPolicy, PolicyErr};
+pub use proxy::{run_proxy, HttpUpstream, Proxy, ProxyConfig, ProxyErr, Upstream};
 pub use server::resolve_path;
 pub use server::run_server;
-pub use server::BackendImpl;
-
-use serde::{Deserialize, Serialize};
-#[derive(Deserialize, Serialize)]
-pub struct Config {
-    pub service: String,
-    pub account: String,
-    pub store: String,
-}
+pub use server::{ApiBackendErr, BackendImpl, HotApi};
+pub use slip39::Slip39Err;
+pub use solana::{Pubkey, SolanaErr, SolanaPayload, SolanaSignature};
+pub use store::{
+    AuditEntry, DirKeyStore, Engine, KeyStore, KeyVersion, MemKeyStore, SqliteKeyStore, StoreErr,
+};
 
+mod backup;
+mod clef;
+mod cli;
+mod client;
+mod config;
 mod crypto;
+mod evm;
 mod mac;
+mod manifest;
+mod paper;
+mod policy;
+mod proxy;
+mod secure_fs;
 mod server;
+mod slip39;
+mod solana;
+mod store;
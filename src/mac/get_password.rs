@@ -6,13 +6,13 @@ use std::{
 use zeroize::Zeroize;
 
 #[repr(C)]
-struct CFDictionary(c_void);
+pub(super) struct CFDictionary(c_void);
 
 #[repr(C)]
-struct CFString(c_void);
+pub(super) struct CFString(c_void);
 
 #[repr(C)]
-struct CFTypeRef(c_void);
+pub(super) struct CFTypeRef(c_void);
 
 extern "C" {
     fn SecItemCopyMatching(query: *const CFDictionary, result: *mut *const CFTypeRef) -> c_int;
@@ -45,9 +45,10 @@ struct CFRange {
     location: usize,
     length: usize,
 }
+#[allow(non_upper_case_globals)]
 const kCFStringEncodingUTF8: u32 = 0x08000100;
 
-fn create_cf_string(string: &str) -> Result<*const CFString, GetPasswordErr> {
+pub(super) fn create_cf_string(string: &str) -> Result<*const CFString, GetPasswordErr> {
     let cstr = CString::new(string)?;
     unsafe {
         Ok(CFStringCreateWithCString(
@@ -61,19 +62,14 @@ fn create_cf_string(string: &str) -> Result<*const CFString, GetPasswordErr> {
 fn create_query(service: &str, account: &str) -> Result<*const CFDictionary, GetPasswordErr> {
     unsafe {
         // Use Core Foundation constants for keys
-        let keys = [
-            kSecClass as *const c_void,
-            kSecAttrService as *const c_void,
-            kSecAttrAccount as *const c_void,
-            kSecReturnData as *const c_void,
-        ];
+        let keys = [kSecClass, kSecAttrService, kSecAttrAccount, kSecReturnData];
 
         // Use valid values for the keys
         let values = [
-            kSecClassGenericPassword as *const c_void, // Class: Generic Password
+            kSecClassGenericPassword,                    // Class: Generic Password
             create_cf_string(service)? as *const c_void, // Service string
             create_cf_string(account)? as *const c_void, // Account string
-            kCFBooleanTrue as *const c_void,           // Return data as true
+            kCFBooleanTrue,                              // Return data as true
         ];
 
         // Create the query dictionary
@@ -1,14 +1,23 @@
+use crate::crypto::KdfConfig;
+use crate::policy::KeyPolicy;
 use crate::server::BackendImpl;
+use crate::store::{Engine, KeyStore};
 use get_password::get_password_from_keychain;
+use set_password::{add_password_to_keychain, has_password_in_keychain};
+use std::collections::BTreeMap;
 use touch_id::authorize_with_touch_id;
 
 mod get_password;
+mod set_password;
 mod touch_id;
 
 pub struct MacBackend {
     service: String,
     account: String,
     store: String,
+    kdf: KdfConfig,
+    engine: Engine,
+    policies: BTreeMap<String, KeyPolicy>,
 }
 impl MacBackend {
     pub fn new(service: &str, account: &str, store: &str) -> Self {
@@ -16,8 +25,23 @@ impl MacBackend {
             service: service.into(),
             account: account.into(),
             store: store.into(),
+            kdf: KdfConfig::default(),
+            engine: Engine::default(),
+            policies: BTreeMap::new(),
         }
     }
+    pub fn with_kdf(mut self, kdf: KdfConfig) -> Self {
+        self.kdf = kdf;
+        self
+    }
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+    pub fn with_policies(mut self, policies: BTreeMap<String, KeyPolicy>) -> Self {
+        self.policies = policies;
+        self
+    }
 }
 
 impl BackendImpl for MacBackend {
@@ -27,9 +51,30 @@ impl BackendImpl for MacBackend {
     fn get_encryption_key(&self) -> Option<Vec<u8>> {
         get_password_from_keychain(&self.service, &self.account).ok()
     }
+    fn set_encryption_key(&self, key: &[u8]) -> bool {
+        match add_password_to_keychain(&self.service, &self.account, key) {
+            Ok(_) => true,
+            Err(e) => {
+                self.communicate_err(format!("failed to add master key {}", e));
+                false
+            }
+        }
+    }
+    fn has_encryption_key(&self) -> bool {
+        has_password_in_keychain(&self.service, &self.account).unwrap_or(false)
+    }
     fn store(&self) -> &str {
         &self.store
     }
+    fn kdf(&self) -> KdfConfig {
+        self.kdf.clone()
+    }
+    fn key_store(&self) -> Box<dyn KeyStore> {
+        self.engine.key_store(&self.store_path())
+    }
+    fn policy(&self, name: &str) -> KeyPolicy {
+        self.policies.get(name).cloned().unwrap_or_default()
+    }
     fn communicate_err(&self, e: String) {
         eprintln!("{}", e)
     }
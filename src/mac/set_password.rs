@@ -0,0 +1,114 @@
+use super::get_password::{create_cf_string, CFDictionary, CFTypeRef, GetPasswordErr};
+use std::{
+    ffi::{c_int, c_void},
+    ptr,
+};
+
+extern "C" {
+    fn SecItemAdd(attributes: *const CFDictionary, result: *mut *const CFTypeRef) -> c_int;
+    fn SecItemCopyMatching(query: *const CFDictionary, result: *mut *const CFTypeRef) -> c_int;
+    fn CFDictionaryCreate(
+        allocator: *const c_void,
+        keys: *const *const c_void,
+        values: *const *const c_void,
+        num_values: usize,
+        key_callbacks: *const c_void,
+        value_callbacks: *const c_void,
+    ) -> *const CFDictionary;
+    fn CFDataCreateWithBytesNoCopy(
+        allocator: *const c_void,
+        bytes: *const u8,
+        length: isize,
+        bytes_deallocator: *const c_void,
+    ) -> *const c_void;
+    fn CFRelease(cf: *const c_void);
+    static kCFAllocatorNull: *const c_void;
+    static kSecClass: *const c_void;
+    static kSecAttrService: *const c_void;
+    static kSecAttrAccount: *const c_void;
+    static kSecValueData: *const c_void;
+    static kSecClassGenericPassword: *const c_void;
+}
+
+const ERR_SEC_ITEM_NOT_FOUND: c_int = -25300;
+
+/// a CoreFoundation object we created, released when dropped so no return path leaks it
+struct Owned(*const c_void);
+
+impl Owned {
+    fn new<T>(cf: *const T) -> Result<Self, GetPasswordErr> {
+        match cf.is_null() {
+            true => Err(GetPasswordErr::NullRes),
+            false => Ok(Self(cf as *const c_void)),
+        }
+    }
+}
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        unsafe { CFRelease(self.0) }
+    }
+}
+
+/// the dictionary doesn't retain its keys and values, they have to outlive it
+fn create_dict(keys: &[*const c_void], values: &[*const c_void]) -> Result<Owned, GetPasswordErr> {
+    let dictionary = unsafe {
+        CFDictionaryCreate(
+            ptr::null(),
+            keys.as_ptr(),
+            values.as_ptr(),
+            keys.len(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    Owned::new(dictionary).map_err(|_| GetPasswordErr::FailCreateDict)
+}
+
+/// same item `security add-generic-password -a <account> -s <service>` would create,
+/// without the password ever being on a command line
+pub fn add_password_to_keychain(
+    service: &str,
+    account: &str,
+    password: &[u8],
+) -> Result<(), GetPasswordErr> {
+    let service = Owned::new(create_cf_string(service)?)?;
+    let account = Owned::new(create_cf_string(account)?)?;
+    // points at `password` instead of copying it, so no copy is left behind unzeroized
+    let data = Owned::new(unsafe {
+        CFDataCreateWithBytesNoCopy(
+            ptr::null(),
+            password.as_ptr(),
+            password.len() as isize,
+            kCFAllocatorNull,
+        )
+    })?;
+    let keys = unsafe { [kSecClass, kSecAttrService, kSecAttrAccount, kSecValueData] };
+    let values = [
+        unsafe { kSecClassGenericPassword },
+        service.0,
+        account.0,
+        data.0,
+    ];
+    let attributes = create_dict(&keys, &values)?;
+    let status = unsafe { SecItemAdd(attributes.0 as *const CFDictionary, ptr::null_mut()) };
+    if status != 0 {
+        return Err(GetPasswordErr::NonzeroStatus(status));
+    }
+    Ok(())
+}
+
+/// looks the item up without asking for its data
+pub fn has_password_in_keychain(service: &str, account: &str) -> Result<bool, GetPasswordErr> {
+    let service = Owned::new(create_cf_string(service)?)?;
+    let account = Owned::new(create_cf_string(account)?)?;
+    let keys = unsafe { [kSecClass, kSecAttrService, kSecAttrAccount] };
+    let values = [unsafe { kSecClassGenericPassword }, service.0, account.0];
+    let query = create_dict(&keys, &values)?;
+    let status = unsafe { SecItemCopyMatching(query.0 as *const CFDictionary, ptr::null_mut()) };
+    match status {
+        0 => Ok(true),
+        ERR_SEC_ITEM_NOT_FOUND => Ok(false),
+        status => Err(GetPasswordErr::NonzeroStatus(status)),
+    }
+}
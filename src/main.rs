@@ -1,14 +1,6 @@
-use hot_cheese::{run_server, Config, MacBackend};
+use hot_cheese::run_cli;
 
 fn main() {
-    // so everybody can customize the storage and name of service and account
-    // and embed it in the binary
-    let bytes = include_bytes!("./conf/cheese_config.json");
-    let conf: Config = serde_json::from_slice(bytes.as_slice()).unwrap();
-    run_server(Box::new(MacBackend::new(
-        &conf.service,
-        &conf.account,
-        &conf.store,
-    )))
-    .unwrap()
+    // no subcommand means `serve`, see `hot_cheese help`
+    std::process::exit(run_cli(std::env::args().skip(1).collect()))
 }
@@ -0,0 +1,222 @@
+//! Store-wide integrity manifest. Per-file MACs can't tell that a keystore was rolled back to
+//! an older copy, removed or planted, so the hash of every keystore in the store is kept in
+//! `.manifest` and the whole list is MACed under a key derived from the master key.
+use crate::crypto::Keystore;
+use crate::policy::LEDGER_NAME;
+use crate::store::{KeyStore, StoreErr};
+use err_mac::create_err_with_impls;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+/// not a valid key name, so it is never listed as a key
+pub const MANIFEST_NAME: &str = ".manifest";
+/// files besides the keys it covers, looked up by name since they are never listed
+pub const TRACKED: [&str; 1] = [LEDGER_NAME];
+const MANIFEST_VERSION: u8 = 1;
+const MAC_KEY_INFO: &[u8] = b"hot_cheese manifest v1";
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// in the store but not in the manifest
+    Added(String),
+    /// in the manifest but gone from the store
+    Removed(String),
+    /// contents differ, e.g. an older copy was restored
+    Changed(String),
+}
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub ManifestErr,
+    Store(StoreErr),
+    Serde(serde_json::Error),
+    Missing,
+    BadMac,
+    UnsupportedVersion(u8),
+    Mismatch(Vec<Change>)
+    ;
+);
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Entry {
+    /// keystore format version, 0 if the file doesn't parse as one
+    pub version: u8,
+    pub sha256: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ManifestFile {
+    version: u8,
+    entries: BTreeMap<String, Entry>,
+    mac: String,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn mac(master: &[u8], entries: &BTreeMap<String, Entry>) -> Result<HmacSha256, ManifestErr> {
+    let mut kdf = HmacSha256::new_from_slice(master).expect("hmac takes any key size");
+    kdf.update(MAC_KEY_INFO);
+    let key = kdf.finalize().into_bytes();
+    let mut mac = HmacSha256::new_from_slice(&key).expect("hmac takes any key size");
+    mac.update(&[MANIFEST_VERSION]);
+    mac.update(&serde_json::to_vec(entries)?);
+    Ok(mac)
+}
+
+/// hash and version of one keystore, `None` if there is no such key
+pub fn entry(store: &dyn KeyStore, name: &str) -> Result<Option<Entry>, ManifestErr> {
+    let Some(bytes) = store.get(name)? else {
+        return Ok(None);
+    };
+    Ok(Some(Entry {
+        version: Keystore::from_slice(&bytes)
+            .map(|k| k.version())
+            .unwrap_or(0),
+        sha256: hex(&Sha256::digest(&bytes)),
+    }))
+}
+
+/// what the store holds right now
+pub fn scan(store: &dyn KeyStore) -> Result<BTreeMap<String, Entry>, ManifestErr> {
+    let mut entries = BTreeMap::new();
+    let tracked = TRACKED.iter().map(|n| n.to_string());
+    for name in store.list()?.into_iter().chain(tracked) {
+        if let Some(e) = entry(store, &name)? {
+            entries.insert(name, e);
+        }
+    }
+    Ok(entries)
+}
+
+/// the authenticated entries, `None` when the store has no manifest yet
+pub fn load(
+    store: &dyn KeyStore,
+    master: &[u8],
+) -> Result<Option<BTreeMap<String, Entry>>, ManifestErr> {
+    let Some(bytes) = store.get(MANIFEST_NAME)? else {
+        return Ok(None);
+    };
+    let file: ManifestFile = serde_json::from_slice(&bytes)?;
+    if file.version != MANIFEST_VERSION {
+        return Err(ManifestErr::UnsupportedVersion(file.version));
+    }
+    let tag = crate::crypto::to_vec(&file.mac).ok_or(ManifestErr::BadMac)?;
+    mac(master, &file.entries)?
+        .verify_slice(&tag)
+        .map_err(|_| ManifestErr::BadMac)?;
+    Ok(Some(file.entries))
+}
+
+pub fn save(
+    store: &dyn KeyStore,
+    master: &[u8],
+    entries: BTreeMap<String, Entry>,
+) -> Result<(), ManifestErr> {
+    let tag = mac(master, &entries)?.finalize().into_bytes();
+    let file = ManifestFile {
+        version: MANIFEST_VERSION,
+        mac: hex(&tag),
+        entries,
+    };
+    store.put(MANIFEST_NAME, &serde_json::to_vec_pretty(&file)?)?;
+    Ok(())
+}
+
+/// differences between what the manifest says and what is on disk
+pub fn diff(expected: &BTreeMap<String, Entry>, actual: &BTreeMap<String, Entry>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (name, e) in expected {
+        match actual.get(name) {
+            None => changes.push(Change::Removed(name.clone())),
+            Some(a) if a != e => changes.push(Change::Changed(name.clone())),
+            _ => {}
+        }
+    }
+    for name in actual.keys() {
+        if !expected.contains_key(name) {
+            changes.push(Change::Added(name.clone()));
+        }
+    }
+    changes
+}
+
+/// the store must match its manifest exactly. a store without a manifest is only fine
+/// while it has no keys
+pub fn verify(store: &dyn KeyStore, master: &[u8]) -> Result<(), ManifestErr> {
+    let actual = scan(store)?;
+    let expected = match load(store, master)? {
+        Some(expected) => expected,
+        None if actual.is_empty() => return Ok(()),
+        None => return Err(ManifestErr::Missing),
+    };
+    let changes = diff(&expected, &actual);
+    if !changes.is_empty() {
+        return Err(ManifestErr::Mismatch(changes));
+    }
+    Ok(())
+}
+
+/// picks up a write the server itself made to `name`, or its removal
+pub fn record(store: &dyn KeyStore, master: &[u8], name: &str) -> Result<(), ManifestErr> {
+    let mut entries = load(store, master)?.unwrap_or_default();
+    match entry(store, name)? {
+        Some(e) => entries.insert(name.to_string(), e),
+        None => entries.remove(name),
+    };
+    save(store, master, entries)
+}
+
+/// trusts whatever is in the store now, returns the names it covers
+pub fn rebuild(store: &dyn KeyStore, master: &[u8]) -> Result<Vec<String>, ManifestErr> {
+    let entries = scan(store)?;
+    let names = entries.keys().cloned().collect();
+    save(store, master, entries)?;
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::MemKeyStore;
+
+    #[test]
+    fn detects_tampering() {
+        let store = MemKeyStore::default();
+        let master = b"master";
+
+        verify(&store, master).unwrap();
+        store.put("A", b"a1").unwrap();
+        assert!(matches!(verify(&store, master), Err(ManifestErr::Missing)));
+        record(&store, master, "A").unwrap();
+        store.put("B", b"b1").unwrap();
+        record(&store, master, "B").unwrap();
+        verify(&store, master).unwrap();
+
+        // rolled back, planted and dropped keys
+        store.put("A", b"a0").unwrap();
+        store.put("C", b"c").unwrap();
+        store.delete("B").unwrap();
+        match verify(&store, master) {
+            Err(ManifestErr::Mismatch(changes)) => assert_eq!(
+                changes,
+                vec![
+                    Change::Changed("A".into()),
+                    Change::Removed("B".into()),
+                    Change::Added("C".into()),
+                ]
+            ),
+            other => panic!("{:?}", other),
+        }
+
+        assert_eq!(rebuild(&store, master).unwrap(), vec!["A", "C"]);
+        verify(&store, master).unwrap();
+        // a manifest written under another master key doesn't count
+        assert!(matches!(verify(&store, b"other"), Err(ManifestErr::BadMac)));
+    }
+}
@@ -0,0 +1,341 @@
+//! Printable passphrase-encrypted backups of single keys, in the spirit of BIP-38. The key
+//! is sealed with XChaCha20-Poly1305 under the passphrase stretched with the configured KDF
+//! and written as one base58check string. Like BIP-38 the string carries a 4 byte hash of
+//! the address, so the address printed next to it can be checked without the passphrase and
+//! the decrypted key can be checked against it.
+use crate::crypto::{open_aead, seal_aead, CryptoErr, KdfConfig, KdfParams, KeyType};
+use err_mac::create_err_with_impls;
+use rand::{CryptoRng, Rng};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+pub const PAPER_TITLE: &str = "hot_cheese paper backup";
+/// base58 of the decoded code starts with these, "hc"
+const PREFIX: [u8; 2] = [0x68, 0x63];
+const PAPER_VERSION: u8 = 1;
+const SALT_LEN: usize = 8;
+const CHECK_LEN: usize = 4;
+const NONCE_LEN: usize = 24;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub PaperErr,
+    Crypto(CryptoErr),
+    NotAPaperBackup,
+    BadChecksum,
+    UnsupportedVersion(u8),
+    UnsupportedKdf,
+    AddressMismatch
+    ;
+);
+
+fn sha256d(bytes: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(bytes)).into()
+}
+
+/// what a code commits to about the address, zeros for keys without one
+fn address_check(address: Option<&str>) -> [u8; CHECK_LEN] {
+    let mut check = [0u8; CHECK_LEN];
+    if let Some(address) = address {
+        check.copy_from_slice(&sha256d(address.as_bytes())[..CHECK_LEN]);
+    }
+    check
+}
+
+fn key_type_byte(key_type: KeyType) -> u8 {
+    match key_type {
+        KeyType::Evm => 0,
+        KeyType::Solana => 1,
+        KeyType::Bytes => 2,
+    }
+}
+
+fn key_type_from_byte(b: u8) -> Option<KeyType> {
+    match b {
+        0 => Some(KeyType::Evm),
+        1 => Some(KeyType::Solana),
+        2 => Some(KeyType::Bytes),
+        _ => None,
+    }
+}
+
+fn encode_kdf(kdf: &KdfConfig) -> Result<Vec<u8>, PaperErr> {
+    match *kdf {
+        KdfConfig::Scrypt { log_n, r, p } => Ok(vec![
+            0,
+            log_n,
+            u8::try_from(r).map_err(|_| PaperErr::UnsupportedKdf)?,
+            u8::try_from(p).map_err(|_| PaperErr::UnsupportedKdf)?,
+        ]),
+        KdfConfig::Argon2id {
+            m_cost,
+            t_cost,
+            p_cost,
+        } => {
+            let mut out = vec![1];
+            out.extend_from_slice(&m_cost.to_be_bytes());
+            out.push(u8::try_from(t_cost).map_err(|_| PaperErr::UnsupportedKdf)?);
+            out.push(u8::try_from(p_cost).map_err(|_| PaperErr::UnsupportedKdf)?);
+            Ok(out)
+        }
+    }
+}
+
+/// the config and how many bytes it took
+fn decode_kdf(bytes: &[u8]) -> Result<(KdfConfig, usize), PaperErr> {
+    match bytes {
+        [0, log_n, r, p, ..] => Ok((
+            KdfConfig::Scrypt {
+                log_n: *log_n,
+                r: *r as u32,
+                p: *p as u32,
+            },
+            4,
+        )),
+        [1, m0, m1, m2, m3, t_cost, p_cost, ..] => Ok((
+            KdfConfig::Argon2id {
+                m_cost: u32::from_be_bytes([*m0, *m1, *m2, *m3]),
+                t_cost: *t_cost as u32,
+                p_cost: *p_cost as u32,
+            },
+            7,
+        )),
+        _ => Err(PaperErr::UnsupportedKdf),
+    }
+}
+
+/// a decrypted code, the secret is wiped when dropped
+pub struct Opened {
+    pub key_type: KeyType,
+    pub secret: Vec<u8>,
+    check: [u8; CHECK_LEN],
+}
+
+impl Opened {
+    /// `address` is the one the code was made for
+    pub fn matches(&self, address: Option<&str>) -> bool {
+        address_check(address) == self.check
+    }
+}
+
+impl Drop for Opened {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+struct Decoded {
+    key_type: KeyType,
+    kdf: KdfConfig,
+    salt: Vec<u8>,
+    check: [u8; CHECK_LEN],
+    /// everything before the nonce
+    aad: Vec<u8>,
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// the base58check code for `secret`
+pub fn encrypt<R: Rng + CryptoRng>(
+    rng: &mut R,
+    secret: &[u8],
+    passphrase: &[u8],
+    key_type: KeyType,
+    address: Option<&str>,
+    kdf: &KdfConfig,
+) -> Result<String, PaperErr> {
+    let mut payload = PREFIX.to_vec();
+    payload.push(PAPER_VERSION);
+    payload.push(key_type_byte(key_type));
+    payload.extend_from_slice(&encode_kdf(kdf)?);
+    let mut salt = vec![0u8; SALT_LEN];
+    rng.fill_bytes(&mut salt);
+    payload.extend_from_slice(&salt);
+    let check = address_check(address);
+    payload.extend_from_slice(&check);
+
+    // the address hash is part of the salt, as in BIP-38
+    salt.extend_from_slice(&check);
+    let mut key = KdfParams::with_salt(kdf, salt).derive(passphrase)?;
+    let res = seal_aead(rng, &key, secret, &payload);
+    key.zeroize();
+    let (nonce, ciphertext) = res?;
+    payload.extend_from_slice(&nonce);
+    payload.extend_from_slice(&ciphertext);
+    let checksum = sha256d(&payload);
+    payload.extend_from_slice(&checksum[..4]);
+    Ok(bs58::encode(payload).into_string())
+}
+
+fn decode(code: &str) -> Result<Decoded, PaperErr> {
+    let bytes = bs58::decode(code.trim())
+        .into_vec()
+        .map_err(|_| PaperErr::NotAPaperBackup)?;
+    if bytes.len() < 4 {
+        return Err(PaperErr::NotAPaperBackup);
+    }
+    let (payload, checksum) = bytes.split_at(bytes.len() - 4);
+    if sha256d(payload)[..4] != *checksum {
+        return Err(PaperErr::BadChecksum);
+    }
+    if payload.len() < 4 || payload[..2] != PREFIX {
+        return Err(PaperErr::NotAPaperBackup);
+    }
+    if payload[2] != PAPER_VERSION {
+        return Err(PaperErr::UnsupportedVersion(payload[2]));
+    }
+    let key_type = key_type_from_byte(payload[3]).ok_or(PaperErr::NotAPaperBackup)?;
+    let (kdf, kdf_len) = decode_kdf(&payload[4..])?;
+    let salt_at = 4 + kdf_len;
+    let nonce_at = salt_at + SALT_LEN + CHECK_LEN;
+    if payload.len() < nonce_at + NONCE_LEN {
+        return Err(PaperErr::NotAPaperBackup);
+    }
+    let mut check = [0u8; CHECK_LEN];
+    check.copy_from_slice(&payload[salt_at + SALT_LEN..nonce_at]);
+    Ok(Decoded {
+        key_type,
+        kdf,
+        salt: payload[salt_at..salt_at + SALT_LEN].to_vec(),
+        check,
+        aad: payload[..nonce_at].to_vec(),
+        nonce: payload[nonce_at..nonce_at + NONCE_LEN].to_vec(),
+        ciphertext: payload[nonce_at + NONCE_LEN..].to_vec(),
+    })
+}
+
+/// a wrong passphrase fails with `CryptoErr::MacMismatch`
+pub fn decrypt(code: &str, passphrase: &[u8]) -> Result<Opened, PaperErr> {
+    let mut decoded = decode(code)?;
+    decoded.salt.extend_from_slice(&decoded.check);
+    let mut key = KdfParams::with_salt(&decoded.kdf, decoded.salt).derive(passphrase)?;
+    let res = open_aead(&key, &decoded.nonce, &decoded.ciphertext, &decoded.aad);
+    key.zeroize();
+    Ok(Opened {
+        key_type: decoded.key_type,
+        secret: res?,
+        check: decoded.check,
+    })
+}
+
+/// the printed form, one `field: value` per line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaperKey {
+    pub name: Option<String>,
+    pub key_type: KeyType,
+    pub address: Option<String>,
+    pub code: String,
+}
+
+impl PaperKey {
+    pub fn to_text(&self) -> String {
+        let key_type = serde_json::to_value(self.key_type).expect("plain enum");
+        let mut lines = vec![format!("{} v{}", PAPER_TITLE, PAPER_VERSION)];
+        if let Some(name) = self.name.as_ref() {
+            lines.push(format!("name:    {}", name));
+        }
+        lines.push(format!(
+            "type:    {}",
+            key_type.as_str().unwrap_or_default()
+        ));
+        if let Some(address) = self.address.as_ref() {
+            lines.push(format!("address: {}", address));
+        }
+        lines.push(format!("key:     {}", self.code));
+        lines.join("\n")
+    }
+
+    /// the printed form or just the code. the address, if given, has to be the one the code
+    /// was made for, which needs no passphrase to check
+    pub fn parse(text: &str) -> Result<Self, PaperErr> {
+        let field = |name: &str| {
+            text.lines().find_map(|line| {
+                let (k, v) = line.split_once(':')?;
+                (k.trim() == name).then(|| v.trim().to_string())
+            })
+        };
+        let code = match field("key") {
+            Some(code) => code,
+            None => text.trim().to_string(),
+        };
+        let decoded = decode(&code)?;
+        let address = field("address");
+        if address.is_some() && address_check(address.as_deref()) != decoded.check {
+            return Err(PaperErr::AddressMismatch);
+        }
+        Ok(Self {
+            name: field("name"),
+            key_type: decoded.key_type,
+            address,
+            code,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEST_KDF: KdfConfig = KdfConfig::Argon2id {
+        m_cost: 256,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn paper_roundtrip() {
+        let mut rng = rand::thread_rng();
+        let secret = [7u8; 32];
+        let address = "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf";
+        let code = encrypt(
+            &mut rng,
+            &secret,
+            b"pass",
+            KeyType::Evm,
+            Some(address),
+            &TEST_KDF,
+        )
+        .unwrap();
+        let paper = PaperKey {
+            name: Some("TRADER".into()),
+            key_type: KeyType::Evm,
+            address: Some(address.into()),
+            code: code.clone(),
+        };
+        let parsed = PaperKey::parse(&paper.to_text()).unwrap();
+        assert_eq!(parsed, paper);
+        assert_eq!(PaperKey::parse(&code).unwrap().address, None);
+
+        let opened = decrypt(&parsed.code, b"pass").unwrap();
+        assert_eq!(opened.secret, secret);
+        assert_eq!(opened.key_type, KeyType::Evm);
+        assert!(opened.matches(Some(address)));
+        assert!(!opened.matches(None));
+        assert!(matches!(
+            decrypt(&code, b"wrong"),
+            Err(PaperErr::Crypto(CryptoErr::MacMismatch))
+        ));
+
+        // a misread character and a swapped address are caught without the passphrase
+        let typo: String = code
+            .chars()
+            .enumerate()
+            .map(|(i, c)| match (i, c) {
+                (10, '2') => '3',
+                (10, _) => '2',
+                _ => c,
+            })
+            .collect();
+        assert!(matches!(
+            decrypt(&typo, b"pass"),
+            Err(PaperErr::BadChecksum)
+        ));
+        let other = paper
+            .to_text()
+            .replace(address, "0x0000000000000000000000000000000000000000");
+        assert!(matches!(
+            PaperKey::parse(&other),
+            Err(PaperErr::AddressMismatch)
+        ));
+    }
+}
@@ -0,0 +1,529 @@
+//! What each key may be used for beyond the defaults. Policies come from the `policies`
+//! map of the config, which is compiled into the binary unless a path is given, so they
+//! can't be loosened by editing files next to the keys. Requests outside a key's policy are
+//! refused before anyone is asked to approve them.
+use crate::evm::{calldata, quantity, Address, FixedBytes, Known, Transaction};
+use crate::manifest::{self, Change, ManifestErr};
+use crate::store::{KeyStore, StoreErr};
+use err_mac::create_err_with_impls;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+/// the window `max_value_per_day` applies to
+pub const DAY_SECS: u64 = 24 * 60 * 60;
+/// where the spend ledger is kept in the store, not a valid key name
+pub const LEDGER_NAME: &str = ".spending";
+const LEDGER_MAC_INFO: &[u8] = b"hot_cheese spending v1";
+
+pub type Selector = FixedBytes<4>;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub PolicyErr,
+    RawHashNotAllowed,
+    TokenNotAllowed(Address),
+    ForeignReceiver,
+    DelegateCall,
+    ChainNotAllowed(u64),
+    UnknownChain,
+    DestinationNotAllowed(Option<Address>),
+    SelectorNotAllowed(Selector),
+    ShortCalldata,
+    ValueTooHigh,
+    OverDailyLimit,
+    GasPriceTooHigh,
+    RelayAuthNotAllowed,
+    BadLedgerMac,
+    Store(StoreErr),
+    Manifest(ManifestErr),
+    Serde(serde_json::Error)
+    ;
+);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KeyPolicy {
+    /// sign bare 32 byte digests. off by default, a digest can be anything including a
+    /// transaction, and the approval prompt can't say what
+    #[serde(default)]
+    pub allow_raw_hash: bool,
+    /// sign Flashbots and other MEV relay request bodies, without asking. the signature
+    /// only vouches for who sent a request, meant for a reputation key that holds nothing
+    #[serde(default)]
+    pub allow_relay_auth: bool,
+    /// the only tokens CoW orders may sell or buy, any when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<Vec<Address>>,
+    /// CoW orders may pay out to someone other than the signer
+    #[serde(default)]
+    pub allow_foreign_receiver: bool,
+    /// Safe transactions may DELEGATECALL, which runs arbitrary code as the Safe
+    #[serde(default)]
+    pub allow_delegatecall: bool,
+    /// the chains transactions and typed data may be signed for, any when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_ids: Option<Vec<u64>>,
+    /// the only addresses transactions may call or pay, no contract creation when set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<Vec<Address>>,
+    /// the only functions transactions may call. plain transfers call none and are allowed,
+    /// data too short for a selector is not
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selectors: Option<Vec<Selector>>,
+    /// wei per transaction
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_value: Option<u128>,
+    /// wei over any 24 hours, counted from the spend ledger kept in the store
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_value_per_day: Option<u128>,
+    /// `gasPrice` or `maxFeePerGas` in wei
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_gas_price: Option<u128>,
+    /// sign transactions that pass this policy without asking
+    #[serde(default)]
+    pub auto_approve: bool,
+}
+
+impl KeyPolicy {
+    pub fn check_raw_hash(&self) -> Result<(), PolicyErr> {
+        match self.allow_raw_hash {
+            true => Ok(()),
+            false => Err(PolicyErr::RawHashNotAllowed),
+        }
+    }
+    pub fn check_relay_auth(&self) -> Result<(), PolicyErr> {
+        match self.allow_relay_auth {
+            true => Ok(()),
+            false => Err(PolicyErr::RelayAuthNotAllowed),
+        }
+    }
+    pub fn check_chain(&self, chain_id: u64) -> Result<(), PolicyErr> {
+        match self.chain_ids.as_ref() {
+            Some(chain_ids) if !chain_ids.contains(&chain_id) => {
+                Err(PolicyErr::ChainNotAllowed(chain_id))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// the chain of an EIP-712 domain, which may be missing or unreadable. refused then
+    /// when chains are restricted
+    pub fn check_domain_chain(&self, chain_id: Option<u64>) -> Result<(), PolicyErr> {
+        match chain_id {
+            Some(chain_id) => self.check_chain(chain_id),
+            None if self.chain_ids.is_some() => Err(PolicyErr::UnknownChain),
+            None => Ok(()),
+        }
+    }
+    /// `value` in wei, `None` when it doesn't even fit 128 bits. a `to` of `None` creates a
+    /// contract
+    fn check_call(
+        &self,
+        to: Option<Address>,
+        value: Option<u128>,
+        data: &[u8],
+    ) -> Result<(), PolicyErr> {
+        if let Some(allowed) = self.to.as_ref() {
+            if !to.is_some_and(|to| allowed.contains(&to)) {
+                return Err(PolicyErr::DestinationNotAllowed(to));
+            }
+        }
+        if let Some(allowed) = self.selectors.as_ref() {
+            match calldata::selector(data) {
+                Some(selector) if !allowed.contains(&FixedBytes(selector)) => {
+                    return Err(PolicyErr::SelectorNotAllowed(FixedBytes(selector)));
+                }
+                // too short for a selector, ends up in the fallback function
+                None if !data.is_empty() => return Err(PolicyErr::ShortCalldata),
+                _ => {}
+            }
+        }
+        if let Some(max) = self.max_value {
+            if value.is_none_or(|value| value > max) {
+                return Err(PolicyErr::ValueTooHigh);
+            }
+        }
+        Ok(())
+    }
+    /// everything but the daily limit, which needs the [`SpendLedger`]
+    pub fn check_transaction(&self, tx: &Transaction) -> Result<(), PolicyErr> {
+        self.check_chain(tx.chain_id.unwrap_or_default())?;
+        self.check_call(tx.to, Some(tx.value), &tx.data)?;
+        match self.max_gas_price {
+            Some(max) if tx.max_fee_per_gas() > max => Err(PolicyErr::GasPriceTooHigh),
+            _ => Ok(()),
+        }
+    }
+    /// `signer` is the address of the key about to sign
+    pub fn check_known(&self, known: &Known, signer: Address) -> Result<(), PolicyErr> {
+        match known {
+            Known::CowOrder(order) => {
+                if let Some(tokens) = self.tokens.as_ref() {
+                    for token in [order.sell_token, order.buy_token] {
+                        if !tokens.contains(&token) {
+                            return Err(PolicyErr::TokenNotAllowed(token));
+                        }
+                    }
+                }
+                if !self.allow_foreign_receiver
+                    && !order.receiver.is_zero()
+                    && order.receiver != signer
+                {
+                    return Err(PolicyErr::ForeignReceiver);
+                }
+            }
+            Known::SafeTx(tx) => {
+                if tx.operation != 0 && !self.allow_delegatecall {
+                    return Err(PolicyErr::DelegateCall);
+                }
+                // the refund is paid out of the Safe, like any other transfer from it
+                if let Some(tokens) = self.tokens.as_ref() {
+                    if !tx.gas_token.is_zero() && !tokens.contains(&tx.gas_token) {
+                        return Err(PolicyErr::TokenNotAllowed(tx.gas_token));
+                    }
+                }
+                let refunds = tx.gas_price.iter().any(|b| *b != 0);
+                if refunds
+                    && !self.allow_foreign_receiver
+                    && !tx.refund_receiver.is_zero()
+                    && tx.refund_receiver != signer
+                {
+                    return Err(PolicyErr::ForeignReceiver);
+                }
+                let value = match tx.value[..16].iter().all(|b| *b == 0) {
+                    true => Some(u128::from_be_bytes(
+                        tx.value[16..].try_into().expect("16 bytes"),
+                    )),
+                    false => None,
+                };
+                self.check_call(Some(tx.to), value, &tx.data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `(unix seconds, wei)` per key
+type Spent = BTreeMap<String, VecDeque<(u64, u128)>>;
+
+#[derive(Deserialize, Serialize)]
+struct LedgerFile {
+    spent: Spent,
+    mac: String,
+}
+
+fn ledger_mac(master: &[u8], spent: &Spent) -> Result<Hmac<Sha256>, PolicyErr> {
+    let mut kdf = Hmac::<Sha256>::new_from_slice(master).expect("hmac takes any key size");
+    kdf.update(LEDGER_MAC_INFO);
+    let key = kdf.finalize().into_bytes();
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("hmac takes any key size");
+    mac.update(&serde_json::to_vec(spent)?);
+    Ok(mac)
+}
+
+/// what `name` signed away in the day before `now`, plus `value`
+fn total(spent: &Spent, name: &str, value: u128, now: u64) -> u128 {
+    spent
+        .get(name)
+        .into_iter()
+        .flatten()
+        .filter(|(at, _)| *at + DAY_SECS > now)
+        .fold(value, |total, (_, v)| total.saturating_add(*v))
+}
+
+/// what each key signed away over the last day. kept in the store, MACed under the master
+/// key and covered by the manifest, so neither a restart nor deleting or rolling back the
+/// file starts the day over
+#[derive(Default)]
+pub struct SpendLedger {
+    /// `None` until read from the store
+    spent: Mutex<Option<Spent>>,
+}
+
+impl SpendLedger {
+    /// the ledger the manifest recorded, empty only while it never had one
+    fn load(store: &dyn KeyStore, master: &[u8]) -> Result<Spent, PolicyErr> {
+        let recorded = manifest::load(store, master)?.and_then(|mut e| e.remove(LEDGER_NAME));
+        let current = manifest::entry(store, LEDGER_NAME)?;
+        if recorded != current {
+            let change = match (recorded, current) {
+                (Some(_), None) => Change::Removed,
+                (None, Some(_)) => Change::Added,
+                _ => Change::Changed,
+            };
+            return Err(ManifestErr::Mismatch(vec![change(LEDGER_NAME.to_string())]).into());
+        }
+        let Some(bytes) = store.get(LEDGER_NAME)? else {
+            return Ok(Spent::new());
+        };
+        let file: LedgerFile = serde_json::from_slice(&bytes)?;
+        let tag = crate::crypto::to_vec(&file.mac).ok_or(PolicyErr::BadLedgerMac)?;
+        ledger_mac(master, &file.spent)?
+            .verify_slice(&tag)
+            .map_err(|_| PolicyErr::BadLedgerMac)?;
+        Ok(file.spent)
+    }
+
+    fn save(store: &dyn KeyStore, master: &[u8], spent: &Spent) -> Result<(), PolicyErr> {
+        let tag = ledger_mac(master, spent)?.finalize().into_bytes();
+        let file = LedgerFile {
+            spent: spent.clone(),
+            mac: tag.iter().map(|b| format!("{:02x}", b)).collect(),
+        };
+        let previous = store.get(LEDGER_NAME)?;
+        store.put(LEDGER_NAME, &serde_json::to_vec(&file)?)?;
+        if let Err(e) = manifest::record(store, master, LEDGER_NAME) {
+            let _ = match previous {
+                Some(previous) => store.put(LEDGER_NAME, &previous),
+                None => store.delete(LEDGER_NAME).map(|_| ()),
+            };
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// refuses `value` for `name` at `now` if it takes the last day over `limit`, without
+    /// recording it
+    pub fn check(
+        &self,
+        store: &dyn KeyStore,
+        master: &[u8],
+        name: &str,
+        value: u128,
+        limit: u128,
+        now: u64,
+    ) -> Result<(), PolicyErr> {
+        let mut guard = self.spent.lock().expect("not poisoned");
+        if guard.is_none() {
+            *guard = Some(Self::load(store, master)?);
+        }
+        let spent = guard.as_ref().expect("loaded above");
+        match total(spent, name, value, now) > limit {
+            true => Err(PolicyErr::OverDailyLimit),
+            false => Ok(()),
+        }
+    }
+
+    /// records `value` for `name` at `now` unless it takes the last day over `limit`. the
+    /// spend is written to the store and the manifest before it counts, a failed write
+    /// refuses it. callers serialize this with other writes to the manifest
+    pub fn spend(
+        &self,
+        store: &dyn KeyStore,
+        master: &[u8],
+        name: &str,
+        value: u128,
+        limit: u128,
+        now: u64,
+    ) -> Result<(), PolicyErr> {
+        let mut guard = self.spent.lock().expect("not poisoned");
+        if guard.is_none() {
+            *guard = Some(Self::load(store, master)?);
+        }
+        let spent = guard.as_mut().expect("loaded above");
+        for entries in spent.values_mut() {
+            while entries.front().is_some_and(|(at, _)| *at + DAY_SECS <= now) {
+                entries.pop_front();
+            }
+        }
+        spent.retain(|_, entries| !entries.is_empty());
+        if total(spent, name, value, now) > limit {
+            return Err(PolicyErr::OverDailyLimit);
+        }
+        spent
+            .entry(name.to_string())
+            .or_default()
+            .push_back((now, value));
+        if let Err(e) = Self::save(store, master, spent) {
+            // forget it again so memory agrees with the store
+            *guard = None;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::MemKeyStore;
+
+    #[test]
+    fn transactions() {
+        let policy: KeyPolicy = serde_json::from_value(serde_json::json!({
+            "chain_ids": [1],
+            "to": ["0x3535353535353535353535353535353535353535"],
+            "selectors": ["0xa9059cbb"],
+            "max_value": "1000",
+            "max_gas_price": 100
+        }))
+        .unwrap();
+        let tx = Transaction {
+            chain_id: Some(1),
+            to: "0x3535353535353535353535353535353535353535".parse().ok(),
+            value: 1000,
+            gas_price: Some(100),
+            ..Default::default()
+        };
+        policy.check_transaction(&tx).unwrap();
+        let transfer = Transaction {
+            data: vec![0xa9, 0x05, 0x9c, 0xbb],
+            ..tx.clone()
+        };
+        policy.check_transaction(&transfer).unwrap();
+
+        let violations = [
+            (
+                Transaction {
+                    chain_id: Some(5),
+                    ..tx.clone()
+                },
+                "ChainNotAllowed(5)",
+            ),
+            (
+                Transaction {
+                    to: None,
+                    ..tx.clone()
+                },
+                "DestinationNotAllowed(None)",
+            ),
+            (
+                Transaction {
+                    data: vec![0x09, 0x5e, 0xa7, 0xb3],
+                    ..tx.clone()
+                },
+                "SelectorNotAllowed(FixedBytes([9, 94, 167, 179]))",
+            ),
+            (
+                Transaction {
+                    data: vec![0xa9, 0x05, 0x9c],
+                    ..tx.clone()
+                },
+                "ShortCalldata",
+            ),
+            (
+                Transaction {
+                    value: 1001,
+                    ..tx.clone()
+                },
+                "ValueTooHigh",
+            ),
+            (
+                Transaction {
+                    gas_price: Some(101),
+                    ..tx.clone()
+                },
+                "GasPriceTooHigh",
+            ),
+        ];
+        for (tx, err) in violations {
+            assert_eq!(
+                format!("{:?}", policy.check_transaction(&tx).unwrap_err()),
+                err
+            );
+        }
+        // no policy, no limits
+        KeyPolicy::default()
+            .check_transaction(&Transaction {
+                value: u128::MAX,
+                ..tx
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn domain_chain() {
+        let policy = KeyPolicy {
+            chain_ids: Some(vec![1]),
+            ..Default::default()
+        };
+        policy.check_domain_chain(Some(1)).unwrap();
+        assert!(matches!(
+            policy.check_domain_chain(Some(5)),
+            Err(PolicyErr::ChainNotAllowed(5))
+        ));
+        assert!(matches!(
+            policy.check_domain_chain(None),
+            Err(PolicyErr::UnknownChain)
+        ));
+        KeyPolicy::default().check_domain_chain(None).unwrap();
+    }
+
+    #[test]
+    fn rolling_day() {
+        let store = MemKeyStore::default();
+        let master = b"master";
+        let ledger = SpendLedger::default();
+        let spend = |ledger: &SpendLedger, name, value, now| {
+            ledger.spend(&store, master, name, value, 100, now)
+        };
+        spend(&ledger, "A", 60, 1_000).unwrap();
+        let first = store.get(LEDGER_NAME).unwrap().unwrap();
+        assert!(matches!(
+            spend(&ledger, "A", 50, 2_000),
+            Err(PolicyErr::OverDailyLimit)
+        ));
+        // other keys have their own budget
+        spend(&ledger, "B", 100, 2_000).unwrap();
+        spend(&ledger, "A", 40, 3_000).unwrap();
+        // the first spend falls out of the window a day later
+        assert!(spend(&ledger, "A", 60, 1_000 + DAY_SECS - 1).is_err());
+        spend(&ledger, "A", 60, 1_000 + DAY_SECS).unwrap();
+
+        // a restarted server picks up where the last one stopped
+        let restarted = SpendLedger::default();
+        assert!(matches!(
+            spend(&restarted, "A", 1, 2_000 + DAY_SECS),
+            Err(PolicyErr::OverDailyLimit)
+        ));
+        assert!(matches!(
+            restarted.check(&store, master, "A", 1, 100, 2_000 + DAY_SECS),
+            Err(PolicyErr::OverDailyLimit)
+        ));
+        restarted
+            .check(&store, master, "A", 1, 100, 3_000 + DAY_SECS)
+            .unwrap();
+
+        // deleted or rolled back, the ledger no longer matches the manifest
+        let current = store.get(LEDGER_NAME).unwrap().unwrap();
+        let mismatch = |change: Change| {
+            let res = spend(&SpendLedger::default(), "A", 1, 2_000 + DAY_SECS);
+            match res {
+                Err(PolicyErr::Manifest(ManifestErr::Mismatch(changes))) => {
+                    assert_eq!(changes, vec![change])
+                }
+                other => panic!("{:?}", other),
+            }
+        };
+        store.delete(LEDGER_NAME).unwrap();
+        mismatch(Change::Removed(LEDGER_NAME.into()));
+        store.put(LEDGER_NAME, &first).unwrap();
+        mismatch(Change::Changed(LEDGER_NAME.into()));
+        store.put(LEDGER_NAME, &current).unwrap();
+
+        // and a ledger edited behind its back is refused even once the manifest trusts it
+        let mut file: serde_json::Value = serde_json::from_slice(&current).unwrap();
+        file["spent"]["A"] = serde_json::json!([]);
+        store
+            .put(LEDGER_NAME, &serde_json::to_vec(&file).unwrap())
+            .unwrap();
+        manifest::rebuild(&store, master).unwrap();
+        assert!(matches!(
+            spend(&SpendLedger::default(), "A", 1, 2_000 + DAY_SECS),
+            Err(PolicyErr::BadLedgerMac)
+        ));
+    }
+}
@@ -0,0 +1,611 @@
+//! an Ethereum JSON-RPC endpoint in front of a node, for dapps and scripts that expect the
+//! node to hold the keys. account and signing methods are answered here with the stored
+//! keys, transactions go out as `eth_sendRawTransaction`, everything else is passed through
+use crate::clef::{self, invalid, param, response, RpcErr, DENIED, PARSE_ERROR};
+use crate::crypto::to_vec;
+use crate::evm::{to_hex, Address, TypedData};
+use crate::secure_fs::write_atomic;
+use crate::server::{BackendImpl, HotApi};
+use err_mac::create_err_with_impls;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST, ORIGIN,
+};
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub ProxyErr,
+    Upstream(String),
+    Serde(serde_json::Error),
+    IO(std::io::Error)
+    ;
+);
+
+/// EIP-1193 `unauthorized`, an account the proxy doesn't sign for
+const UNAUTHORIZED: i64 = 4100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProxyConfig {
+    /// the node everything else goes to, `http(s)://`
+    pub upstream: String,
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    /// what callers send as `Authorization: Bearer <token>`, when unset a fresh one is
+    /// written to [`TOKEN_FILE`] in the store at startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// the keys `eth_accounts` offers and the proxy signs with, by name
+    pub keys: Vec<String>,
+}
+
+/// owner-only, dot prefixed so it is never taken for a key
+pub const TOKEN_FILE: &str = ".proxy-token";
+
+fn default_listen() -> String {
+    "127.0.0.1:8545".into()
+}
+
+/// the proxy speaks plain http, so it only listens where nothing else can connect
+fn check_listen(listen: &str) -> Result<(), String> {
+    let addrs: Vec<_> = listen
+        .to_socket_addrs()
+        .map_err(|e| format!("bad listen address '{}': {}", listen, e))?
+        .collect();
+    if addrs.is_empty() || addrs.iter().any(|a| !a.ip().is_loopback()) {
+        return Err(format!(
+            "refusing to listen on '{}', the proxy has no tls and only listens on loopback",
+            listen
+        ));
+    }
+    Ok(())
+}
+
+/// who may talk to the proxy: callers holding the token, never a web page, which could
+/// only reach it through the user's browser or DNS rebinding
+struct Gate {
+    token: String,
+    /// the `Host`s the proxy answers to, its own address
+    hosts: Vec<String>,
+}
+
+impl Gate {
+    fn check(&self, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+        let header = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+        if headers.contains_key(ORIGIN) {
+            return Err((StatusCode::FORBIDDEN, "browser requests are not allowed"));
+        }
+        let host = header(HOST).unwrap_or_default().to_ascii_lowercase();
+        if !self.hosts.contains(&host) {
+            return Err((StatusCode::FORBIDDEN, "unexpected Host"));
+        }
+        if !header(CONTENT_TYPE).is_some_and(|t| t.starts_with("application/json")) {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected application/json",
+            ));
+        }
+        let token = header(AUTHORIZATION)
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        // compared in full so the time taken says nothing about the token
+        let matches = token.len() == self.token.len()
+            && token
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0;
+        if !matches {
+            return Err((StatusCode::UNAUTHORIZED, "missing or wrong bearer token"));
+        }
+        Ok(())
+    }
+}
+
+fn json_response(status: StatusCode, res: &Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(
+        serde_json::to_vec(res).expect("json value"),
+    )));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// where requests the proxy doesn't answer itself go
+pub trait Upstream: Send + Sync {
+    /// one JSON-RPC request in, the node's response out
+    fn call(&self, req: &Value) -> Result<Value, ProxyErr>;
+}
+
+pub struct HttpUpstream {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpUpstream {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            agent: ureq::builder().build(),
+        }
+    }
+}
+
+impl Upstream for HttpUpstream {
+    fn call(&self, req: &Value) -> Result<Value, ProxyErr> {
+        let body = serde_json::to_vec(req)?;
+        let res = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_bytes(&body);
+        // nodes answer JSON-RPC errors with 4xx/5xx too, the body still says what happened
+        let res = match res {
+            Ok(res) | Err(ureq::Error::Status(_, res)) => res,
+            Err(e) => return Err(ProxyErr::Upstream(e.to_string())),
+        };
+        let text = res.into_string()?;
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+pub struct Proxy {
+    hot: Arc<HotApi>,
+    upstream: Box<dyn Upstream>,
+    keys: Vec<String>,
+}
+
+impl Proxy {
+    pub fn new(hot: Arc<HotApi>, upstream: Box<dyn Upstream>, keys: Vec<String>) -> Self {
+        Self {
+            hot,
+            upstream,
+            keys,
+        }
+    }
+
+    /// the allowed keys that have an address, in the order they are configured
+    fn accounts(&self) -> Result<Vec<(String, Address)>, RpcErr> {
+        let accounts = self.hot.evm_accounts()?;
+        Ok(self
+            .keys
+            .iter()
+            .filter_map(|key| accounts.iter().find(|(name, _)| name == key).cloned())
+            .collect())
+    }
+
+    fn key_for(&self, address: &Address) -> Result<String, RpcErr> {
+        self.accounts()?
+            .into_iter()
+            .find(|(_, a)| a == address)
+            .map(|(name, _)| name)
+            .ok_or(RpcErr(
+                UNAUTHORIZED,
+                format!("{} is not an account of this proxy", address),
+            ))
+    }
+
+    /// `method` on the upstream node, its result or its error
+    fn node(&self, method: &str, params: Value) -> Result<Value, RpcErr> {
+        let req = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        let res = self
+            .upstream
+            .call(&req)
+            .map_err(|e| RpcErr(DENIED, e.to_string()))?;
+        if let Some(err) = res.get("error") {
+            let code = err.get("code").and_then(|c| c.as_i64()).unwrap_or(DENIED);
+            let message = err.get("message").and_then(|m| m.as_str()).unwrap_or("");
+            return Err(RpcErr(code, format!("{}: {}", method, message)));
+        }
+        Ok(res.get("result").cloned().unwrap_or(Value::Null))
+    }
+
+    /// fills what wallets usually leave to the node, signs, and sends the raw transaction
+    fn send_transaction(&self, args: &Value) -> Result<Value, RpcErr> {
+        let mut args: Map<String, Value> = args
+            .as_object()
+            .ok_or(invalid("expected a transaction"))?
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let from = Address::deserialize(args.get("from").ok_or(invalid("missing from"))?)
+            .map_err(invalid)?;
+        let name = self.key_for(&from)?;
+        if !args.contains_key("chainId") {
+            args.insert("chainId".into(), self.node("eth_chainId", json!([]))?);
+        }
+        if !args.contains_key("nonce") {
+            let nonce = self.node("eth_getTransactionCount", json!([from, "pending"]))?;
+            args.insert("nonce".into(), nonce);
+        }
+        if !args.contains_key("gas") && !args.contains_key("gasLimit") {
+            let gas = self.node("eth_estimateGas", json!([Value::Object(args.clone())]))?;
+            args.insert("gas".into(), gas);
+        }
+        if !args.contains_key("gasPrice") && !args.contains_key("maxFeePerGas") {
+            args.insert("gasPrice".into(), self.node("eth_gasPrice", json!([]))?);
+        }
+        let tx = clef::transaction(&Value::Object(args))?;
+        let signed = self.hot.sign_transaction(&name, &tx)?;
+        self.node("eth_sendRawTransaction", json!([to_hex(&signed.raw)]))
+    }
+
+    /// `personal_sign(data, address)`, data is hex or, from some wallets, plain text
+    fn personal_sign(&self, params: &[Value]) -> Result<Value, RpcErr> {
+        let data = param(params, 0)?.as_str().ok_or(invalid("expected data"))?;
+        let message = match data.starts_with("0x") {
+            true => to_vec(data).ok_or(invalid("expected hex data"))?,
+            false => data.as_bytes().to_vec(),
+        };
+        let name = self.key_for(&clef::address(params, 1)?)?;
+        let signed = self.hot.personal_sign(&name, &message)?;
+        Ok(json!(to_hex(&signed.signature)))
+    }
+
+    /// `eth_signTypedData_v4(address, typedData)`, typed data as a JSON string or object
+    fn sign_typed_data(&self, params: &[Value]) -> Result<Value, RpcErr> {
+        let name = self.key_for(&clef::address(params, 0)?)?;
+        let typed_data = match param(params, 1)? {
+            Value::String(s) => serde_json::from_str::<TypedData>(s),
+            other => TypedData::deserialize(other),
+        }
+        .map_err(invalid)?;
+        let signed = self.hot.sign_typed_data(&name, &typed_data)?;
+        Ok(json!(to_hex(&signed.signature)))
+    }
+
+    fn handle_one(&self, req: &Value) -> Value {
+        let id = req.get("id").cloned().unwrap_or(Value::Null);
+        let method = req.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        let params = match req.get("params") {
+            Some(Value::Array(params)) => params.as_slice(),
+            _ => &[],
+        };
+        let res = match method {
+            "eth_accounts" | "eth_requestAccounts" => self
+                .accounts()
+                .map(|accounts| json!(accounts.iter().map(|(_, a)| a).collect::<Vec<_>>())),
+            "eth_sendTransaction" => param(params, 0).and_then(|tx| self.send_transaction(tx)),
+            "personal_sign" => self.personal_sign(params),
+            "eth_signTypedData_v4" => self.sign_typed_data(params),
+            _ => {
+                return self
+                    .upstream
+                    .call(req)
+                    .unwrap_or_else(|e| response(id, Err(RpcErr(DENIED, e.to_string()))))
+            }
+        };
+        if let Err(RpcErr(_, message)) = res.as_ref() {
+            self.hot.backend().communicate_err(message.clone());
+        }
+        response(id, res)
+    }
+
+    /// a request or a batch of them
+    pub fn handle(&self, body: &[u8]) -> Value {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Array(batch)) => {
+                Value::Array(batch.iter().map(|req| self.handle_one(req)).collect())
+            }
+            Ok(req) => self.handle_one(&req),
+            Err(e) => response(Value::Null, Err(RpcErr(PARSE_ERROR, e.to_string()))),
+        }
+    }
+}
+
+/// serves the proxy over plain http on `conf.listen`, which must be loopback, until it
+/// fails. requests need the bearer token and each signature still needs approval
+#[tokio::main]
+pub async fn run_proxy(
+    backend: Box<dyn BackendImpl>,
+    conf: ProxyConfig,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    check_listen(&conf.listen)?;
+    let token_file = backend.store_path().join(TOKEN_FILE);
+    let api = Arc::new(HotApi::new(backend));
+    // refuse to serve from a store that was tampered with
+    if let Err(e) = api.verify_manifest() {
+        return Err(format!("store integrity check failed: {}", e).into());
+    }
+    let proxy = Arc::new(Proxy::new(
+        api,
+        Box::new(HttpUpstream::new(&conf.upstream)),
+        conf.keys,
+    ));
+    let incoming = TcpListener::bind(&conf.listen).await?;
+    let local = incoming.local_addr()?;
+    println!("Proxying http://{} to {}", local, conf.upstream);
+    let token = match conf.token {
+        Some(token) => token,
+        None => {
+            let mut bytes = [0u8; 32];
+            OsRng.fill_bytes(&mut bytes);
+            let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            write_atomic(&token_file, token.as_bytes())?;
+            println!("Bearer token for this session in {}", token_file.display());
+            token
+        }
+    };
+    let gate = Arc::new(Gate {
+        token,
+        hosts: vec![
+            conf.listen.to_ascii_lowercase(),
+            local.to_string(),
+            format!("localhost:{}", local.port()),
+        ],
+    });
+
+    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+        let proxy = proxy.clone();
+        let gate = gate.clone();
+        async move {
+            if let Err((status, message)) = gate.check(req.headers()) {
+                let res = response(Value::Null, Err(RpcErr(UNAUTHORIZED, message.into())));
+                return Ok(json_response(status, &res));
+            }
+            let body = req.into_body().collect().await?.to_bytes();
+            // signing waits on the approval prompt and the node, keep it off the runtime
+            let res = tokio::task::spawn_blocking(move || proxy.handle(&body))
+                .await
+                .unwrap_or_else(|e| response(Value::Null, Err(RpcErr(DENIED, e.to_string()))));
+            Ok::<_, hyper::Error>(json_response(StatusCode::OK, &res))
+        }
+    });
+
+    loop {
+        let (tcp_stream, _remote_addr) = incoming.accept().await?;
+        let service = service.clone();
+        tokio::spawn(async move {
+            if let Err(err) = Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(tcp_stream), service)
+                .await
+            {
+                eprintln!("failed to serve connection: {err:#}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::evm::Transaction;
+    use crate::server::test::TestBackend;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::Mutex;
+
+    /// answers like a node on chain 1 and remembers what it was asked
+    #[derive(Clone, Default)]
+    struct FakeNode {
+        calls: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl Upstream for FakeNode {
+        fn call(&self, req: &Value) -> Result<Value, ProxyErr> {
+            self.calls.lock().unwrap().push(req.clone());
+            let result = match req["method"].as_str().unwrap() {
+                "eth_chainId" => json!("0x1"),
+                "eth_getTransactionCount" => json!("0x3"),
+                "eth_estimateGas" => json!("0x5208"),
+                "eth_gasPrice" => json!("0x3b9aca00"),
+                "eth_sendRawTransaction" => json!("0xfeed"),
+                "eth_blockNumber" => json!("0x10"),
+                _ => {
+                    return Ok(json!({
+                        "jsonrpc": "2.0",
+                        "id": req["id"],
+                        "error": {"code": -32601, "message": "method not found"}
+                    }))
+                }
+            };
+            Ok(json!({"jsonrpc": "2.0", "id": req["id"], "result": result}))
+        }
+    }
+
+    fn rpc(proxy: &Proxy, method: &str, params: Value) -> Value {
+        let req = json!({"jsonrpc": "2.0", "id": 9, "method": method, "params": params});
+        proxy.handle(&serde_json::to_vec(&req).unwrap())
+    }
+
+    #[test]
+    fn signs_locally_and_forwards() {
+        let hot = Arc::new(HotApi::new(Box::<TestBackend>::default()));
+        hot.generate("A").unwrap();
+        hot.generate("HIDDEN").unwrap();
+        let node = FakeNode::default();
+        let proxy = Proxy::new(hot.clone(), Box::new(node.clone()), vec!["A".into()]);
+        let (_, address) = hot
+            .evm_accounts()
+            .unwrap()
+            .into_iter()
+            .find(|(name, _)| name == "A")
+            .unwrap();
+
+        assert_eq!(
+            rpc(&proxy, "eth_accounts", json!([]))["result"],
+            json!([address])
+        );
+        // passed through untouched, errors included
+        assert_eq!(rpc(&proxy, "eth_blockNumber", json!([]))["result"], "0x10");
+        assert_eq!(rpc(&proxy, "debug_x", json!([]))["error"]["code"], -32601);
+
+        let to = "0x3535353535353535353535353535353535353535";
+        let res = rpc(
+            &proxy,
+            "eth_sendTransaction",
+            json!([{"from": address, "to": to, "value": "0x1", "data": null}]),
+        );
+        assert_eq!(res["result"], "0xfeed");
+        let calls = node.calls.lock().unwrap().clone();
+        let methods: Vec<&str> = calls
+            .iter()
+            .map(|c| c["method"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            &methods[2..],
+            [
+                "eth_chainId",
+                "eth_getTransactionCount",
+                "eth_estimateGas",
+                "eth_gasPrice",
+                "eth_sendRawTransaction"
+            ]
+        );
+        let tx: Transaction = serde_json::from_value(json!({
+            "chainId": 1,
+            "nonce": 3,
+            "gas": 21000,
+            "gasPrice": 1_000_000_000,
+            "to": to,
+            "value": 1
+        }))
+        .unwrap();
+        let signed = hot.sign_transaction("A", &tx).unwrap();
+        assert_eq!(
+            calls.last().unwrap()["params"],
+            json!([to_hex(&signed.raw)])
+        );
+
+        let res = rpc(
+            &proxy,
+            "personal_sign",
+            json!(["0x48656c6c6f20576f726c64", address]),
+        );
+        let signed = hot.personal_sign("A", b"Hello World").unwrap();
+        assert_eq!(res["result"], to_hex(&signed.signature));
+        let typed_data = crate::evm::known::test::safe_tx(0);
+        let res = rpc(
+            &proxy,
+            "eth_signTypedData_v4",
+            json!([address, serde_json::to_string(&typed_data).unwrap()]),
+        );
+        let signed = hot.sign_typed_data("A", &typed_data).unwrap();
+        assert_eq!(res["result"], to_hex(&signed.signature));
+
+        // keys that aren't configured are neither offered nor used
+        let (_, hidden) = hot
+            .evm_accounts()
+            .unwrap()
+            .into_iter()
+            .find(|(name, _)| name == "HIDDEN")
+            .unwrap();
+        let res = rpc(&proxy, "personal_sign", json!(["0x00", hidden]));
+        assert_eq!(res["error"]["code"], UNAUTHORIZED);
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "id": 1, "method": "eth_chainId"},
+            {"jsonrpc": "2.0", "id": 2, "method": "eth_accounts"}
+        ]);
+        let res = proxy.handle(&serde_json::to_vec(&batch).unwrap());
+        assert_eq!(res[0]["result"], "0x1");
+        assert_eq!(res[1]["id"], 2);
+        assert_eq!(proxy.handle(b"nope")["error"]["code"], PARSE_ERROR);
+    }
+
+    #[test]
+    fn gate() {
+        let gate = Gate {
+            token: "s3cret".into(),
+            hosts: vec!["127.0.0.1:8545".into(), "localhost:8545".into()],
+        };
+        let headers = |pairs: &[(HeaderName, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+            }
+            headers
+        };
+        let ok = [
+            (HOST, "127.0.0.1:8545"),
+            (CONTENT_TYPE, "application/json"),
+            (AUTHORIZATION, "Bearer s3cret"),
+        ];
+        assert!(gate.check(&headers(&ok)).is_ok());
+        let mut localhost = ok.clone();
+        localhost[0] = (HOST, "LOCALHOST:8545");
+        assert!(gate.check(&headers(&localhost)).is_ok());
+
+        let refused = |pairs: &[(HeaderName, &str)]| gate.check(&headers(pairs)).unwrap_err().0;
+        assert_eq!(refused(&ok[1..]), StatusCode::FORBIDDEN);
+        // dns rebinding, the page's host name pointed at us
+        let mut rebound = ok.clone();
+        rebound[0] = (HOST, "evil.example:8545");
+        assert_eq!(refused(&rebound), StatusCode::FORBIDDEN);
+        let mut browser = ok.to_vec();
+        browser.push((ORIGIN, "https://evil.example"));
+        assert_eq!(refused(&browser), StatusCode::FORBIDDEN);
+        let mut form = ok.clone();
+        form[1] = (CONTENT_TYPE, "text/plain");
+        assert_eq!(refused(&form), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(refused(&ok[..2]), StatusCode::UNAUTHORIZED);
+        let mut wrong = ok.clone();
+        wrong[2] = (AUTHORIZATION, "Bearer s3crex");
+        assert_eq!(refused(&wrong), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn loopback_only() {
+        assert!(check_listen("127.0.0.1:8545").is_ok());
+        assert!(check_listen("[::1]:8545").is_ok());
+        assert!(check_listen("0.0.0.0:8545").is_err());
+        assert!(check_listen("192.168.1.2:8545").is_err());
+        assert!(check_listen("nonsense").is_err());
+    }
+
+    #[test]
+    fn http_upstream() {
+        // a stand-in node that answers one request
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let node = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(v) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    len = v.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            let req: Value = serde_json::from_slice(&body).unwrap();
+            let res = json!({"jsonrpc": "2.0", "id": req["id"], "result": req["method"]});
+            let res = serde_json::to_string(&res).unwrap();
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                res.len(),
+                res
+            )
+            .unwrap();
+        });
+        let upstream = HttpUpstream::new(&url);
+        let res = upstream
+            .call(&json!({"jsonrpc": "2.0", "id": 4, "method": "eth_blockNumber"}))
+            .unwrap();
+        assert_eq!(
+            res,
+            json!({"jsonrpc": "2.0", "id": 4, "result": "eth_blockNumber"})
+        );
+        node.join().unwrap();
+    }
+}
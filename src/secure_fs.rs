@@ -0,0 +1,123 @@
+//! Everything in the store is written owner-only and atomically: temp file in the same
+//! directory, fsync, rename, fsync of the directory. A crash leaves either the old or the
+//! new file, never a truncated one.
+use std::fs::{read_dir, remove_file, rename, DirBuilder, File, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+pub const FILE_MODE: u32 = 0o600;
+pub const DIR_MODE: u32 = 0o700;
+
+/// replaces `path` with `contents`, the file ends up 0600 whatever the umask
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or(io::Error::other(format!(
+            "no file name in {}",
+            path.display()
+        )))?;
+    // dot prefixed, never taken for a key
+    let tmp = dir.join(format!(".{}.{:08x}.tmp", name, rand::random::<u32>()));
+    let res = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(FILE_MODE)
+            .open(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        rename(&tmp, path)?;
+        File::open(dir)?.sync_all()
+    })();
+    if res.is_err() {
+        let _ = remove_file(&tmp);
+    }
+    res
+}
+
+/// creates the store (and missing parents) as 0700
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(DIR_MODE)
+        .create(path)
+}
+
+/// anything in the store readable by group or others, tightened when `fix` is set.
+/// returns one line per offending path
+pub fn check_permissions(store: &Path, fix: bool) -> io::Result<Vec<String>> {
+    let mut findings = Vec::new();
+    let mut check = |path: &Path, want: u32| -> io::Result<()> {
+        let mode = path.metadata()?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            let action = if fix {
+                std::fs::set_permissions(path, Permissions::from_mode(want))?;
+                "fixed"
+            } else {
+                "expected"
+            };
+            findings.push(format!(
+                "{} is {:o}, {} {:o}",
+                path.display(),
+                mode,
+                action,
+                want
+            ));
+        }
+        Ok(())
+    };
+    check(store, DIR_MODE)?;
+    for entry in read_dir(store)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            check(&entry.path(), FILE_MODE)?;
+        }
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::fs::{read, remove_dir_all, set_permissions, write};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// a path under the temp dir no other test, or concurrent run, uses
+    pub(crate) fn unique_dir(label: &str) -> PathBuf {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        std::env::temp_dir().join(format!(
+            "hot_cheese_{}_{}_{}",
+            label,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ))
+    }
+
+    #[test]
+    fn writes_private_files() {
+        let dir = unique_dir("secure_fs_test");
+        create_private_dir(&dir).unwrap();
+        let path = dir.join("A");
+
+        write_atomic(&path, b"one").unwrap();
+        write_atomic(&path, b"two").unwrap();
+        assert_eq!(read(&path).unwrap(), b"two");
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+        // no temp files left behind
+        assert_eq!(read_dir(&dir).unwrap().count(), 1);
+        assert!(check_permissions(&dir, false).unwrap().is_empty());
+
+        write(dir.join("B"), b"loose").unwrap();
+        set_permissions(dir.join("B"), Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(check_permissions(&dir, true).unwrap().len(), 1);
+        assert!(check_permissions(&dir, false).unwrap().is_empty());
+        remove_dir_all(&dir).unwrap();
+    }
+}
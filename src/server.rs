@@ -1,4 +1,20 @@
-use crate::crypto::{decrypt_key, encrypt_key, keccak256, random_pk, CryptoErr};
+use crate::backup::{self, BackupErr, BackupReport, Blob, Contents};
+use crate::clef;
+use crate::crypto::{
+    encrypt_keystore, keccak256, now_secs, random_pk, CryptoErr, KdfConfig, KeyMeta, KeyType,
+    Keystore,
+};
+use crate::evm::{
+    self, Address, EthSignature, EvmErr, Known, SignedTransaction, Transaction, TypedData,
+    Verification, VerifyRequest, B256,
+};
+use crate::manifest::{self, ManifestErr};
+use crate::paper::{self, PaperErr, PaperKey};
+use crate::policy::{KeyPolicy, PolicyErr, SpendLedger};
+use crate::secure_fs::create_private_dir;
+use crate::slip39::{self, Slip39Err};
+use crate::solana::{SolanaErr, SolanaPayload, SolanaSignature};
+use crate::store::{AuditEntry, DirKeyStore, KeyStore, MemKeyStore, StoreErr};
 use df_share::error::Unspecified;
 use df_share::{to_hex_str, ClientReq, EphemeralServer};
 use err_mac::create_err_with_impls;
@@ -10,12 +26,12 @@ use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
 use pki_types::{CertificateDer, PrivateKeyDer};
 use rustls::ServerConfig;
-use std::fs::create_dir_all;
+use std::collections::BTreeMap;
 use std::io;
 use std::io::{BufReader, Cursor};
 use std::net::{Ipv4Addr, SocketAddr};
-use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
 use zeroize::Zeroize;
@@ -29,12 +45,12 @@ pub fn resolve_path(path: &str) -> PathBuf {
     PathBuf::from(path) // Fallback: return the path as-is
 }
 
-fn load_certs() -> io::Result<Vec<CertificateDer<'static>>> {
+pub(crate) fn load_certs() -> io::Result<Vec<CertificateDer<'static>>> {
     let cert = include_bytes!("conf/ssl-cert.pem");
     let mut reader = BufReader::new(Cursor::new(cert));
     rustls_pemfile::certs(&mut reader).collect()
 }
-fn load_private_key() -> io::Result<PrivateKeyDer<'static>> {
+pub(crate) fn load_private_key() -> io::Result<PrivateKeyDer<'static>> {
     let key = include_bytes!("conf/ssl-key.pem");
     let mut reader = BufReader::new(Cursor::new(key));
     rustls_pemfile::private_key(&mut reader).map(|key| key.unwrap())
@@ -65,7 +81,14 @@ pub async fn run_server(
     server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
     let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
 
-    let api = Arc::new(HotApi { inner: backend });
+    let api = Arc::new(HotApi::new(backend));
+    for finding in api.check_permissions(false)? {
+        eprintln!("WARNING {}, run `hot_cheese doctor --fix`", finding);
+    }
+    // refuse to serve from a store that was tampered with
+    if let Err(e) = api.verify_manifest() {
+        return Err(format!("store integrity check failed: {}", e).into());
+    }
 
     let wrapped = move |mut req: Request<_>| {
         let inner = api.clone();
@@ -99,7 +122,7 @@ pub async fn run_server(
     }
 }
 
-fn is_valid_string_name(name: &str) -> bool {
+pub(crate) fn is_valid_string_name(name: &str) -> bool {
     // Check that all characters in the name are valid (a-z, A-Z, _)
     name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
@@ -115,20 +138,60 @@ create_err_with_impls!(
     NotDeviceOwner,
     Unspecified(Unspecified),
     FailedToGetEncryptionKey,
-    Crypto(CryptoErr)
+    FailedToSetEncryptionKey,
+    MasterKeyExists,
+    UnverifiedMasterKey,
+    Crypto(CryptoErr),
+    Manifest(ManifestErr),
+    Store(StoreErr),
+    Backup(BackupErr),
+    Slip39(Slip39Err),
+    Paper(PaperErr),
+    Evm(EvmErr),
+    Policy(PolicyErr),
+    Solana(SolanaErr),
+    IO(std::io::Error)
     ;
 );
 
 pub trait BackendImpl: Send + Sync {
     fn is_device_owner(&self, reason: &str) -> bool;
     fn get_encryption_key(&self) -> Option<Vec<u8>>;
+    /// provision the master key, must not replace an existing one
+    fn set_encryption_key(&self, key: &[u8]) -> bool;
     fn store(&self) -> &str;
     fn communicate_err(&self, e: String);
+    /// one line per request and decision, stdout unless overridden
+    fn trace(&self, line: &str) {
+        println!("{}", line)
+    }
+    /// KDF for newly written keystores
+    fn kdf(&self) -> KdfConfig {
+        KdfConfig::default()
+    }
+    /// where keystores live, one file per key in `store()` unless overridden.
+    /// called once by `HotApi::new`
+    fn key_store(&self) -> Box<dyn KeyStore> {
+        Box::new(DirKeyStore::new(self.store_path()))
+    }
+    /// what `name` may be used for, the defaults unless overridden
+    fn policy(&self, _name: &str) -> KeyPolicy {
+        KeyPolicy::default()
+    }
 
+    fn has_encryption_key(&self) -> bool {
+        match self.get_encryption_key() {
+            Some(mut key) => {
+                key.zeroize();
+                true
+            }
+            None => false,
+        }
+    }
     fn store_path(&self) -> PathBuf {
         let buf = resolve_path(self.store());
         if !buf.exists() {
-            if let Err(e) = create_dir_all(buf.clone()) {
+            if let Err(e) = create_private_dir(&buf) {
                 eprintln!("failed create keys dir {}", e)
             }
         }
@@ -145,6 +208,11 @@ pub trait BackendImpl: Send + Sync {
 
 pub struct HotApi {
     inner: Box<dyn BackendImpl>,
+    store: Box<dyn KeyStore>,
+    /// for `max_value_per_day`
+    spending: SpendLedger,
+    /// one writer at a time, so a keystore and its manifest entry change together
+    writes: Mutex<()>,
 }
 
 fn sk_to_adr(key: &[u8]) -> Result<String, ApiBackendErr> {
@@ -159,88 +227,876 @@ fn sk_to_adr(key: &[u8]) -> Result<String, ApiBackendErr> {
     Ok(to_hex_str(&hash[12..]))
 }
 
+/// derives the public address so it can live in the keystore metadata
+fn key_meta(name: &str, key_type: KeyType, key: &[u8]) -> Result<KeyMeta, ApiBackendErr> {
+    use solana_signer::Signer;
+    let address = match key_type {
+        KeyType::Evm => Some(sk_to_adr(key)?),
+        KeyType::Solana => Some(
+            solana_keypair::Keypair::try_from(key)
+                .map_err(|_| ApiBackendErr::FailReadKeypair)?
+                .pubkey()
+                .to_string(),
+        ),
+        KeyType::Bytes => None,
+    };
+    Ok(KeyMeta {
+        name: name.into(),
+        key_type,
+        address,
+        created_at: now_secs(),
+    })
+}
+
+/// opens a bundle and checks it the way a restore would: its keystores have to match its own
+/// manifest under `password`, and each one has to decrypt under the name it is stored as
+fn check_backup(bundle: &[u8], password: &[u8]) -> Result<(BackupReport, Contents), ApiBackendErr> {
+    let contents = backup::open(password, bundle)?;
+    let staged = MemKeyStore::default();
+    for (name, blob) in contents.store.iter() {
+        staged.put(name, &blob.0)?;
+    }
+    manifest::verify(&staged, password)?;
+    let keys = staged.list()?;
+    for name in keys.iter() {
+        let keystore = Keystore::from_slice(&contents.store[name].0)?;
+        keystore.check_name(name)?;
+        keystore.decrypt(password)?.zeroize();
+    }
+    let report = BackupReport {
+        created_at: backup::created_at(bundle)?,
+        keys,
+        conf: contents.conf.keys().cloned().collect(),
+    };
+    Ok((report, contents))
+}
+
+/// the master key as it is split: its length in two bytes, itself and zeros up to the even
+/// length of at least 16 bytes SLIP-39 takes, so typed passwords of any length can be split
+fn frame_master(master: &[u8]) -> Result<Vec<u8>, ApiBackendErr> {
+    let len = u16::try_from(master.len()).map_err(|_| Slip39Err::SecretLength(master.len()))?;
+    let mut framed = len.to_be_bytes().to_vec();
+    framed.extend_from_slice(master);
+    framed.resize((framed.len() + framed.len() % 2).max(16), 0);
+    Ok(framed)
+}
+
+/// undoes `frame_master`, `None` for anything else, e.g. what a wrong passphrase recovers
+fn unframe_master(mut secret: Vec<u8>) -> Option<Vec<u8>> {
+    let (len, rest) = secret.split_first_chunk::<2>()?;
+    let len = u16::from_be_bytes(*len) as usize;
+    let padded = (len + 2 + len % 2).max(16);
+    let master = match secret.len() == padded && rest[len..].iter().all(|b| *b == 0) {
+        true => Some(rest[..len].to_vec()),
+        false => None,
+    };
+    secret.zeroize();
+    master
+}
+
+/// best guess for v3 files which don't record what they hold
+fn infer_key_type(key: &[u8]) -> KeyType {
+    if key.len() == 32 && k256::ecdsa::SigningKey::from_slice(key).is_ok() {
+        KeyType::Evm
+    } else if key.len() == 64 && solana_keypair::Keypair::try_from(key).is_ok() {
+        KeyType::Solana
+    } else {
+        KeyType::Bytes
+    }
+}
+
 impl HotApi {
-    pub fn address(&self, name: &str) -> Result<String, ApiBackendErr> {
-        let path = Path::new(&self.inner.store_path()).join(name);
-        if !path.exists() {
-            return Err(ApiBackendErr::KeyNotExists);
+    pub fn new(inner: Box<dyn BackendImpl>) -> Self {
+        let store = inner.key_store();
+        Self {
+            inner,
+            store,
+            spending: SpendLedger::default(),
+            writes: Mutex::new(()),
+        }
+    }
+    pub fn backend(&self) -> &dyn BackendImpl {
+        self.inner.as_ref()
+    }
+    pub fn key_store(&self) -> &dyn KeyStore {
+        self.store.as_ref()
+    }
+    /// names of all stored keys, needs no approval
+    pub fn list(&self) -> Result<Vec<String>, ApiBackendErr> {
+        Ok(self.store.list()?)
+    }
+    fn assert_exists(&self, name: &str) -> Result<(), ApiBackendErr> {
+        match self.store.contains(name)? {
+            true => Ok(()),
+            false => Err(ApiBackendErr::KeyNotExists),
+        }
+    }
+    fn assert_not_exists(&self, name: &str) -> Result<(), ApiBackendErr> {
+        match self.store.contains(name)? {
+            true => Err(ApiBackendErr::KeyExists),
+            false => Ok(()),
+        }
+    }
+    /// parsed but not decrypted
+    fn keystore(&self, name: &str) -> Result<Keystore, ApiBackendErr> {
+        let bytes = self.store.get(name)?.ok_or(ApiBackendErr::KeyNotExists)?;
+        Ok(Keystore::from_slice(&bytes)?)
+    }
+    pub fn delete(&self, name: &str) -> Result<(), ApiBackendErr> {
+        self.assert_exists(name)?;
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("delete '{}'", name).as_str())?;
+        let res = self.write(name, None, &password);
+        password.zeroize();
+        res
+    }
+    /// store an existing secret, `pk` is taken as is
+    pub fn import(&self, name: &str, key_type: KeyType, pk: &[u8]) -> Result<(), ApiBackendErr> {
+        self.assert_not_exists(name)?;
+        let meta = key_meta(name, key_type, pk)?;
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("import '{}'", name).as_str())?;
+        let res = self.write_new(name, pk, &password, meta);
+        password.zeroize();
+        res
+    }
+    /// names of keystores that are v3 or below the configured KDF, needs no approval.
+    /// they are resealed the next time they are opened or by `upgrade`
+    pub fn outdated(&self) -> Result<Vec<String>, ApiBackendErr> {
+        let kdf = self.inner.kdf();
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|name| matches!(self.keystore(name), Ok(k) if k.is_outdated(&kdf)))
+            .collect())
+    }
+    /// reseals outdated keystores among `names` with the current KDF, one approval for all
+    /// of them. `key_type` overrides the guess made from the secret of v3 files
+    pub fn upgrade(
+        &self,
+        names: &[String],
+        key_type: Option<KeyType>,
+    ) -> Result<Vec<String>, ApiBackendErr> {
+        let kdf = self.inner.kdf();
+        let outdated = names
+            .iter()
+            .filter(|name| matches!(self.keystore(name), Ok(k) if k.is_outdated(&kdf)))
+            .collect::<Vec<_>>();
+        if outdated.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("upgrade {} keys", outdated.len()).as_str())?;
+        let res = (|| {
+            self.verify_store(&password)?;
+            for name in outdated.iter() {
+                let keystore = self.keystore(name)?;
+                keystore.check_name(name)?;
+                let meta = keystore.meta().cloned();
+                let mut key = keystore.decrypt(&password)?;
+                let res = self.reseal(name, &key, &password, meta, key_type, &kdf);
+                key.zeroize();
+                res?;
+            }
+            Ok(outdated.into_iter().cloned().collect())
+        })();
+        password.zeroize();
+        res
+    }
+    /// decrypts `name` for an already approved operation and reseals the file on the way
+    /// if it is outdated, a failed reseal leaves the old file and only fails the read of a
+    /// v3 file, which isn't bound to its name before.
+    /// `key_type` is what the caller is about to use the secret as, it has to match the
+    /// bound one
+    fn open(
+        &self,
+        name: &str,
+        password: &[u8],
+        key_type: Option<KeyType>,
+    ) -> Result<Vec<u8>, ApiBackendErr> {
+        self.verify_store(password)?;
+        let keystore = self.keystore(name)?;
+        keystore.check_name(name)?;
+        if let Some(key_type) = key_type {
+            keystore.check_key_type(key_type)?;
+        }
+        let kdf = self.inner.kdf();
+        let outdated = keystore.is_outdated(&kdf);
+        let meta = keystore.meta().cloned();
+        let bound = meta.is_some();
+        let mut key = keystore.decrypt(password)?;
+        if let Err(e) = self.store.audit("open", name) {
+            key.zeroize();
+            return Err(e.into());
+        }
+        if outdated {
+            if let Err(e) = self.reseal(name, &key, password, meta, key_type, &kdf) {
+                // a v3 file isn't bound to its name, it is only used once it is
+                if !bound {
+                    key.zeroize();
+                    return Err(e);
+                }
+                self.inner
+                    .communicate_err(format!("failed to upgrade keystore '{}': {}", name, e));
+            }
+        }
+        Ok(key)
+    }
+    /// v4 files keep their meta, v3 ones get it derived from the secret. versions the store
+    /// kept of the old file are pruned
+    fn reseal(
+        &self,
+        name: &str,
+        key: &[u8],
+        password: &[u8],
+        meta: Option<KeyMeta>,
+        key_type: Option<KeyType>,
+        kdf: &KdfConfig,
+    ) -> Result<(), ApiBackendErr> {
+        let meta = match meta {
+            Some(meta) => meta,
+            None => key_meta(name, key_type.unwrap_or(infer_key_type(key)), key)?,
+        };
+        let mut rng = rand::rngs::OsRng;
+        let contents = encrypt_keystore(&mut rng, key, password, meta, kdf)?;
+        self.write(name, Some(&contents), password)?;
+        // the older copies are the same secret under a weaker KDF or format
+        self.store.prune(name)?;
+        Ok(())
+    }
+    /// stores a new key, only into a store that still matches its manifest
+    fn write_new(
+        &self,
+        name: &str,
+        pk: &[u8],
+        password: &[u8],
+        meta: KeyMeta,
+    ) -> Result<(), ApiBackendErr> {
+        self.verify_store(password)?;
+        let mut rng = rand::rngs::OsRng;
+        let contents = encrypt_keystore(&mut rng, pk, password, meta, &self.inner.kdf())?;
+        self.write(name, Some(&contents), password)
+    }
+    /// writes `name`, or removes it with `None`, and records it in the manifest. a failed
+    /// manifest update puts the previous contents back
+    fn write(
+        &self,
+        name: &str,
+        contents: Option<&[u8]>,
+        password: &[u8],
+    ) -> Result<(), ApiBackendErr> {
+        let _writes = self.writes.lock().expect("not poisoned");
+        let previous = self.store.get(name)?;
+        match contents {
+            Some(contents) => self.store.put(name, contents)?,
+            None => {
+                self.store.delete(name)?;
+            }
+        }
+        if let Err(e) = manifest::record(self.store.as_ref(), password, name) {
+            let _ = match previous {
+                Some(previous) => self.store.put(name, &previous),
+                None => self.store.delete(name).map(|_| ()),
+            };
+            return Err(e.into());
+        }
+        Ok(())
+    }
+    /// checks the store against its manifest. a store written before the manifest existed
+    /// gets one with the owner's approval instead of failing every read
+    fn verify_store(&self, password: &[u8]) -> Result<(), ApiBackendErr> {
+        match manifest::verify(self.store.as_ref(), password) {
+            Err(ManifestErr::Missing) => {
+                if !self
+                    .inner
+                    .is_device_owner("trust the key store, it has no manifest yet")
+                {
+                    return Err(ManifestErr::Missing.into());
+                }
+                let _writes = self.writes.lock().expect("not poisoned");
+                // another writer may have made one meanwhile, that one stands
+                match manifest::verify(self.store.as_ref(), password) {
+                    Err(ManifestErr::Missing) => {
+                        manifest::rebuild(self.store.as_ref(), password)?;
+                        Ok(())
+                    }
+                    res => Ok(res?),
+                }
+            }
+            res => Ok(res?),
+        }
+    }
+    /// store dir and files that others can read, tightened to 0700/0600 when `fix` is set
+    pub fn check_permissions(&self, fix: bool) -> Result<Vec<String>, ApiBackendErr> {
+        Ok(self.store.check_permissions(fix)?)
+    }
+    /// accesses the store recorded, empty for stores without an audit trail
+    pub fn audit_log(&self) -> Result<Vec<AuditEntry>, ApiBackendErr> {
+        Ok(self.store.audit_log()?)
+    }
+    /// copies every keystore, the spend ledger and the manifest as they are into an empty
+    /// `target`, only from a store that matches its manifest. returns the copied names
+    pub fn migrate(&self, target: &dyn KeyStore) -> Result<Vec<String>, ApiBackendErr> {
+        if !target.list()?.is_empty() {
+            return Err(ApiBackendErr::KeyExists);
+        }
+        let mut password = self
+            .inner
+            .get_encryption_key()
+            .ok_or(ApiBackendErr::FailedToGetEncryptionKey)?;
+        let res = (|| {
+            self.verify_store(&password)?;
+            let names = self.list()?;
+            let internal = manifest::TRACKED
+                .into_iter()
+                .chain([manifest::MANIFEST_NAME]);
+            for name in names.iter().map(|n| n.as_str()).chain(internal) {
+                if let Some(contents) = self.store.get(name)? {
+                    target.put(name, &contents)?;
+                }
+            }
+            manifest::verify(target, &password)?;
+            Ok(names)
+        })();
+        password.zeroize();
+        res
+    }
+    /// checks the store against its manifest, needs the master key but no approval
+    pub fn verify_manifest(&self) -> Result<(), ApiBackendErr> {
+        let mut password = self
+            .inner
+            .get_encryption_key()
+            .ok_or(ApiBackendErr::FailedToGetEncryptionKey)?;
+        let res = self.verify_store(&password);
+        password.zeroize();
+        res
+    }
+    /// accepts the current contents of the store as the truth, e.g. after restoring a
+    /// backup or on a store written before the manifest existed
+    pub fn rebuild_manifest(&self) -> Result<Vec<String>, ApiBackendErr> {
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key("trust the current key store")?;
+        let res = {
+            let _writes = self.writes.lock().expect("not poisoned");
+            manifest::rebuild(self.store.as_ref(), &password)
+        };
+        password.zeroize();
+        Ok(res?)
+    }
+    /// one encrypted bundle with every keystore, the spend ledger, the manifest and the
+    /// `conf` files (by file name), only from a store that matches its manifest
+    pub fn backup(&self, conf: BTreeMap<String, Vec<u8>>) -> Result<Vec<u8>, ApiBackendErr> {
+        let names = self.list()?;
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("back up {} keys", names.len()).as_str())?;
+        let res = (|| {
+            self.verify_store(&password)?;
+            let mut contents = Contents::default();
+            let internal = manifest::TRACKED
+                .into_iter()
+                .chain([manifest::MANIFEST_NAME]);
+            for name in names.iter().map(|n| n.as_str()).chain(internal) {
+                if let Some(bytes) = self.store.get(name)? {
+                    contents.store.insert(name.to_string(), Blob(bytes));
+                }
+            }
+            contents.conf = conf.into_iter().map(|(name, b)| (name, Blob(b))).collect();
+            let mut rng = rand::rngs::OsRng;
+            Ok(backup::seal(
+                &mut rng,
+                &password,
+                &self.inner.kdf(),
+                &contents,
+            )?)
+        })();
+        password.zeroize();
+        res
+    }
+    /// decrypts every key in a bundle without restoring anything, needs the master key but
+    /// no approval
+    pub fn verify_backup(&self, bundle: &[u8]) -> Result<BackupReport, ApiBackendErr> {
+        let mut password = self
+            .inner
+            .get_encryption_key()
+            .ok_or(ApiBackendErr::FailedToGetEncryptionKey)?;
+        let res = check_backup(bundle, &password).map(|(report, _)| report);
+        password.zeroize();
+        res
+    }
+    /// puts the keys of a bundle into an empty store and hands back its config files. a
+    /// bundle that is corrupted, was made under another master key or doesn't match its own
+    /// manifest is refused before anything is written
+    pub fn restore(
+        &self,
+        bundle: &[u8],
+    ) -> Result<(BackupReport, BTreeMap<String, Vec<u8>>), ApiBackendErr> {
+        if !self.list()?.is_empty() {
+            return Err(ApiBackendErr::KeyExists);
         }
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key("restore a backup")?;
+        let res = (|| {
+            let (report, contents) = check_backup(bundle, &password)?;
+            let _writes = self.writes.lock().expect("not poisoned");
+            let internal = manifest::TRACKED
+                .into_iter()
+                .chain([manifest::MANIFEST_NAME]);
+            for name in report.keys.iter().map(|n| n.as_str()).chain(internal) {
+                if let Some(blob) = contents.store.get(name) {
+                    self.store.put(name, &blob.0)?;
+                }
+            }
+            manifest::verify(self.store.as_ref(), &password)?;
+            let conf = contents
+                .conf
+                .iter()
+                .map(|(name, b)| (name.clone(), b.0.clone()))
+                .collect();
+            Ok((report, conf))
+        })();
+        password.zeroize();
+        res
+    }
+    /// SLIP-39 shares of the master key, any `threshold` of the `count` shares and the
+    /// passphrase recover it, see `frame_master`
+    pub fn split_master(
+        &self,
+        threshold: u8,
+        count: u8,
+        passphrase: &[u8],
+    ) -> Result<Vec<String>, ApiBackendErr> {
         let mut password = self.inner.assert_owner_get_encryption_key(
-            format!("get address '{}'", name).as_str(),
+            format!("split the master key into {} shares", count).as_str(),
         )?;
-        let mut key = decrypt_key(path, &password)?;
-        let addr = sk_to_adr(&key)?;
+        let res = frame_master(&password).and_then(|mut framed| {
+            let mut rng = rand::rngs::OsRng;
+            let res = slip39::split(&mut rng, &framed, passphrase, threshold, count);
+            framed.zeroize();
+            Ok(res?)
+        });
         password.zeroize();
-        key.zeroize();
-        Ok(addr)
+        res
     }
-    pub fn address_solana(&self, name: &str) -> Result<String, ApiBackendErr> {
-        use solana_signer::Signer;
-        let path = Path::new(&self.inner.store_path()).join(name);
-        if !path.exists() {
-            return Err(ApiBackendErr::KeyNotExists);
-        }
+    /// SLIP-39 shares of the secret of `name`, see `split_master`
+    pub fn split_key(
+        &self,
+        name: &str,
+        threshold: u8,
+        count: u8,
+        passphrase: &[u8],
+    ) -> Result<Vec<String>, ApiBackendErr> {
+        self.assert_exists(name)?;
         let mut password = self.inner.assert_owner_get_encryption_key(
-            format!("get solana address '{}'", name).as_str(),
+            format!("split '{}' into {} shares", name, count).as_str(),
         )?;
-        let mut key = decrypt_key(path, &password)?;
-        let keypair = solana_keypair::Keypair::from_bytes(&key)
-            .map_err(|_| ApiBackendErr::FailReadKeypair)?;
-        let addr = keypair.pubkey();
-        keypair.to_bytes().zeroize();
+        let res = self.open(name, &password, None).and_then(|mut key| {
+            let mut rng = rand::rngs::OsRng;
+            let res = slip39::split(&mut rng, &key, passphrase, threshold, count);
+            key.zeroize();
+            Ok(res?)
+        });
         password.zeroize();
+        res
+    }
+    /// provisions the master key recovered from shares into a backend that has none. a
+    /// wrong passphrase recovers a different key, so it has to open `backup` when given,
+    /// else the store's manifest or, in a store from before the manifest, a keystore
+    pub fn recover_master<S: AsRef<str>>(
+        &self,
+        shares: &[S],
+        passphrase: &[u8],
+        backup: Option<&[u8]>,
+    ) -> Result<(), ApiBackendErr> {
+        if self.inner.has_encryption_key() {
+            return Err(ApiBackendErr::MasterKeyExists);
+        }
+        let mut master = unframe_master(slip39::combine(shares, passphrase)?)
+            .ok_or(ApiBackendErr::UnverifiedMasterKey)?;
+        let res = (|| {
+            match backup {
+                Some(bundle) => {
+                    check_backup(bundle, &master)?;
+                }
+                None if manifest::load(self.store.as_ref(), &master)?.is_some() => {}
+                None => {
+                    let name = self.list()?.into_iter().next();
+                    let name = name.ok_or(ApiBackendErr::UnverifiedMasterKey)?;
+                    self.keystore(&name)?.decrypt(&master)?.zeroize();
+                }
+            }
+            if !self
+                .inner
+                .is_device_owner("provision the recovered master key")
+            {
+                return Err(ApiBackendErr::NotDeviceOwner);
+            }
+            if !self.inner.set_encryption_key(&master) {
+                return Err(ApiBackendErr::FailedToSetEncryptionKey);
+            }
+            Ok(())
+        })();
+        master.zeroize();
+        res
+    }
+    /// imports the secret recovered from shares as a new key `name`
+    pub fn recover_key<S: AsRef<str>>(
+        &self,
+        name: &str,
+        key_type: KeyType,
+        shares: &[S],
+        passphrase: &[u8],
+    ) -> Result<(), ApiBackendErr> {
+        self.assert_not_exists(name)?;
+        let mut key = slip39::combine(shares, passphrase)?;
+        let res = self.import(name, key_type, &key);
         key.zeroize();
-        Ok(addr.to_string())
+        res
     }
-    pub fn generate_solana(&self, name: &str) -> Result<(), ApiBackendErr> {
-        let path = Path::new(&self.inner.store_path()).join(name);
-        if path.exists() {
-            return Err(ApiBackendErr::KeyExists);
+    /// `name` sealed under `passphrase` with the configured KDF, in printable form. the
+    /// address is derived from the secret, not taken from the keystore
+    pub fn export_paper(&self, name: &str, passphrase: &[u8]) -> Result<PaperKey, ApiBackendErr> {
+        self.assert_exists(name)?;
+        let key_type = self.keystore(name)?.meta().map(|m| m.key_type);
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("export '{}' to paper", name).as_str())?;
+        let res = self.open(name, &password, key_type).and_then(|mut key| {
+            let res = (|| {
+                let key_type = key_type.unwrap_or(infer_key_type(&key));
+                let address = key_meta(name, key_type, &key)?.address;
+                let mut rng = rand::rngs::OsRng;
+                let code = paper::encrypt(
+                    &mut rng,
+                    &key,
+                    passphrase,
+                    key_type,
+                    address.as_deref(),
+                    &self.inner.kdf(),
+                )?;
+                Ok(PaperKey {
+                    name: Some(name.to_string()),
+                    key_type,
+                    address,
+                    code,
+                })
+            })();
+            key.zeroize();
+            res
+        });
+        password.zeroize();
+        res
+    }
+    /// imports a paper backup (printed form or bare code) as `name`. the decrypted secret
+    /// has to give the address the code was made for
+    pub fn import_paper(
+        &self,
+        name: &str,
+        text: &str,
+        passphrase: &[u8],
+    ) -> Result<(), ApiBackendErr> {
+        let parsed = PaperKey::parse(text)?;
+        self.assert_not_exists(name)?;
+        let opened = paper::decrypt(&parsed.code, passphrase)?;
+        let address = key_meta(name, opened.key_type, &opened.secret)?.address;
+        if !opened.matches(address.as_deref()) {
+            return Err(PaperErr::AddressMismatch.into());
         }
+        self.import(name, opened.key_type, &opened.secret)
+    }
+    pub fn address(&self, name: &str) -> Result<String, ApiBackendErr> {
+        self.assert_exists(name)?;
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("get address '{}'", name).as_str())?;
+        let res = self
+            .open(name, &password, Some(KeyType::Evm))
+            .and_then(|mut key| {
+                let res = sk_to_adr(&key);
+                key.zeroize();
+                res
+            });
+        password.zeroize();
+        res
+    }
+    /// signs `tx` with the evm key `name`, the secret never leaves the server. an
+    /// incomplete transaction, or one outside the key's policy or daily limit, is rejected
+    /// before asking for approval
+    pub fn sign_transaction(
+        &self,
+        name: &str,
+        tx: &Transaction,
+    ) -> Result<SignedTransaction, ApiBackendErr> {
+        self.assert_exists(name)?;
+        tx.validate()?;
+        let policy = self.inner.policy(name);
+        policy.check_transaction(tx)?;
+        let reason = format!("sign with '{}': {}", name, tx.describe());
+        let mut password = self
+            .inner
+            .get_encryption_key()
+            .ok_or(ApiBackendErr::FailedToGetEncryptionKey)?;
+        let res = (|| {
+            // over the limit is refused without asking, the spend itself counts once signed
+            if let Some(limit) = policy.max_value_per_day {
+                let store = self.store.as_ref();
+                let now = now_secs();
+                self.spending
+                    .check(store, &password, name, tx.value, limit, now)?;
+            }
+            match policy.auto_approve {
+                true => self.inner.trace(&format!("auto-approved: {}", reason)),
+                false if !self.inner.is_device_owner(&reason) => {
+                    return Err(ApiBackendErr::NotDeviceOwner)
+                }
+                false => {}
+            }
+            let mut key = self.open(name, &password, Some(KeyType::Evm))?;
+            let signed = tx.sign(&key);
+            key.zeroize();
+            let signed = signed?;
+            if let Some(limit) = policy.max_value_per_day {
+                // its manifest entry changes with the ledger
+                let _writes = self.writes.lock().expect("not poisoned");
+                let store = self.store.as_ref();
+                let now = now_secs();
+                self.spending
+                    .spend(store, &password, name, tx.value, limit, now)?;
+            }
+            Ok(signed)
+        })();
+        password.zeroize();
+        res
+    }
+    /// signs the EIP-191 `personal_sign` hash of `message`
+    pub fn personal_sign(&self, name: &str, message: &[u8]) -> Result<EthSignature, ApiBackendErr> {
+        self.assert_exists(name)?;
+        let reason = format!(
+            "sign message with '{}': {}",
+            name,
+            evm::message::describe(message)
+        );
+        self.sign_digest(name, evm::eip191_hash(message), &reason, &|_| Ok(()))
+    }
+    /// signs EIP-712 typed data, the prompt names the domain and the primary type. CoW
+    /// orders and Safe transactions are decoded for the prompt and checked against the policy
+    pub fn sign_typed_data(
+        &self,
+        name: &str,
+        typed_data: &TypedData,
+    ) -> Result<EthSignature, ApiBackendErr> {
+        self.assert_exists(name)?;
+        let hash = typed_data.signing_hash()?;
+        let known = Known::recognise(typed_data)?;
+        let reason = match known.as_ref() {
+            Some(known) => format!(
+                "sign {} with '{}', {}",
+                known.describe(),
+                name,
+                typed_data.describe()
+            ),
+            None => format!("sign {} with '{}'", typed_data.describe(), name),
+        };
+        let policy = self.inner.policy(name);
+        policy.check_domain_chain(typed_data.chain_id())?;
+        self.sign_digest(name, hash, &reason, &|signer| match known.as_ref() {
+            Some(known) => Ok(policy.check_known(known, signer)?),
+            None => Ok(()),
+        })
+    }
+    /// signs a bare digest, only for keys whose policy allows it
+    pub fn sign_hash(&self, name: &str, hash: &B256) -> Result<EthSignature, ApiBackendErr> {
+        self.assert_exists(name)?;
+        self.inner.policy(name).check_raw_hash()?;
+        let reason = format!("sign raw hash with '{}': {}", name, hash);
+        self.sign_digest(name, hash.0, &reason, &|_| Ok(()))
+    }
+    /// the `X-Flashbots-Signature` header value, `address:signature`, for a relay request
+    /// with `body`. only for keys whose policy allows relay auth, which are never prompted
+    /// for since searchers sign a request every block
+    pub fn flashbots_signature(&self, name: &str, body: &[u8]) -> Result<String, ApiBackendErr> {
+        self.assert_exists(name)?;
+        self.inner.policy(name).check_relay_auth()?;
+        let mut password = self
+            .inner
+            .get_encryption_key()
+            .ok_or(ApiBackendErr::FailedToGetEncryptionKey)?;
+        let res = self
+            .open(name, &password, Some(KeyType::Evm))
+            .and_then(|mut key| {
+                let res = evm::sign_hash(&key, &evm::flashbots_hash(body));
+                key.zeroize();
+                let (sig, from) = res?;
+                Ok(format!("{}:{}", from, evm::to_hex(&sig.to_rsv())))
+            });
+        password.zeroize();
+        res
+    }
+    /// `check` gets the signer's address, before the prompt when the keystore metadata has
+    /// it and again once the key is open
+    fn sign_digest(
+        &self,
+        name: &str,
+        hash: [u8; 32],
+        reason: &str,
+        check: &dyn Fn(Address) -> Result<(), ApiBackendErr>,
+    ) -> Result<EthSignature, ApiBackendErr> {
+        if let Some(address) = self.meta_address(name)? {
+            check(address)?;
+        }
+        let mut password = self.inner.assert_owner_get_encryption_key(reason)?;
+        let res = self
+            .open(name, &password, Some(KeyType::Evm))
+            .and_then(|mut key| {
+                let res = evm::sign_hash(&key, &hash);
+                key.zeroize();
+                let (sig, from) = res?;
+                check(from)?;
+                Ok(EthSignature::new(hash, &sig, from))
+            });
+        password.zeroize();
+        res
+    }
+    /// checks a signature against the address of the evm key `name`. the address comes
+    /// from the keystore metadata once the manifest vouches for it, so no approval is needed
+    /// unless the keystore predates metadata
+    pub fn verify_signature(
+        &self,
+        name: &str,
+        req: &VerifyRequest,
+    ) -> Result<Verification, ApiBackendErr> {
+        self.assert_exists(name)?;
+        self.verify_manifest()?;
+        let keystore = self.keystore(name)?;
+        keystore.check_name(name)?;
+        keystore.check_key_type(KeyType::Evm)?;
+        let address = match self.meta_address(name)? {
+            Some(address) => address,
+            None => self.address(name)?.parse().map_err(EvmErr::InvalidField)?,
+        };
+        Ok(req.verify(address)?)
+    }
+    /// names and addresses of the evm keys, from the keystore metadata once the manifest
+    /// vouches for it. keys that predate metadata are left out
+    pub fn evm_accounts(&self) -> Result<Vec<(String, Address)>, ApiBackendErr> {
+        self.verify_manifest()?;
+        let mut accounts = Vec::new();
+        for name in self.store.list()? {
+            let keystore = self.keystore(&name)?;
+            let address = match keystore.meta() {
+                Some(meta) if meta.key_type == KeyType::Evm && meta.name == name => {
+                    meta.address.as_deref().and_then(|a| a.parse().ok())
+                }
+                _ => None,
+            };
+            if let Some(address) = address {
+                accounts.push((name, address));
+            }
+        }
+        Ok(accounts)
+    }
+    /// the evm key with `address`
+    pub fn account_name(&self, address: &Address) -> Result<String, ApiBackendErr> {
+        self.evm_accounts()?
+            .into_iter()
+            .find(|(_, a)| a == address)
+            .map(|(name, _)| name)
+            .ok_or(ApiBackendErr::KeyNotExists)
+    }
+    /// the address in the keystore metadata, unauthenticated until the keystore is opened
+    fn meta_address(&self, name: &str) -> Result<Option<Address>, ApiBackendErr> {
+        let keystore = self.keystore(name)?;
+        let address = keystore.meta().and_then(|m| m.address.as_deref());
+        Ok(address.and_then(|a| a.parse().ok()))
+    }
+    pub fn address_solana(&self, name: &str) -> Result<String, ApiBackendErr> {
+        use solana_signer::Signer;
+        self.assert_exists(name)?;
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("get solana address '{}'", name).as_str())?;
+        let res = self
+            .open(name, &password, Some(KeyType::Solana))
+            .and_then(|mut key| {
+                let keypair = solana_keypair::Keypair::try_from(key.as_slice())
+                    .map_err(|_| ApiBackendErr::FailReadKeypair);
+                key.zeroize();
+                // the secret half wipes itself when the keypair is dropped
+                Ok(keypair?.pubkey().to_string())
+            });
+        password.zeroize();
+        res
+    }
+    pub fn generate_solana(&self, name: &str) -> Result<(), ApiBackendErr> {
+        self.assert_not_exists(name)?;
         let mut pk = solana_keypair::Keypair::new().to_bytes();
+        let meta = key_meta(name, KeyType::Solana, &pk)?;
         // SECURITY
-        let mut password = self.inner.assert_owner_get_encryption_key(
-            format!("generate solana key '{}'", name).as_str(),
-        )?;
-        let mut rng = rand::rngs::OsRng;
-        encrypt_key(self.inner.store_path(), &mut rng, &pk, &password, name)?;
+        let mut password = self
+            .inner
+            .assert_owner_get_encryption_key(format!("generate solana key '{}'", name).as_str())?;
+        let res = self.write_new(name, &pk, &password, meta);
         pk.zeroize();
         password.zeroize();
-        Ok(())
+        res
+    }
+    /// signs a transaction or off-chain message with the solana key `name`. the prompt names
+    /// the fee payer, the programs invoked and decodes transfers
+    pub fn sign_solana(
+        &self,
+        name: &str,
+        payload: &SolanaPayload,
+    ) -> Result<SolanaSignature, ApiBackendErr> {
+        self.assert_exists(name)?;
+        let reason = format!("sign with '{}': {}", name, payload.describe()?);
+        let mut password = self.inner.assert_owner_get_encryption_key(&reason)?;
+        let res = self
+            .open(name, &password, Some(KeyType::Solana))
+            .and_then(|mut key| {
+                let keypair = solana_keypair::Keypair::try_from(key.as_slice())
+                    .map_err(|_| ApiBackendErr::FailReadKeypair);
+                key.zeroize();
+                // the secret half wipes itself when the keypair is dropped
+                Ok(payload.sign(&keypair?)?)
+            });
+        password.zeroize();
+        res
     }
     pub fn generate(&self, name: &str) -> Result<(), ApiBackendErr> {
-        let path = Path::new(&self.inner.store_path()).join(name);
-        if path.exists() {
-            return Err(ApiBackendErr::KeyExists);
-        }
+        self.assert_not_exists(name)?;
         let mut rng = rand::rngs::OsRng;
         let mut pk = random_pk(&mut rng).to_bytes().to_vec();
+        let meta = key_meta(name, KeyType::Evm, &pk)?;
         // SECURITY
         let mut password = self
             .inner
             .assert_owner_get_encryption_key(format!("generate '{}'", name).as_str())?;
-        encrypt_key(self.inner.store_path(), &mut rng, &pk, &password, name)?;
+        let res = self.write_new(name, &pk, &password, meta);
         pk.zeroize();
         password.zeroize();
-        Ok(())
+        res
     }
     /// read works for both solana/evm
     pub fn read(&self, body: &[u8], name: &str) -> Result<Vec<u8>, ApiBackendErr> {
         let req: ClientReq = serde_json::from_slice(body)?;
-        let path = Path::new(&self.inner.store_path()).join(name);
-        if !path.exists() {
-            return Err(ApiBackendErr::KeyNotExists);
-        }
-        println!("client pubk:\n{}", df_share::generate_ascii_art(&req.pubk));
+        self.assert_exists(name)?;
+        let art = df_share::generate_ascii_art(&req.pubk);
+        self.inner.trace(&format!("client pubk:\n{}", art));
         let mut password = self
             .inner
             .assert_owner_get_encryption_key(format!("read '{}'", name).as_str())?;
-        let mut key = decrypt_key(path, &password)?;
-        let server = EphemeralServer::new()?;
-        let res = server.encrypt_secret(&req, &key)?;
+        let res = self.open(name, &password, None).and_then(|mut key| {
+            let res = EphemeralServer::new().and_then(|server| server.encrypt_secret(&req, &key));
+            key.zeroize();
+            Ok(serde_json::to_vec(&res?)?)
+        });
         password.zeroize();
-        key.zeroize();
-        Ok(serde_json::to_vec(&res)?)
+        res
     }
 }
 
@@ -250,13 +1106,13 @@ async fn service_impl(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, h
     let hot = req.extensions().get::<Arc<HotApi>>().unwrap().clone();
 
     let path = req.uri().path().to_string();
-    println!("req {}", path);
+    hot.inner.trace(&format!("req {}", path));
+    let body = req.collect().await?.to_bytes();
     if path.ends_with("/health") {
         *response.body_mut() = "ok".as_bytes().to_vec().into();
     }
     if let Some(name) = path.strip_prefix("/read/") {
         if is_valid_string_name(name) {
-            let body = req.collect().await?.to_bytes();
             match hot.read(&body, name) {
                 Ok(v) => {
                     *response.body_mut() = v.into();
@@ -295,6 +1151,115 @@ async fn service_impl(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, h
             }
         }
     }
+    // body is the unsigned transaction as JSON, responds with the signed one
+    if let Some(name) = path.strip_prefix("/evm_sign_tx/") {
+        if is_valid_string_name(name) {
+            let res = serde_json::from_slice::<Transaction>(&body)
+                .map_err(ApiBackendErr::from)
+                .and_then(|tx| hot.sign_transaction(name, &tx));
+            match res {
+                Ok(signed) => {
+                    *response.body_mut() =
+                        serde_json::to_vec(&signed).expect("plain struct").into();
+                }
+                Err(e) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    hot.inner.communicate_err(e.to_string());
+                }
+            }
+        }
+    }
+    // body is the message itself
+    if let Some(name) = path.strip_prefix("/evm_personal_sign/") {
+        if is_valid_string_name(name) {
+            match hot.personal_sign(name, &body) {
+                Ok(signed) => {
+                    *response.body_mut() =
+                        serde_json::to_vec(&signed).expect("plain struct").into();
+                }
+                Err(e) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    hot.inner.communicate_err(e.to_string());
+                }
+            }
+        }
+    }
+    // body is the typed data JSON as eth_signTypedData_v4 takes it
+    if let Some(name) = path.strip_prefix("/evm_sign_typed_data/") {
+        if is_valid_string_name(name) {
+            let res = serde_json::from_slice::<TypedData>(&body)
+                .map_err(ApiBackendErr::from)
+                .and_then(|typed_data| hot.sign_typed_data(name, &typed_data));
+            match res {
+                Ok(signed) => {
+                    *response.body_mut() =
+                        serde_json::to_vec(&signed).expect("plain struct").into();
+                }
+                Err(e) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    hot.inner.communicate_err(e.to_string());
+                }
+            }
+        }
+    }
+    // body is the 0x prefixed hex digest
+    if let Some(name) = path.strip_prefix("/evm_sign_hash/") {
+        if is_valid_string_name(name) {
+            let res = std::str::from_utf8(&body)
+                .map_err(|e| EvmErr::InvalidField(e.to_string()))
+                .and_then(|s| s.trim().parse().map_err(EvmErr::InvalidField))
+                .map_err(ApiBackendErr::from)
+                .and_then(|hash| hot.sign_hash(name, &hash));
+            match res {
+                Ok(signed) => {
+                    *response.body_mut() =
+                        serde_json::to_vec(&signed).expect("plain struct").into();
+                }
+                Err(e) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    hot.inner.communicate_err(e.to_string());
+                }
+            }
+        }
+    }
+    // body is the relay request exactly as it will be sent
+    if let Some(name) = path.strip_prefix("/flashbots_sign/") {
+        if is_valid_string_name(name) {
+            match hot.flashbots_signature(name, &body) {
+                Ok(header) => {
+                    *response.body_mut() = header.into_bytes().into();
+                }
+                Err(e) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    hot.inner.communicate_err(e.to_string());
+                }
+            }
+        }
+    }
+    if let Some(name) = path.strip_prefix("/evm_verify/") {
+        if is_valid_string_name(name) {
+            let res = serde_json::from_slice::<VerifyRequest>(&body)
+                .map_err(ApiBackendErr::from)
+                .and_then(|req| hot.verify_signature(name, &req));
+            match res {
+                Ok(verification) => {
+                    *response.body_mut() = serde_json::to_vec(&verification)
+                        .expect("plain struct")
+                        .into();
+                }
+                Err(e) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    hot.inner.communicate_err(e.to_string());
+                }
+            }
+        }
+    }
+    // JSON-RPC for tooling that signs through geth's Clef, responds with errors in the body
+    if path == "/clef" {
+        *response.body_mut() = serde_json::to_vec(&clef::handle(&hot, &body))
+            .expect("json value")
+            .into();
+    }
     // solana
     if let Some(name) = path.strip_prefix("/solana_generate/") {
         if is_valid_string_name(name) {
@@ -322,36 +1287,149 @@ async fn service_impl(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, h
             }
         }
     }
+    // body is `{"transaction": "<base64>"}` or `{"offchainMessage": "text"}`
+    if let Some(name) = path.strip_prefix("/solana_sign/") {
+        if is_valid_string_name(name) {
+            let res = serde_json::from_slice::<SolanaPayload>(&body)
+                .map_err(ApiBackendErr::from)
+                .and_then(|payload| hot.sign_solana(name, &payload));
+            match res {
+                Ok(signed) => {
+                    *response.body_mut() =
+                        serde_json::to_vec(&signed).expect("plain struct").into();
+                }
+                Err(e) => {
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    hot.inner.communicate_err(e.to_string());
+                }
+            }
+        }
+    }
     Ok(response)
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
+    use crate::crypto::{decrypt_key, encrypt_key};
+    use crate::secure_fs::test::unique_dir;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const TEST_KDF: KdfConfig = KdfConfig::Argon2id {
+        m_cost: 256,
+        t_cost: 1,
+        p_cost: 1,
+    };
 
-    struct TestBackend {}
+    /// the master key most tests run with
+    const TEST_MASTER: &[u8] = b"I_am_a_secret_that_should_not_be_In_memory";
+
+    /// keys live in memory unless `on_disk`, every `HotApi` gets an empty store
+    /// and every backend its own directory
+    pub(crate) struct TestBackend {
+        key: Mutex<Option<Vec<u8>>>,
+        owner: Arc<AtomicBool>,
+        policy: fn(&str) -> KeyPolicy,
+        store: String,
+        on_disk: bool,
+    }
+    impl Default for TestBackend {
+        fn default() -> Self {
+            let dir = unique_dir("test");
+            TestBackend {
+                key: Mutex::new(Some(TEST_MASTER.to_vec())),
+                owner: Arc::new(AtomicBool::new(true)),
+                policy: test_policy,
+                store: dir.to_string_lossy().into_owned(),
+                on_disk: false,
+            }
+        }
+    }
+    impl TestBackend {
+        /// `None` is a machine that lost its master key
+        fn with_key(self, key: Option<&[u8]>) -> Self {
+            *self.key.lock().unwrap() = key.map(<[u8]>::to_vec);
+            self
+        }
+        /// approves only while `owner` is set
+        fn with_owner(mut self, owner: &Arc<AtomicBool>) -> Self {
+            self.owner = owner.clone();
+            self
+        }
+        fn with_policy(mut self, policy: fn(&str) -> KeyPolicy) -> Self {
+            self.policy = policy;
+            self
+        }
+        /// the default directory store
+        fn on_disk(mut self) -> Self {
+            self.on_disk = true;
+            self
+        }
+    }
+    impl Drop for TestBackend {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.store);
+        }
+    }
     impl BackendImpl for TestBackend {
         fn communicate_err(&self, e: String) {
             eprintln!("{:?}", e)
         }
         fn get_encryption_key(&self) -> Option<Vec<u8>> {
-            Some(
-                "I_am_a_secret_that_should_not_be_In_memory"
-                    .as_bytes()
-                    .to_vec(),
-            )
+            self.key.lock().unwrap().clone()
         }
-        fn is_device_owner(&self, _: &str) -> bool {
+        fn set_encryption_key(&self, key: &[u8]) -> bool {
+            let mut current = self.key.lock().unwrap();
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(key.to_vec());
             true
         }
+        fn is_device_owner(&self, _: &str) -> bool {
+            self.owner.load(Ordering::SeqCst)
+        }
         fn store(&self) -> &str {
-            "~/HOT_CHEESE_TEST"
+            &self.store
+        }
+        fn kdf(&self) -> KdfConfig {
+            TEST_KDF
+        }
+        fn key_store(&self) -> Box<dyn KeyStore> {
+            if self.on_disk {
+                Box::new(DirKeyStore::new(self.store_path()))
+            } else {
+                Box::new(MemKeyStore::default())
+            }
+        }
+        fn policy(&self, name: &str) -> KeyPolicy {
+            (self.policy)(name)
+        }
+    }
+
+    fn test_policy(name: &str) -> KeyPolicy {
+        KeyPolicy {
+            allow_raw_hash: name == "RAW",
+            tokens: (name == "COW").then(|| vec![Address::from([0xaa; 20])]),
+            ..Default::default()
+        }
+    }
+
+    /// `BOT` auto-approves within its limits
+    fn bot_policy(name: &str) -> KeyPolicy {
+        KeyPolicy {
+            chain_ids: Some(vec![1]),
+            max_value: Some(100),
+            max_value_per_day: Some(150),
+            auto_approve: name == "BOT",
+            allow_relay_auth: name == "SEARCHER",
+            ..Default::default()
         }
     }
 
     #[test]
     fn encrypt_existing() {
-        let inner = TestBackend {};
+        let inner = TestBackend::default();
 
         // input
         let name = "encrypt_existing";
@@ -359,6 +1437,666 @@ mod test {
 
         let mut rng = rand::rngs::OsRng;
         let password = inner.assert_owner_get_encryption_key("hi").unwrap();
-        encrypt_key(inner.store_path(), &mut rng, &pk, &password, name).unwrap();
+        let meta = key_meta(name, KeyType::Bytes, &pk).unwrap();
+        encrypt_key(
+            inner.store_path(),
+            &mut rng,
+            &pk,
+            &password,
+            meta,
+            &inner.kdf(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn upgrade_v3() {
+        // password of test-keys/key-scrypt.json
+        let backend = TestBackend::default().with_key(Some(b"grOQ8QDnGHvpYJf"));
+        let api = HotApi::new(Box::new(backend.on_disk()));
+        let name = "upgrade_v3".to_string();
+        let path = api.inner.store_path().join(&name);
+        std::fs::copy("./test-keys/key-scrypt.json", &path).unwrap();
+        // a store from before the manifest gets one on the way
+        let before = decrypt_key(&path, "grOQ8QDnGHvpYJf").unwrap();
+
+        let upgraded = api.upgrade(std::slice::from_ref(&name), None).unwrap();
+        assert_eq!(upgraded, vec![name.clone()]);
+        let keystore = Keystore::read(&path).unwrap();
+        assert_eq!(keystore.version(), 4);
+        let meta = keystore.meta().unwrap().clone();
+        assert_eq!(meta.key_type, KeyType::Evm);
+        assert_eq!(meta.address, Some(sk_to_adr(&before).unwrap()));
+        assert_eq!(keystore.decrypt("grOQ8QDnGHvpYJf").unwrap(), before);
+        // already current
+        assert!(api
+            .upgrade(std::slice::from_ref(&name), None)
+            .unwrap()
+            .is_empty());
+        api.verify_manifest().unwrap();
+    }
+
+    #[test]
+    fn upgrade_on_read() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        let name = "upgrade_on_read";
+        let mut rng = rand::rngs::OsRng;
+        let pk = random_pk(&mut rng).to_bytes().to_vec();
+        let meta = key_meta(name, KeyType::Evm, &pk).unwrap();
+        let password = api.inner.get_encryption_key().unwrap();
+        let cheap = KdfConfig::Scrypt {
+            log_n: 4,
+            r: 8,
+            p: 1,
+        };
+        let before = encrypt_keystore(&mut rng, &pk, &password, meta.clone(), &cheap).unwrap();
+        api.store.put(name, &before).unwrap();
+        api.rebuild_manifest().unwrap();
+        assert_eq!(api.outdated().unwrap(), vec![name]);
+
+        assert_eq!(api.address(name).unwrap(), sk_to_adr(&pk).unwrap());
+        assert!(api.outdated().unwrap().is_empty());
+        let keystore = api.keystore(name).unwrap();
+        assert_eq!(keystore.meta(), Some(&meta));
+        assert_eq!(keystore.decrypt(&password).unwrap(), pk);
+        // the reseal went into the manifest and the weaker copy is gone
+        api.verify_manifest().unwrap();
+        assert_eq!(api.store.versions(name).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn swapped_keys_are_refused() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        let (a, b) = ("swapped_keys_a", "swapped_keys_b");
+        api.generate(a).unwrap();
+        let stolen = api.store.get(a).unwrap().unwrap();
+        api.store.put(b, &stolen).unwrap();
+        assert!(matches!(
+            api.address(b),
+            Err(ApiBackendErr::Manifest(ManifestErr::Mismatch(_)))
+        ));
+        // even with the planted file accepted the name doesn't match
+        api.rebuild_manifest().unwrap();
+        assert!(matches!(
+            api.address(b),
+            Err(ApiBackendErr::Crypto(CryptoErr::NameMismatch(n))) if n == a
+        ));
+        assert!(matches!(
+            api.address_solana(a),
+            Err(ApiBackendErr::Crypto(CryptoErr::KeyTypeMismatch(
+                KeyType::Evm
+            )))
+        ));
+    }
+
+    #[test]
+    fn rolled_back_keys_are_refused() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        let old = api.store.get("A").unwrap().unwrap();
+        api.delete("A").unwrap();
+        api.generate("A").unwrap();
+        api.address("A").unwrap();
+
+        api.store.put("A", &old).unwrap();
+        match api.address("A") {
+            Err(ApiBackendErr::Manifest(ManifestErr::Mismatch(changes))) => {
+                assert_eq!(changes, vec![manifest::Change::Changed("A".into())])
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(api.verify_manifest().is_err());
+    }
+
+    #[test]
+    fn manifest_bootstrap() {
+        let owner = Arc::new(AtomicBool::new(false));
+        let api = HotApi::new(Box::new(TestBackend::default().with_owner(&owner)));
+        let password = api.inner.get_encryption_key().unwrap();
+        let mut rng = rand::rngs::OsRng;
+        let meta = key_meta("A", KeyType::Bytes, b"secret").unwrap();
+        let old = encrypt_keystore(&mut rng, b"secret", &password, meta, &TEST_KDF).unwrap();
+        api.store.put("A", &old).unwrap();
+        // written before the manifest existed, trusted only with approval
+        assert!(matches!(
+            api.verify_manifest(),
+            Err(ApiBackendErr::Manifest(ManifestErr::Missing))
+        ));
+        owner.store(true, Ordering::SeqCst);
+        api.verify_manifest().unwrap();
+        assert!(api.store.contains(manifest::MANIFEST_NAME).unwrap());
+        assert_eq!(api.open("A", &password, None).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn writes_keep_the_manifest() {
+        let api = Arc::new(HotApi::new(Box::<TestBackend>::default()));
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let api = api.clone();
+                std::thread::spawn(move || api.generate(&format!("K{}", i)).unwrap())
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        api.verify_manifest().unwrap();
+        assert_eq!(api.list().unwrap().len(), 8);
+
+        // a manifest that can't be updated leaves the key where it was
+        let manifest = api.store.get(manifest::MANIFEST_NAME).unwrap().unwrap();
+        api.store.put(manifest::MANIFEST_NAME, b"garbage").unwrap();
+        assert!(api.delete("K0").is_err());
+        assert!(api.store.contains("K0").unwrap());
+        api.store.put(manifest::MANIFEST_NAME, &manifest).unwrap();
+        api.verify_manifest().unwrap();
+    }
+
+    #[test]
+    fn migrate_between_engines() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        api.generate_solana("B").unwrap();
+        let dir = unique_dir("migrate_test");
+        let target = crate::store::Engine::Sqlite.key_store(&dir);
+
+        assert_eq!(api.migrate(target.as_ref()).unwrap(), vec!["A", "B"]);
+        assert!(matches!(
+            api.migrate(target.as_ref()),
+            Err(ApiBackendErr::KeyExists)
+        ));
+        // same keystores, same manifest
+        let password = api.inner.get_encryption_key().unwrap();
+        manifest::verify(target.as_ref(), &password).unwrap();
+        assert_eq!(target.get("A").unwrap(), api.store.get("A").unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_restore() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        api.generate_solana("B").unwrap();
+        let conf = BTreeMap::from([("cheese_config.json".to_string(), b"{}".to_vec())]);
+        let bundle = api.backup(conf.clone()).unwrap();
+
+        let report = api.verify_backup(&bundle).unwrap();
+        assert_eq!(report.keys, vec!["A", "B"]);
+        assert_eq!(report.conf, vec!["cheese_config.json"]);
+        assert!(matches!(
+            api.restore(&bundle),
+            Err(ApiBackendErr::KeyExists)
+        ));
+
+        let fresh = HotApi::new(Box::<TestBackend>::default());
+        let mut corrupted = bundle.clone();
+        let at = corrupted.len() - 10;
+        corrupted[at] = if corrupted[at] == b'0' { b'1' } else { b'0' };
+        assert!(matches!(
+            fresh.restore(&corrupted),
+            Err(ApiBackendErr::Backup(BackupErr::Crypto(
+                CryptoErr::MacMismatch
+            )))
+        ));
+        // sealed correctly but a key is missing from it
+        let password = api.inner.get_encryption_key().unwrap();
+        let mut contents = backup::open(&password, &bundle).unwrap();
+        contents.store.remove("B");
+        let partial =
+            backup::seal(&mut rand::rngs::OsRng, &password, &TEST_KDF, &contents).unwrap();
+        assert!(matches!(
+            fresh.verify_backup(&partial),
+            Err(ApiBackendErr::Manifest(ManifestErr::Mismatch(_)))
+        ));
+        assert!(fresh.restore(&partial).is_err());
+        assert!(fresh.list().unwrap().is_empty());
+
+        let (report, restored_conf) = fresh.restore(&bundle).unwrap();
+        assert_eq!(report.keys, vec!["A", "B"]);
+        assert_eq!(restored_conf, conf);
+        assert_eq!(fresh.address("A").unwrap(), api.address("A").unwrap());
+        fresh.verify_manifest().unwrap();
+    }
+
+    #[test]
+    fn framed_master_keys() {
+        for master in [&b"short"[..], b"seventeen_bytes!!", &[0; 16], &[7; 300]] {
+            let framed = frame_master(master).unwrap();
+            assert!(framed.len() >= 16 && framed.len().is_multiple_of(2));
+            assert_eq!(unframe_master(framed).unwrap(), master);
+        }
+        assert_eq!(unframe_master(vec![0, 17, 1, 2]), None);
+        assert_eq!(unframe_master(vec![1; 16]), None);
+
+        let api = HotApi::new(Box::new(TestBackend::default().with_key(Some(b"short"))));
+        api.generate("A").unwrap();
+        let shares = api.split_master(2, 3, b"").unwrap();
+        let fresh = HotApi::new(Box::new(TestBackend::default().with_key(None)));
+        api.migrate(fresh.key_store()).unwrap();
+        fresh.recover_master(&shares[..2], b"", None).unwrap();
+        assert_eq!(fresh.backend().get_encryption_key().unwrap(), b"short");
+    }
+
+    #[test]
+    fn recover_from_shares() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        let master_shares = api.split_master(2, 3, b"").unwrap();
+        let key_shares = api.split_key("A", 2, 3, b"pw").unwrap();
+
+        let fresh = HotApi::new(Box::new(TestBackend::default().with_key(None)));
+        api.migrate(fresh.key_store()).unwrap();
+        assert!(matches!(
+            fresh.recover_master(&master_shares[..1], b"", None),
+            Err(ApiBackendErr::Slip39(Slip39Err::NotEnoughShares))
+        ));
+        assert!(matches!(
+            fresh.recover_master(&master_shares[1..], b"wrong", None),
+            Err(ApiBackendErr::UnverifiedMasterKey | ApiBackendErr::Manifest(ManifestErr::BadMac))
+        ));
+        assert!(!fresh.backend().has_encryption_key());
+        fresh
+            .recover_master(&master_shares[1..], b"", None)
+            .unwrap();
+        assert_eq!(fresh.address("A").unwrap(), api.address("A").unwrap());
+        assert!(matches!(
+            fresh.recover_master(&master_shares, b"", None),
+            Err(ApiBackendErr::MasterKeyExists)
+        ));
+
+        // an empty store can't tell a wrong passphrase, a backup can
+        let bundle = api.backup(BTreeMap::new()).unwrap();
+        let empty = HotApi::new(Box::new(TestBackend::default().with_key(None)));
+        assert!(matches!(
+            empty.recover_master(&master_shares[1..], b"", None),
+            Err(ApiBackendErr::UnverifiedMasterKey)
+        ));
+        assert!(empty
+            .recover_master(&master_shares[1..], b"wrong", Some(&bundle))
+            .is_err());
+        empty
+            .recover_master(&master_shares[1..], b"", Some(&bundle))
+            .unwrap();
+        empty.restore(&bundle).unwrap();
+
+        fresh.delete("A").unwrap();
+        fresh
+            .recover_key("A", KeyType::Evm, &[&key_shares[2], &key_shares[0]], b"pw")
+            .unwrap();
+        assert_eq!(fresh.address("A").unwrap(), api.address("A").unwrap());
+    }
+
+    #[test]
+    fn paper_backup() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        api.generate_solana("B").unwrap();
+        let a = api.export_paper("A", b"pass").unwrap();
+        let b = api.export_paper("B", b"pass").unwrap();
+        assert_eq!(
+            a.address.as_deref(),
+            Some(api.address("A").unwrap().as_str())
+        );
+
+        let fresh = HotApi::new(Box::<TestBackend>::default());
+        assert!(matches!(
+            fresh.import_paper("A", &a.to_text(), b"wrong"),
+            Err(ApiBackendErr::Paper(PaperErr::Crypto(
+                CryptoErr::MacMismatch
+            )))
+        ));
+        fresh.import_paper("A", &a.to_text(), b"pass").unwrap();
+        fresh.import_paper("C", &b.code, b"pass").unwrap();
+        assert_eq!(fresh.address("A").unwrap(), api.address("A").unwrap());
+        assert_eq!(
+            fresh.address_solana("C").unwrap(),
+            api.address_solana("B").unwrap()
+        );
+    }
+
+    #[test]
+    fn sign_transaction() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.import("A", KeyType::Evm, &[0x46; 32]).unwrap();
+        api.generate_solana("B").unwrap();
+        let mut tx: Transaction = serde_json::from_str(
+            r#"{"chainId": "0x1", "nonce": "0x9", "gasPrice": "0x4a817c800", "gas": "0x5208",
+                "to": "0x3535353535353535353535353535353535353535",
+                "value": "0xde0b6b3a7640000"}"#,
+        )
+        .unwrap();
+        let signed = api.sign_transaction("A", &tx).unwrap();
+        assert_eq!(
+            signed.from.to_string(),
+            api.address("A").unwrap().to_lowercase()
+        );
+        assert!(crate::evm::to_hex(&signed.raw).starts_with("0xf86c09"));
+
+        assert!(matches!(
+            api.sign_transaction("B", &tx),
+            Err(ApiBackendErr::Crypto(_))
+        ));
+        tx.from = Some(
+            "0x0000000000000000000000000000000000000001"
+                .parse()
+                .unwrap(),
+        );
+        assert!(matches!(
+            api.sign_transaction("A", &tx),
+            Err(ApiBackendErr::Evm(EvmErr::FromMismatch))
+        ));
+        tx.chain_id = None;
+        assert!(matches!(
+            api.sign_transaction("A", &tx),
+            Err(ApiBackendErr::Evm(EvmErr::MissingField("chainId")))
+        ));
+    }
+
+    #[test]
+    fn flashbots_signature() {
+        let owner = Arc::new(AtomicBool::new(true));
+        let api = HotApi::new(Box::new(
+            TestBackend::default()
+                .with_owner(&owner)
+                .with_policy(bot_policy),
+        ));
+        api.generate("SEARCHER").unwrap();
+        api.generate("BOT").unwrap();
+        let address = api.meta_address("SEARCHER").unwrap().unwrap();
+        // signed without asking
+        owner.store(false, Ordering::SeqCst);
+        let body = br#"{"jsonrpc":"2.0","id":1,"method":"eth_sendBundle","params":[]}"#;
+        let header = api.flashbots_signature("SEARCHER", body).unwrap();
+        let (from, signature) = header.split_once(':').unwrap();
+        assert_eq!(from, address.to_string());
+        let signature = crate::crypto::to_vec(signature).unwrap();
+        assert!(matches!(signature[64], 27 | 28));
+        assert_eq!(
+            evm::recover(&evm::flashbots_hash(body), &signature).unwrap(),
+            address
+        );
+        // the same as personal_sign of the body hash as text
+        owner.store(true, Ordering::SeqCst);
+        let text = evm::to_hex(&crate::crypto::keccak256(body.to_vec()));
+        let personal = api.personal_sign("SEARCHER", text.as_bytes()).unwrap();
+        assert_eq!(evm::to_hex(&personal.signature), header[43..]);
+        assert!(matches!(
+            api.flashbots_signature("BOT", body),
+            Err(ApiBackendErr::Policy(PolicyErr::RelayAuthNotAllowed))
+        ));
+    }
+
+    #[test]
+    fn clef() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        api.generate_solana("S").unwrap();
+        let rpc = |method: &str, params: serde_json::Value| {
+            let req = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "method": method,
+                "params": params
+            });
+            clef::handle(&api, &serde_json::to_vec(&req).unwrap())
+        };
+        let address = api.meta_address("A").unwrap().unwrap();
+        let res = rpc("account_list", serde_json::json!([]));
+        assert_eq!(res["id"], 7);
+        assert_eq!(res["result"], serde_json::json!([address]));
+
+        // the way geth's external signer sends it
+        let args = serde_json::json!({
+            "from": address.to_string().to_uppercase().replace("0X", "0x"),
+            "to": "0x3535353535353535353535353535353535353535",
+            "gas": "0x5208",
+            "gasPrice": null,
+            "maxFeePerGas": "0x6fc23ac00",
+            "maxPriorityFeePerGas": "0x3b9aca00",
+            "value": "0x1",
+            "nonce": "0x0",
+            "data": null,
+            "input": "0x",
+            "chainId": "0x1"
+        });
+        let res = rpc("account_signTransaction", serde_json::json!([args]));
+        let tx: Transaction = serde_json::from_value(serde_json::json!({
+            "chainId": 1,
+            "nonce": 0,
+            "gas": 21000,
+            "maxFeePerGas": "30000000000",
+            "maxPriorityFeePerGas": "1000000000",
+            "to": "0x3535353535353535353535353535353535353535",
+            "value": 1
+        }))
+        .unwrap();
+        let signed = api.sign_transaction("A", &tx).unwrap();
+        assert_eq!(res["result"]["raw"], evm::to_hex(&signed.raw));
+        let json = &res["result"]["tx"];
+        assert_eq!(json["type"], "0x2");
+        assert_eq!(json["input"], "0x");
+        assert_eq!(json["accessList"], serde_json::json!([]));
+        assert_eq!(json["hash"], signed.hash.to_string());
+        assert!(!json["r"].as_str().unwrap().starts_with("0x0"));
+
+        let res = rpc(
+            "account_signData",
+            serde_json::json!(["text/plain", address, "0x48656c6c6f20576f726c64"]),
+        );
+        let signed = api.personal_sign("A", b"Hello World").unwrap();
+        assert_eq!(res["result"], evm::to_hex(&signed.signature));
+        let typed_data = crate::evm::known::test::safe_tx(0);
+        let res = rpc(
+            "account_signTypedData",
+            serde_json::json!([address, typed_data]),
+        );
+        let signed = api.sign_typed_data("A", &typed_data).unwrap();
+        assert_eq!(res["result"], evm::to_hex(&signed.signature));
+
+        let unknown = "0x3535353535353535353535353535353535353535";
+        let res = rpc(
+            "account_signData",
+            serde_json::json!(["text/plain", unknown, "0x00"]),
+        );
+        assert_eq!(res["error"]["code"], -32000);
+        let res = rpc(
+            "account_signData",
+            serde_json::json!(["text/clique", address, "0x00"]),
+        );
+        assert_eq!(res["error"]["code"], -32602);
+        assert_eq!(
+            rpc("eth_sign", serde_json::json!([]))["error"]["code"],
+            -32601
+        );
+        assert_eq!(clef::handle(&api, b"{")["error"]["code"], -32700);
+    }
+
+    #[test]
+    fn sign_solana() {
+        use crate::solana::{tx::test::transfer, Pubkey};
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate_solana("S").unwrap();
+        api.generate("A").unwrap();
+        let me: Pubkey = api.address_solana("S").unwrap().parse().unwrap();
+        let payload = SolanaPayload::Transaction(transfer(me, Pubkey([2; 32]), 5000));
+        let signed = api.sign_solana("S", &payload).unwrap();
+        assert_eq!(signed.signer, me);
+        assert!(signed.signed_transaction.unwrap()[1..65]
+            .iter()
+            .any(|b| *b != 0));
+        let message = SolanaPayload::OffchainMessage("Hello".into());
+        assert_eq!(api.sign_solana("S", &message).unwrap().signer, me);
+        assert!(matches!(
+            api.sign_solana("A", &payload),
+            Err(ApiBackendErr::Crypto(CryptoErr::KeyTypeMismatch(
+                KeyType::Evm
+            )))
+        ));
+        let other = SolanaPayload::Transaction(transfer(Pubkey([1; 32]), me, 1));
+        assert!(matches!(
+            api.sign_solana("S", &other),
+            Err(ApiBackendErr::Solana(SolanaErr::NotASigner))
+        ));
+    }
+
+    #[test]
+    fn sign_messages() {
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        api.generate("RAW").unwrap();
+        let signed = api.personal_sign("A", b"Hello World").unwrap();
+        assert_eq!(signed.hash.0, evm::eip191_hash(b"Hello World"));
+        assert_eq!(signed.signature.len(), 65);
+        let req = VerifyRequest {
+            payload: evm::Payload::Message("Hello World".into()),
+            signature: signed.signature.clone(),
+        };
+        assert!(api.verify_signature("A", &req).unwrap().valid);
+        let res = api.verify_signature("RAW", &req).unwrap();
+        assert!(!res.valid);
+        assert_eq!(res.signer, signed.from);
+
+        assert!(matches!(
+            api.sign_hash("A", &signed.hash),
+            Err(ApiBackendErr::Policy(PolicyErr::RawHashNotAllowed))
+        ));
+        let raw = api.sign_hash("RAW", &signed.hash).unwrap();
+        let req = VerifyRequest {
+            payload: evm::Payload::Hash(signed.hash),
+            signature: raw.signature,
+        };
+        assert!(api.verify_signature("RAW", &req).unwrap().valid);
+
+        let typed_data: TypedData = serde_json::from_value(serde_json::json!({
+            "types": {"Login": [{"name": "nonce", "type": "uint256"}]},
+            "primaryType": "Login",
+            "domain": {"name": "app", "chainId": "0x1"},
+            "message": {"nonce": "12"}
+        }))
+        .unwrap();
+        let signed = api.sign_typed_data("A", &typed_data).unwrap();
+        let req = VerifyRequest {
+            payload: evm::Payload::TypedData(typed_data),
+            signature: signed.signature,
+        };
+        assert!(api.verify_signature("A", &req).unwrap().valid);
+    }
+
+    #[test]
+    fn known_typed_data_policy() {
+        use crate::evm::known::test::{cow_order, safe_tx, safe_tx_refund};
+        let api = HotApi::new(Box::<TestBackend>::default());
+        api.generate("A").unwrap();
+        api.generate("COW").unwrap();
+        let zero = "0x0000000000000000000000000000000000000000";
+        api.sign_typed_data("A", &cow_order(zero)).unwrap();
+        let own = api.address("A").unwrap();
+        api.sign_typed_data("A", &cow_order(&own)).unwrap();
+        assert!(matches!(
+            api.sign_typed_data(
+                "A",
+                &cow_order("0x1111111111111111111111111111111111111111")
+            ),
+            Err(ApiBackendErr::Policy(PolicyErr::ForeignReceiver))
+        ));
+        assert!(matches!(
+            api.sign_typed_data("COW", &cow_order(zero)),
+            Err(ApiBackendErr::Policy(PolicyErr::TokenNotAllowed(_)))
+        ));
+
+        api.sign_typed_data("A", &safe_tx(0)).unwrap();
+        assert!(matches!(
+            api.sign_typed_data("A", &safe_tx(1)),
+            Err(ApiBackendErr::Policy(PolicyErr::DelegateCall))
+        ));
+        // gas refunds go to the executor, the signer or nowhere, in allowed tokens
+        let other = "0x3333333333333333333333333333333333333333";
+        api.sign_typed_data("A", &safe_tx_refund(0, 1, zero, zero))
+            .unwrap();
+        api.sign_typed_data("A", &safe_tx_refund(0, 1, zero, &own))
+            .unwrap();
+        api.sign_typed_data("A", &safe_tx_refund(0, 0, zero, other))
+            .unwrap();
+        assert!(matches!(
+            api.sign_typed_data("A", &safe_tx_refund(0, 1, zero, other)),
+            Err(ApiBackendErr::Policy(PolicyErr::ForeignReceiver))
+        ));
+        assert!(matches!(
+            api.sign_typed_data("COW", &safe_tx_refund(0, 1, other, zero)),
+            Err(ApiBackendErr::Policy(PolicyErr::TokenNotAllowed(t))) if t.to_string() == other
+        ));
+    }
+
+    #[test]
+    fn policy_and_auto_approve() {
+        let owner = Arc::new(AtomicBool::new(true));
+        let api = HotApi::new(Box::new(
+            TestBackend::default()
+                .with_owner(&owner)
+                .with_policy(bot_policy),
+        ));
+        api.generate("BOT").unwrap();
+        api.generate("HUMAN").unwrap();
+        owner.store(false, Ordering::SeqCst);
+        let tx = Transaction {
+            chain_id: Some(1),
+            nonce: 0,
+            gas: 21000,
+            gas_price: Some(1),
+            to: Some(Address::from([0x35; 20])),
+            value: 100,
+            ..Default::default()
+        };
+        // inside the policy the bot needs nobody, the other key still needs its owner
+        api.sign_transaction("BOT", &tx).unwrap();
+        assert!(matches!(
+            api.sign_transaction("HUMAN", &tx),
+            Err(ApiBackendErr::NotDeviceOwner)
+        ));
+        // outside it nobody is asked
+        for (tx, expected) in [
+            (
+                Transaction {
+                    chain_id: Some(5),
+                    ..tx.clone()
+                },
+                "ChainNotAllowed(5)",
+            ),
+            (
+                Transaction {
+                    value: 101,
+                    ..tx.clone()
+                },
+                "ValueTooHigh",
+            ),
+            (
+                Transaction {
+                    value: 51,
+                    ..tx.clone()
+                },
+                "OverDailyLimit",
+            ),
+        ] {
+            match api.sign_transaction("BOT", &tx) {
+                Err(ApiBackendErr::Policy(e)) => assert_eq!(format!("{:?}", e), expected),
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+        }
+        api.sign_transaction(
+            "BOT",
+            &Transaction {
+                value: 50,
+                ..tx.clone()
+            },
+        )
+        .unwrap();
+        // nor is the owner once a key is over its daily limit
+        owner.store(true, Ordering::SeqCst);
+        api.sign_transaction("HUMAN", &tx).unwrap();
+        owner.store(false, Ordering::SeqCst);
+        assert!(matches!(
+            api.sign_transaction("HUMAN", &Transaction { value: 51, ..tx }),
+            Err(ApiBackendErr::Policy(PolicyErr::OverDailyLimit))
+        ));
     }
 }
@@ -0,0 +1,549 @@
+//! [SLIP-39](https://github.com/satoshilabs/slips/blob/master/slip-0039.md) Shamir backups.
+//! A secret is encrypted with an optional passphrase and split into mnemonic shares, any
+//! `threshold` of them recover it. `split` makes a single group of shares, `combine` takes
+//! shares from any SLIP-39 implementation, groups included.
+use err_mac::create_err_with_impls;
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, Rng};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use zeroize::Zeroize;
+
+const WORDLIST: &str = include_str!("wordlist.txt");
+const RADIX_BITS: u32 = 10;
+/// identifier, extendable flag, iteration exponent, group and member fields
+const HEADER_WORDS: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+const CUSTOMIZATION: &[u8] = b"shamir";
+const CUSTOMIZATION_EXTENDABLE: &[u8] = b"shamir_extendable";
+const MIN_SECRET_LEN: usize = 16;
+const MAX_SHARES: u8 = 16;
+const DIGEST_LEN: usize = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const BASE_ITERATIONS: u32 = 10_000;
+const ROUNDS: u8 = 4;
+/// 20k PBKDF2 iterations in total, what the reference implementation uses
+const ITERATION_EXPONENT: u8 = 1;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub Slip39Err,
+    SecretLength(usize),
+    InvalidThreshold,
+    UnknownWord(String),
+    InvalidChecksum,
+    InvalidMnemonic,
+    Mismatch,
+    NotEnoughShares,
+    InvalidDigest
+    ;
+);
+
+/// one decoded mnemonic
+#[derive(Clone)]
+struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Vec<u8>,
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+fn words() -> Vec<&'static str> {
+    WORDLIST.lines().collect()
+}
+
+const fn gf_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut poly: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = poly as u8;
+        log[poly as usize] = i as u8;
+        // multiply by the generator x + 1, reduce by the Rijndael polynomial
+        poly ^= poly << 1;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11b;
+        }
+        i += 1;
+    }
+    (exp, log)
+}
+
+const GF: ([u8; 255], [u8; 256]) = gf_tables();
+
+fn gf_mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    GF.0[(GF.1[a as usize] as usize + GF.1[b as usize] as usize) % 255]
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    GF.0[(GF.1[a as usize] as usize + 255 - GF.1[b as usize] as usize) % 255]
+}
+
+/// the polynomial through `points` evaluated at `x`, bytewise
+fn interpolate(points: &[(u8, Vec<u8>)], x: u8) -> Vec<u8> {
+    if let Some((_, y)) = points.iter().find(|(xi, _)| *xi == x) {
+        return y.clone();
+    }
+    let mut out = vec![0u8; points[0].1.len()];
+    for (i, (xi, yi)) in points.iter().enumerate() {
+        let mut num = 1;
+        let mut den = 1;
+        for (j, (xj, _)) in points.iter().enumerate() {
+            if i != j {
+                num = gf_mul(num, x ^ xj);
+                den = gf_mul(den, xi ^ xj);
+            }
+        }
+        let basis = gf_div(num, den);
+        for (o, y) in out.iter_mut().zip(yi) {
+            *o ^= gf_mul(basis, *y);
+        }
+    }
+    out
+}
+
+fn digest(random: &[u8], secret: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(random).expect("hmac takes any key size");
+    mac.update(secret);
+    mac.finalize().into_bytes()[..DIGEST_LEN].to_vec()
+}
+
+/// `count` points, indexed from 0, any `threshold` of which give back `secret` at 255
+fn split_secret<R: Rng + CryptoRng>(
+    rng: &mut R,
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+) -> Vec<(u8, Vec<u8>)> {
+    if threshold == 1 {
+        return (0..count).map(|i| (i, secret.to_vec())).collect();
+    }
+    let mut random = vec![0u8; secret.len() - DIGEST_LEN];
+    rng.fill_bytes(&mut random);
+    let mut digest_share = digest(&random, secret);
+    digest_share.extend_from_slice(&random);
+    random.zeroize();
+
+    let mut base: Vec<(u8, Vec<u8>)> = (0..threshold - 2)
+        .map(|i| {
+            let mut y = vec![0u8; secret.len()];
+            rng.fill_bytes(&mut y);
+            (i, y)
+        })
+        .collect();
+    base.push((DIGEST_INDEX, digest_share));
+    base.push((SECRET_INDEX, secret.to_vec()));
+    let mut shares: Vec<_> = base[..threshold as usize - 2].to_vec();
+    for i in threshold - 2..count {
+        shares.push((i, interpolate(&base, i)));
+    }
+    base.iter_mut().for_each(|(_, y)| y.zeroize());
+    shares
+}
+
+fn recover_secret(threshold: u8, points: &[(u8, Vec<u8>)]) -> Result<Vec<u8>, Slip39Err> {
+    if threshold == 1 {
+        return Ok(points[0].1.clone());
+    }
+    let mut secret = interpolate(points, SECRET_INDEX);
+    let mut digest_share = interpolate(points, DIGEST_INDEX);
+    let ok = digest(&digest_share[DIGEST_LEN..], &secret) == digest_share[..DIGEST_LEN];
+    digest_share.zeroize();
+    if !ok {
+        secret.zeroize();
+        return Err(Slip39Err::InvalidDigest);
+    }
+    Ok(secret)
+}
+
+/// the 4 round Feistel network keyed by PBKDF2 of the passphrase
+fn feistel(
+    secret: &[u8],
+    passphrase: &[u8],
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    encrypt: bool,
+) -> Vec<u8> {
+    let half = secret.len() / 2;
+    let (mut left, mut right) = (secret[..half].to_vec(), secret[half..].to_vec());
+    let mut salt = Vec::new();
+    if !extendable {
+        salt.extend_from_slice(CUSTOMIZATION);
+        salt.extend_from_slice(&identifier.to_be_bytes());
+    }
+    let iterations = (BASE_ITERATIONS << iteration_exponent) / ROUNDS as u32;
+    let mut rounds: Vec<u8> = (0..ROUNDS).collect();
+    if !encrypt {
+        rounds.reverse();
+    }
+    for round in rounds {
+        let mut password = vec![round];
+        password.extend_from_slice(passphrase);
+        let mut round_salt = salt.clone();
+        round_salt.extend_from_slice(&right);
+        let mut f = vec![0u8; half];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(&password, &round_salt, iterations, &mut f);
+        password.zeroize();
+        for (l, f) in left.iter_mut().zip(f.iter()) {
+            *l ^= f;
+        }
+        f.zeroize();
+        std::mem::swap(&mut left, &mut right);
+    }
+    right.extend_from_slice(&left);
+    left.zeroize();
+    right
+}
+
+fn polymod(values: impl Iterator<Item = u32>) -> u32 {
+    const GEN: [u32; 10] = [
+        0xe0e040, 0x1c1c080, 0x3838100, 0x7070200, 0xe0e0009, 0x1c0c2412, 0x38086c24, 0x3090fc48,
+        0x21b1f890, 0x3f3f120,
+    ];
+    let mut chk = 1u32;
+    for v in values {
+        let b = chk >> 20;
+        chk = ((chk & 0xfffff) << 10) ^ v;
+        for (i, g) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn customization(extendable: bool) -> &'static [u8] {
+    match extendable {
+        true => CUSTOMIZATION_EXTENDABLE,
+        false => CUSTOMIZATION,
+    }
+}
+
+/// big endian bits into 10 bit words
+#[derive(Default)]
+struct WordWriter {
+    words: Vec<u16>,
+    acc: u16,
+    len: u32,
+}
+
+impl WordWriter {
+    fn push(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1) as u16;
+            self.len += 1;
+            if self.len == RADIX_BITS {
+                self.words.push(self.acc);
+                (self.acc, self.len) = (0, 0);
+            }
+        }
+    }
+}
+
+/// reads `bits` bits starting at bit `from` of `words`
+fn read_bits(words: &[u16], from: usize, bits: usize) -> u32 {
+    (from..from + bits).fold(0, |acc, i| {
+        let word = words[i / RADIX_BITS as usize];
+        let bit = (word >> (RADIX_BITS as usize - 1 - i % RADIX_BITS as usize)) & 1;
+        (acc << 1) | bit as u32
+    })
+}
+
+impl Share {
+    fn to_mnemonic(&self) -> String {
+        let mut w = WordWriter::default();
+        w.push(self.identifier as u32, 15);
+        w.push(self.extendable as u32, 1);
+        w.push(self.iteration_exponent as u32, 4);
+        w.push(self.group_index as u32, 4);
+        w.push(self.group_threshold as u32 - 1, 4);
+        w.push(self.group_count as u32 - 1, 4);
+        w.push(self.member_index as u32, 4);
+        w.push(self.member_threshold as u32 - 1, 4);
+        let value_bits = self.value.len() as u32 * 8;
+        w.push(0, (RADIX_BITS - value_bits % RADIX_BITS) % RADIX_BITS);
+        for b in self.value.iter() {
+            w.push(*b as u32, 8);
+        }
+        let chk = polymod(
+            customization(self.extendable)
+                .iter()
+                .map(|b| *b as u32)
+                .chain(w.words.iter().map(|w| *w as u32))
+                .chain([0; CHECKSUM_WORDS]),
+        ) ^ 1;
+        for i in (0..CHECKSUM_WORDS).rev() {
+            w.push(chk >> (RADIX_BITS * i as u32), RADIX_BITS);
+        }
+        let list = words();
+        w.words
+            .iter()
+            .map(|i| list[*i as usize])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn from_mnemonic(mnemonic: &str) -> Result<Self, Slip39Err> {
+        let list = words();
+        let indices = mnemonic
+            .split_whitespace()
+            .map(|word| {
+                let word = word.to_lowercase();
+                list.binary_search(&word.as_str())
+                    .map(|i| i as u16)
+                    .map_err(|_| Slip39Err::UnknownWord(word))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // a 128 bit secret needs 13 value words
+        if indices.len() < HEADER_WORDS + 13 + CHECKSUM_WORDS {
+            return Err(Slip39Err::InvalidMnemonic);
+        }
+        let extendable = read_bits(&indices, 15, 1) == 1;
+        let chk = polymod(
+            customization(extendable)
+                .iter()
+                .map(|b| *b as u32)
+                .chain(indices.iter().map(|w| *w as u32)),
+        );
+        if chk != 1 {
+            return Err(Slip39Err::InvalidChecksum);
+        }
+        let value_words = &indices[HEADER_WORDS..indices.len() - CHECKSUM_WORDS];
+        let value_bits = value_words.len() * RADIX_BITS as usize;
+        let padding = value_bits % 16;
+        if padding > 8 || read_bits(value_words, 0, padding) != 0 {
+            return Err(Slip39Err::InvalidMnemonic);
+        }
+        let value = (padding..value_bits)
+            .step_by(8)
+            .map(|from| read_bits(value_words, from, 8) as u8)
+            .collect();
+        let header = |from, bits| read_bits(&indices, from, bits) as u8;
+        let share = Share {
+            identifier: read_bits(&indices, 0, 15) as u16,
+            extendable,
+            iteration_exponent: header(16, 4),
+            group_index: header(20, 4),
+            group_threshold: header(24, 4) + 1,
+            group_count: header(28, 4) + 1,
+            member_index: header(32, 4),
+            member_threshold: header(36, 4) + 1,
+            value,
+        };
+        if share.group_threshold > share.group_count {
+            return Err(Slip39Err::InvalidMnemonic);
+        }
+        Ok(share)
+    }
+}
+
+/// `count` mnemonics for `secret`, any `threshold` of which recover it. `secret` has to be an
+/// even number of bytes, at least 16
+pub fn split<R: Rng + CryptoRng>(
+    rng: &mut R,
+    secret: &[u8],
+    passphrase: &[u8],
+    threshold: u8,
+    count: u8,
+) -> Result<Vec<String>, Slip39Err> {
+    if secret.len() < MIN_SECRET_LEN || secret.len() % 2 == 1 {
+        return Err(Slip39Err::SecretLength(secret.len()));
+    }
+    // more than one share of a 1-of-n split would just be copies
+    if threshold == 0 || threshold > count || count > MAX_SHARES || (threshold == 1 && count > 1) {
+        return Err(Slip39Err::InvalidThreshold);
+    }
+    let identifier = rng.gen::<u16>() & 0x7fff;
+    let mut encrypted = feistel(
+        secret,
+        passphrase,
+        identifier,
+        false,
+        ITERATION_EXPONENT,
+        true,
+    );
+    let points = split_secret(rng, threshold, count, &encrypted);
+    encrypted.zeroize();
+    Ok(points
+        .into_iter()
+        .map(|(member_index, value)| {
+            Share {
+                identifier,
+                extendable: false,
+                iteration_exponent: ITERATION_EXPONENT,
+                group_index: 0,
+                group_threshold: 1,
+                group_count: 1,
+                member_index,
+                member_threshold: threshold,
+                value,
+            }
+            .to_mnemonic()
+        })
+        .collect())
+}
+
+/// the secret behind enough `mnemonics` of one set. a wrong passphrase isn't detected, it
+/// gives a different secret
+pub fn combine<S: AsRef<str>>(mnemonics: &[S], passphrase: &[u8]) -> Result<Vec<u8>, Slip39Err> {
+    let shares = mnemonics
+        .iter()
+        .map(|m| Share::from_mnemonic(m.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = shares.first().ok_or(Slip39Err::NotEnoughShares)?;
+    let same_set = |s: &Share| {
+        (
+            s.identifier,
+            s.extendable,
+            s.iteration_exponent,
+            s.group_threshold,
+            s.group_count,
+            s.value.len(),
+        ) == (
+            first.identifier,
+            first.extendable,
+            first.iteration_exponent,
+            first.group_threshold,
+            first.group_count,
+            first.value.len(),
+        )
+    };
+    if !shares.iter().all(same_set) {
+        return Err(Slip39Err::Mismatch);
+    }
+
+    let mut groups: BTreeMap<u8, Vec<&Share>> = BTreeMap::new();
+    for share in shares.iter() {
+        groups.entry(share.group_index).or_default().push(share);
+    }
+    let mut group_points = Vec::new();
+    for (group_index, members) in groups {
+        let threshold = members[0].member_threshold;
+        let mut points: Vec<(u8, Vec<u8>)> = Vec::new();
+        for m in members {
+            if m.member_threshold != threshold {
+                return Err(Slip39Err::Mismatch);
+            }
+            if !points.iter().any(|(x, _)| *x == m.member_index) {
+                points.push((m.member_index, m.value.clone()));
+            }
+        }
+        if points.len() >= threshold as usize {
+            points.truncate(threshold as usize);
+            group_points.push((group_index, recover_secret(threshold, &points)?));
+        }
+        points.iter_mut().for_each(|(_, y)| y.zeroize());
+    }
+    if group_points.len() < first.group_threshold as usize {
+        return Err(Slip39Err::NotEnoughShares);
+    }
+    group_points.truncate(first.group_threshold as usize);
+    let res = recover_secret(first.group_threshold, &group_points);
+    group_points.iter_mut().for_each(|(_, y)| y.zeroize());
+    let mut encrypted = res?;
+    let secret = feistel(
+        &encrypted,
+        passphrase,
+        first.identifier,
+        first.extendable,
+        first.iteration_exponent,
+        false,
+    );
+    encrypted.zeroize();
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn wordlist() {
+        let list = words();
+        assert_eq!(list.len(), 1 << RADIX_BITS);
+        assert!(list.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn spec_vectors() {
+        let single = ["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"];
+        assert_eq!(
+            hex(&combine(&single, b"TREZOR").unwrap()),
+            "bb54aac4b89dc868ba37d9cc21b2cece"
+        );
+        let two_of_three = [
+            "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+            "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+        ];
+        assert_eq!(
+            hex(&combine(&two_of_three, b"TREZOR").unwrap()),
+            "b43ceb7e57a0ea8766221624d01b0864"
+        );
+        assert!(matches!(
+            combine(&two_of_three[..1], b"TREZOR"),
+            Err(Slip39Err::NotEnoughShares)
+        ));
+        let typo = single[0].replace("keyboard", "kidney");
+        assert!(matches!(
+            combine(&[typo], b"TREZOR"),
+            Err(Slip39Err::InvalidChecksum)
+        ));
+    }
+
+    #[test]
+    fn split_combine() {
+        let mut rng = rand::thread_rng();
+        let secret: Vec<u8> = (0..32).collect();
+        let shares = split(&mut rng, &secret, b"pw", 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        assert_eq!(shares[0].split(' ').count(), 33);
+        assert_eq!(combine(&shares[2..], b"pw").unwrap(), secret);
+        assert_eq!(
+            combine(&[&shares[4], &shares[0], &shares[3]], b"pw").unwrap(),
+            secret
+        );
+        assert_ne!(combine(&shares[..3], b"other").unwrap(), secret);
+        assert!(matches!(
+            combine(&shares[..2], b"pw"),
+            Err(Slip39Err::NotEnoughShares)
+        ));
+        let other_set = split(&mut rng, &secret, b"pw", 3, 5).unwrap();
+        assert!(matches!(
+            combine(&[&shares[0], &shares[1], &other_set[2]], b"pw"),
+            Err(Slip39Err::Mismatch)
+        ));
+        assert!(matches!(
+            split(&mut rng, &secret[..15], b"", 2, 3),
+            Err(Slip39Err::SecretLength(15))
+        ));
+        assert!(matches!(
+            split(&mut rng, &secret, b"", 4, 3),
+            Err(Slip39Err::InvalidThreshold)
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+//! Signing with the stored ed25519 keypairs so Solana consumers get signatures instead of
+//! the secret. Transactions travel base64 encoded the way `sendTransaction` takes them,
+//! keys and signatures are base58.
+use crate::evm::printable;
+use err_mac::create_err_with_impls;
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use solana_keypair::Keypair;
+use solana_signer::Signer;
+
+mod offchain;
+pub mod tx;
+pub use offchain::offchain_message;
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub SolanaErr,
+    Truncated,
+    TrailingBytes,
+    UnsupportedVersion(u8),
+    InvalidAccountIndex,
+    SignatureCount,
+    NotASigner,
+    InvalidMessage
+    ;
+);
+
+/// an account address or program id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pubkey(pub [u8; 32]);
+
+impl std::fmt::Display for Pubkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&bs58::encode(self.0).into_string())
+    }
+}
+
+impl std::str::FromStr for Pubkey {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        bs58::decode(s)
+            .into_vec()
+            .ok()
+            .and_then(|v| v.try_into().ok())
+            .map(Self)
+            .ok_or(format!("expected a base58 public key, got '{}'", s))
+    }
+}
+
+impl Serialize for Pubkey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pubkey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = std::borrow::Cow::<str>::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+/// serde for bytes as standard base64
+pub mod bytes_base64 {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = std::borrow::Cow::<str>::deserialize(deserializer)?;
+        STANDARD.decode(s.as_bytes()).map_err(D::Error::custom)
+    }
+}
+
+/// what to sign, `{"transaction": "<base64>"}` for a serialized legacy or v0 transaction,
+/// signature slots may be zeroed, or `{"offchainMessage": "text"}`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SolanaPayload {
+    Transaction(#[serde(with = "bytes_base64")] Vec<u8>),
+    OffchainMessage(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolanaSignature {
+    pub signer: Pubkey,
+    /// base58, the way explorers and `getSignatureStatuses` take it
+    pub signature: String,
+    /// the transaction with our signature in its slot, ready to send
+    #[serde(
+        default,
+        with = "bytes_base64_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub signed_transaction: Option<Vec<u8>>,
+}
+
+mod bytes_base64_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => super::bytes_base64::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrap(#[serde(with = "super::bytes_base64")] Vec<u8>);
+        Ok(Option::<Wrap>::deserialize(deserializer)?.map(|w| w.0))
+    }
+}
+
+impl SolanaPayload {
+    /// one line for the approval prompt
+    pub fn describe(&self) -> Result<String, SolanaErr> {
+        match self {
+            SolanaPayload::Transaction(raw) => Ok(tx::parse(raw)?.message.describe()),
+            SolanaPayload::OffchainMessage(text) => {
+                offchain_message(text)?;
+                Ok(format!("off-chain message \"{}\"", printable(text)))
+            }
+        }
+    }
+
+    pub fn sign(&self, keypair: &Keypair) -> Result<SolanaSignature, SolanaErr> {
+        let signer = Pubkey(keypair.pubkey().to_bytes());
+        let res = match self {
+            SolanaPayload::Transaction(raw) => {
+                let parsed = tx::parse(raw)?;
+                let slot = parsed
+                    .message
+                    .signers()
+                    .iter()
+                    .position(|k| *k == signer)
+                    .ok_or(SolanaErr::NotASigner)?;
+                let signature = keypair.sign_message(&raw[parsed.message_at..]);
+                let start = parsed.signatures_at + 64 * slot;
+                let mut signed = raw.clone();
+                signed[start..start + 64].copy_from_slice(signature.as_ref());
+                SolanaSignature {
+                    signer,
+                    signature: signature.to_string(),
+                    signed_transaction: Some(signed),
+                }
+            }
+            SolanaPayload::OffchainMessage(text) => SolanaSignature {
+                signer,
+                signature: keypair.sign_message(&offchain_message(text)?).to_string(),
+                signed_transaction: None,
+            },
+        };
+        Ok(res)
+    }
+}
+
+/// reads the compact-u16 length prefix Solana puts before every array
+fn short_vec(bytes: &mut &[u8]) -> Result<usize, SolanaErr> {
+    let mut len = 0usize;
+    for i in 0..3 {
+        let (b, rest) = bytes.split_first().ok_or(SolanaErr::Truncated)?;
+        *bytes = rest;
+        len |= ((b & 0x7f) as usize) << (7 * i);
+        if b & 0x80 == 0 {
+            return Ok(len);
+        }
+    }
+    Err(SolanaErr::Truncated)
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8], SolanaErr> {
+    if bytes.len() < n {
+        return Err(SolanaErr::Truncated);
+    }
+    let (head, rest) = bytes.split_at(n);
+    *bytes = rest;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn short_vecs() {
+        for (encoded, len) in [
+            (&[0x00][..], 0),
+            (&[0x7f], 127),
+            (&[0x80, 0x01], 128),
+            (&[0xff, 0xff, 0x03], 65535),
+        ] {
+            let mut bytes = encoded;
+            assert_eq!(short_vec(&mut bytes).unwrap(), len);
+            assert!(bytes.is_empty());
+        }
+        assert!(short_vec(&mut &[0x80][..]).is_err());
+    }
+
+    #[test]
+    fn pubkeys() {
+        let system = "11111111111111111111111111111111";
+        let key: Pubkey = system.parse().unwrap();
+        assert_eq!(key, Pubkey([0; 32]));
+        assert_eq!(key.to_string(), system);
+        assert!("0x00".parse::<Pubkey>().is_err());
+    }
+
+    #[test]
+    fn signs() {
+        let keypair = Keypair::new_from_array([7; 32]);
+        let me = Pubkey(keypair.pubkey().to_bytes());
+        let raw = tx::test::transfer(me, Pubkey([2; 32]), 5000);
+        let payload: SolanaPayload = serde_json::from_value(serde_json::json!({
+            "transaction": base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &raw)
+        }))
+        .unwrap();
+        assert!(payload
+            .describe()
+            .unwrap()
+            .contains("transfer 5000 lamports"));
+        let signed = payload.sign(&keypair).unwrap();
+        assert_eq!(signed.signer, me);
+        let signed_tx = signed.signed_transaction.unwrap();
+        assert_eq!(signed_tx[65..], raw[65..]);
+        let signature = keypair.sign_message(&raw[65..]);
+        assert!(signature.verify(&me.0, &raw[65..]));
+        assert_eq!(signed_tx[1..65], *signature.as_ref());
+        assert_eq!(signed.signature, signature.to_string());
+
+        // someone else's transaction
+        let other = SolanaPayload::Transaction(tx::test::transfer(Pubkey([1; 32]), me, 1));
+        assert!(matches!(other.sign(&keypair), Err(SolanaErr::NotASigner)));
+
+        let message = SolanaPayload::OffchainMessage("Hello".into());
+        assert_eq!(message.describe().unwrap(), "off-chain message \"Hello\"");
+        let signed = message.sign(&keypair).unwrap();
+        assert_eq!(signed.signed_transaction, None);
+        let expected = keypair.sign_message(&offchain_message("Hello").unwrap());
+        assert_eq!(signed.signature, expected.to_string());
+        let json = serde_json::to_value(&signed).unwrap();
+        assert_eq!(json["signer"], me.to_string());
+        assert!(json.get("signedTransaction").is_none());
+    }
+}
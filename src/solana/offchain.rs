@@ -0,0 +1,41 @@
+//! off-chain messages the way `solana sign-offchain-message` frames them, so a signature
+//! over one can never pass as a transaction
+use super::SolanaErr;
+
+pub const SIGNING_DOMAIN: &[u8; 16] = b"\xffsolana offchain";
+/// what a Ledger shows, 1232 byte packets less the header
+const MAX_LEN_LEDGER: usize = 1212;
+const MAX_LEN: usize = u16::MAX as usize - 20;
+
+/// the framed version 0 message, the format byte picks the narrowest of restricted ASCII,
+/// UTF-8 a Ledger can show and any UTF-8
+pub fn offchain_message(text: &str) -> Result<Vec<u8>, SolanaErr> {
+    let format = match text.len() {
+        0 => return Err(SolanaErr::InvalidMessage),
+        len if len <= MAX_LEN_LEDGER && text.bytes().all(|b| (0x20..=0x7e).contains(&b)) => 0,
+        len if len <= MAX_LEN_LEDGER => 1,
+        len if len <= MAX_LEN => 2,
+        _ => return Err(SolanaErr::InvalidMessage),
+    };
+    let mut out = SIGNING_DOMAIN.to_vec();
+    out.extend_from_slice(&[0, format]);
+    out.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn framing() {
+        let framed = offchain_message("Hello").unwrap();
+        assert_eq!(&framed[..16], SIGNING_DOMAIN);
+        assert_eq!(&framed[16..], &[0, 0, 5, 0, b'H', b'e', b'l', b'l', b'o']);
+        assert_eq!(offchain_message("naïve").unwrap()[17], 1);
+        assert_eq!(offchain_message(&"a\n".repeat(1000)).unwrap()[17], 2);
+        assert!(offchain_message("").is_err());
+        assert!(offchain_message(&"a".repeat(MAX_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,434 @@
+//! the legacy and v0 transaction wire format, and what the instructions in it do for the
+//! approval prompt. only System, SPL Token and compute budget instructions are decoded
+use super::{short_vec, take, Pubkey, SolanaErr};
+
+pub const SYSTEM_PROGRAM: &str = "11111111111111111111111111111111";
+pub const TOKEN_PROGRAM: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+pub const TOKEN_2022_PROGRAM: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+pub const COMPUTE_BUDGET_PROGRAM: &str = "ComputeBudget111111111111111111111111111111";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub program_id_index: u8,
+    pub accounts: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+/// accounts a v0 transaction loads from an address lookup table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup {
+    pub table: Pubkey,
+    pub writable: Vec<u8>,
+    pub readonly: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// `None` for legacy transactions
+    pub version: Option<u8>,
+    pub num_required_signatures: u8,
+    pub num_readonly_signed: u8,
+    pub num_readonly_unsigned: u8,
+    /// the signers first, the first of them pays the fee
+    pub account_keys: Vec<Pubkey>,
+    pub recent_blockhash: Pubkey,
+    pub instructions: Vec<Instruction>,
+    pub lookups: Vec<Lookup>,
+}
+
+/// where the parts of a serialized transaction start
+pub struct Parsed {
+    pub signatures_at: usize,
+    pub message_at: usize,
+    pub message: Message,
+}
+
+fn pubkey(bytes: &mut &[u8]) -> Result<Pubkey, SolanaErr> {
+    Ok(Pubkey(take(bytes, 32)?.try_into().expect("32 bytes")))
+}
+
+fn byte_vec(bytes: &mut &[u8]) -> Result<Vec<u8>, SolanaErr> {
+    let len = short_vec(bytes)?;
+    Ok(take(bytes, len)?.to_vec())
+}
+
+fn u64_at(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// a serialized transaction, signatures then message
+pub fn parse(raw: &[u8]) -> Result<Parsed, SolanaErr> {
+    let mut bytes = raw;
+    let count = short_vec(&mut bytes)?;
+    let signatures_at = raw.len() - bytes.len();
+    take(&mut bytes, 64 * count)?;
+    let message_at = raw.len() - bytes.len();
+    let message = Message::parse(bytes)?;
+    if count != message.num_required_signatures as usize {
+        return Err(SolanaErr::SignatureCount);
+    }
+    Ok(Parsed {
+        signatures_at,
+        message_at,
+        message,
+    })
+}
+
+impl Message {
+    pub fn parse(mut bytes: &[u8]) -> Result<Self, SolanaErr> {
+        let bytes = &mut bytes;
+        let mut first = take(bytes, 1)?[0];
+        // versioned messages set the top bit, a legacy header can't have 128 signers
+        let version = match first & 0x80 {
+            0 => None,
+            _ => {
+                let version = first & 0x7f;
+                if version != 0 {
+                    return Err(SolanaErr::UnsupportedVersion(version));
+                }
+                first = take(bytes, 1)?[0];
+                Some(version)
+            }
+        };
+        let header = take(bytes, 2)?;
+        let keys = short_vec(bytes)?;
+        let account_keys = (0..keys)
+            .map(|_| pubkey(bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        let recent_blockhash = pubkey(bytes)?;
+        let instructions = (0..short_vec(bytes)?)
+            .map(|_| {
+                Ok(Instruction {
+                    program_id_index: take(bytes, 1)?[0],
+                    accounts: byte_vec(bytes)?,
+                    data: byte_vec(bytes)?,
+                })
+            })
+            .collect::<Result<Vec<_>, SolanaErr>>()?;
+        let lookups = match version {
+            None => Vec::new(),
+            Some(_) => (0..short_vec(bytes)?)
+                .map(|_| {
+                    Ok(Lookup {
+                        table: pubkey(bytes)?,
+                        writable: byte_vec(bytes)?,
+                        readonly: byte_vec(bytes)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, SolanaErr>>()?,
+        };
+        if !bytes.is_empty() {
+            return Err(SolanaErr::TrailingBytes);
+        }
+        let message = Self {
+            version,
+            num_required_signatures: first,
+            num_readonly_signed: header[0],
+            num_readonly_unsigned: header[1],
+            account_keys,
+            recent_blockhash,
+            instructions,
+            lookups,
+        };
+        message.validate()?;
+        Ok(message)
+    }
+
+    fn validate(&self) -> Result<(), SolanaErr> {
+        let keys = self.account_keys.len();
+        if self.num_required_signatures == 0 || keys < self.num_required_signatures as usize {
+            return Err(SolanaErr::SignatureCount);
+        }
+        let loaded: usize = self
+            .lookups
+            .iter()
+            .map(|l| l.writable.len() + l.readonly.len())
+            .sum();
+        for ix in &self.instructions {
+            // programs can't come from lookup tables
+            if ix.program_id_index as usize >= keys
+                || ix.accounts.iter().any(|a| *a as usize >= keys + loaded)
+            {
+                return Err(SolanaErr::InvalidAccountIndex);
+            }
+        }
+        Ok(())
+    }
+
+    /// the accounts that sign, in the order of the signature slots
+    pub fn signers(&self) -> &[Pubkey] {
+        &self.account_keys[..self.num_required_signatures as usize]
+    }
+
+    pub fn fee_payer(&self) -> Pubkey {
+        self.account_keys[0]
+    }
+
+    /// the program ids invoked, in order of first use
+    pub fn programs(&self) -> Vec<Pubkey> {
+        let mut programs = Vec::new();
+        for ix in &self.instructions {
+            let program = self.account_keys[ix.program_id_index as usize];
+            if !programs.contains(&program) {
+                programs.push(program);
+            }
+        }
+        programs
+    }
+
+    /// loaded accounts are named after their table, their address isn't in the transaction
+    fn account(&self, ix: &Instruction, i: usize) -> Option<String> {
+        let index = *ix.accounts.get(i)? as usize;
+        if let Some(key) = self.account_keys.get(index) {
+            return Some(key.to_string());
+        }
+        let mut index = index - self.account_keys.len();
+        let loaded = self
+            .lookups
+            .iter()
+            .flat_map(|l| l.writable.iter().map(move |i| (l.table, *i)))
+            .chain(
+                self.lookups
+                    .iter()
+                    .flat_map(|l| l.readonly.iter().map(move |i| (l.table, *i))),
+            );
+        for (table, i) in loaded {
+            if index == 0 {
+                return Some(format!("{}[{}]", table, i));
+            }
+            index -= 1;
+        }
+        None
+    }
+
+    fn system(&self, ix: &Instruction) -> Option<String> {
+        let data = &ix.data;
+        let tag = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        let lamports = u64_at(data, 4)?;
+        let call = match tag {
+            0 => format!(
+                "create account {} with {} lamports from {}, owned by {}",
+                self.account(ix, 1)?,
+                lamports,
+                self.account(ix, 0)?,
+                Pubkey(data.get(20..52)?.try_into().ok()?)
+            ),
+            2 => format!(
+                "transfer {} lamports from {} to {}",
+                lamports,
+                self.account(ix, 0)?,
+                self.account(ix, 1)?
+            ),
+            // TransferWithSeed
+            11 => format!(
+                "transfer {} lamports from {} to {}",
+                lamports,
+                self.account(ix, 0)?,
+                self.account(ix, 2)?
+            ),
+            _ => return None,
+        };
+        Some(call)
+    }
+
+    fn token(&self, ix: &Instruction) -> Option<String> {
+        let data = &ix.data;
+        let amount = u64_at(data, 1)?;
+        let call = match data[0] {
+            3 => format!(
+                "transfer {} from token account {} to {}",
+                amount,
+                self.account(ix, 0)?,
+                self.account(ix, 1)?
+            ),
+            4 => format!(
+                "approve {} to spend {} from token account {}",
+                self.account(ix, 1)?,
+                amount,
+                self.account(ix, 0)?
+            ),
+            12 => format!(
+                "transfer {} of mint {} ({} decimals) from token account {} to {}",
+                amount,
+                self.account(ix, 1)?,
+                data.get(9)?,
+                self.account(ix, 0)?,
+                self.account(ix, 2)?
+            ),
+            13 => format!(
+                "approve {} to spend {} of mint {} ({} decimals) from token account {}",
+                self.account(ix, 2)?,
+                amount,
+                self.account(ix, 1)?,
+                data.get(9)?,
+                self.account(ix, 0)?
+            ),
+            _ => return None,
+        };
+        Some(call)
+    }
+
+    fn compute_budget(&self, ix: &Instruction) -> Option<String> {
+        let data = &ix.data;
+        match data.first()? {
+            2 => Some(format!(
+                "compute unit limit {}",
+                u32::from_le_bytes(data.get(1..5)?.try_into().ok()?)
+            )),
+            3 => Some(format!(
+                "priority fee {} micro-lamports per compute unit",
+                u64_at(data, 1)?
+            )),
+            _ => None,
+        }
+    }
+
+    fn describe_instruction(&self, ix: &Instruction) -> String {
+        let program = self.account_keys[ix.program_id_index as usize];
+        let decoded = match program.to_string().as_str() {
+            SYSTEM_PROGRAM => self.system(ix),
+            TOKEN_PROGRAM | TOKEN_2022_PROGRAM => self.token(ix),
+            COMPUTE_BUDGET_PROGRAM => self.compute_budget(ix),
+            _ => None,
+        };
+        decoded.unwrap_or(format!(
+            "call {} with {} accounts and {} bytes of data",
+            program,
+            ix.accounts.len(),
+            ix.data.len()
+        ))
+    }
+
+    /// one line for the approval prompt, token amounts in the mint's base units
+    pub fn describe(&self) -> String {
+        let kind = match self.version {
+            Some(v) => format!("v{}", v),
+            None => "legacy".into(),
+        };
+        let programs: Vec<String> = self.programs().iter().map(|p| p.to_string()).collect();
+        let instructions: Vec<String> = self
+            .instructions
+            .iter()
+            .map(|ix| self.describe_instruction(ix))
+            .collect();
+        let mut out = format!(
+            "{} Solana transaction paid by {}, programs [{}]: {}",
+            kind,
+            self.fee_payer(),
+            programs.join(", "),
+            instructions.join("; ")
+        );
+        if !self.lookups.is_empty() {
+            out.push_str(&format!(
+                " (loads accounts from {} lookup tables)",
+                self.lookups.len()
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    fn short(len: usize) -> u8 {
+        assert!(len < 0x80);
+        len as u8
+    }
+
+    /// a legacy transaction with zeroed signature slots, `payer` sends `lamports` to
+    /// `to` and a token transfer from `source` to `dest`
+    pub fn transfer(payer: Pubkey, to: Pubkey, lamports: u64) -> Vec<u8> {
+        let token: Pubkey = TOKEN_PROGRAM.parse().unwrap();
+        let (source, dest) = (Pubkey([3; 32]), Pubkey([4; 32]));
+        let keys = [payer, to, source, dest, Pubkey([0; 32]), token];
+        let mut raw = vec![1];
+        raw.extend_from_slice(&[0; 64]);
+        raw.extend_from_slice(&[1, 0, 2, short(keys.len())]);
+        for k in keys {
+            raw.extend_from_slice(&k.0);
+        }
+        raw.extend_from_slice(&[9; 32]);
+        raw.push(2);
+        let mut system = 2u32.to_le_bytes().to_vec();
+        system.extend_from_slice(&lamports.to_le_bytes());
+        raw.extend_from_slice(&[4, 2, 0, 1, short(system.len())]);
+        raw.extend_from_slice(&system);
+        let mut token = vec![3];
+        token.extend_from_slice(&1_000_000u64.to_le_bytes());
+        raw.extend_from_slice(&[5, 3, 2, 3, 0, short(token.len())]);
+        raw.extend_from_slice(&token);
+        raw
+    }
+
+    #[test]
+    fn legacy() {
+        let (payer, to) = (Pubkey([1; 32]), Pubkey([2; 32]));
+        let raw = transfer(payer, to, 5000);
+        let parsed = parse(&raw).unwrap();
+        assert_eq!((parsed.signatures_at, parsed.message_at), (1, 65));
+        let message = parsed.message;
+        assert_eq!(message.signers(), &[payer]);
+        assert_eq!(
+            message.describe(),
+            format!(
+                "legacy Solana transaction paid by {payer}, programs [{SYSTEM_PROGRAM}, \
+                 {TOKEN_PROGRAM}]: transfer 5000 lamports from {payer} to {to}; transfer \
+                 1000000 from token account {} to {}",
+                Pubkey([3; 32]),
+                Pubkey([4; 32])
+            )
+        );
+
+        assert!(matches!(
+            parse(&raw[..raw.len() - 1]),
+            Err(SolanaErr::Truncated)
+        ));
+        let mut trailing = raw.clone();
+        trailing.push(0);
+        assert!(matches!(parse(&trailing), Err(SolanaErr::TrailingBytes)));
+        // one signature slot for a message that needs two
+        let mut signers = raw.clone();
+        signers[65] = 2;
+        assert!(matches!(parse(&signers), Err(SolanaErr::SignatureCount)));
+    }
+
+    #[test]
+    fn versioned() {
+        let program = Pubkey([7; 32]);
+        let table = Pubkey([8; 32]);
+        let mut raw = vec![1];
+        raw.extend_from_slice(&[0; 64]);
+        raw.extend_from_slice(&[0x80, 1, 0, 1, 2]);
+        raw.extend_from_slice(&[1; 32]);
+        raw.extend_from_slice(&program.0);
+        raw.extend_from_slice(&[9; 32]);
+        // one instruction using the payer and the first loaded account
+        raw.extend_from_slice(&[1, 1, 2, 0, 2, 1, 0xaa]);
+        raw.extend_from_slice(&[1]);
+        raw.extend_from_slice(&table.0);
+        raw.extend_from_slice(&[1, 5, 0]);
+        let message = parse(&raw).unwrap().message;
+        assert_eq!(message.version, Some(0));
+        assert_eq!(
+            message.account(&message.instructions[0], 1).unwrap(),
+            format!("{}[5]", table)
+        );
+        assert_eq!(
+            message.describe(),
+            format!(
+                "v0 Solana transaction paid by {}, programs [{program}]: call {program} with 2 \
+                 accounts and 1 bytes of data (loads accounts from 1 lookup tables)",
+                Pubkey([1; 32])
+            )
+        );
+        let mut v1 = raw.clone();
+        v1[65] = 0x81;
+        assert!(matches!(parse(&v1), Err(SolanaErr::UnsupportedVersion(1))));
+        // an account past the loaded ones
+        let mut index = raw;
+        index[65 + 5 + 96 + 4] = 3;
+        assert!(matches!(parse(&index), Err(SolanaErr::InvalidAccountIndex)));
+    }
+}
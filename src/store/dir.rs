@@ -0,0 +1,119 @@
+//! the original layout, one keystore file per key named after it
+use super::{check_name, KeyStore, KeyVersion, StoreErr};
+use crate::secure_fs::{check_permissions, create_private_dir, write_atomic};
+use crate::server::is_valid_string_name;
+use std::fs::{read_dir, remove_file, File};
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// Keystores as files in one directory. Files are replaced in place, so only the
+/// current version is kept.
+pub struct DirKeyStore {
+    dir: PathBuf,
+}
+
+impl DirKeyStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+    fn path(&self, name: &str) -> Result<PathBuf, StoreErr> {
+        check_name(name)?;
+        Ok(self.dir.join(name))
+    }
+}
+
+impl KeyStore for DirKeyStore {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+        let path = self.path(name)?;
+        // `list` only sees regular files, a symlink would be read without being listed
+        let link = match path.symlink_metadata() {
+            Ok(link) => link,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if !link.is_file() {
+            return Err(StoreErr::NotAFile);
+        }
+        let mut file = File::open(&path)?;
+        let opened = file.metadata()?;
+        // swapped between the check and the open
+        if (opened.dev(), opened.ino()) != (link.dev(), link.ino()) {
+            return Err(StoreErr::NotAFile);
+        }
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(Some(contents))
+    }
+    fn put(&self, name: &str, contents: &[u8]) -> Result<(), StoreErr> {
+        let path = self.path(name)?;
+        if !self.dir.exists() {
+            create_private_dir(&self.dir)?;
+        }
+        Ok(write_atomic(&path, contents)?)
+    }
+    fn list(&self) -> Result<Vec<String>, StoreErr> {
+        let mut names = Vec::new();
+        if !self.dir.exists() {
+            return Ok(names);
+        }
+        for entry in read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                if is_valid_string_name(name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+    fn delete(&self, name: &str) -> Result<bool, StoreErr> {
+        match remove_file(self.path(name)?) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+    fn versions(&self, name: &str) -> Result<Vec<KeyVersion>, StoreErr> {
+        let path = self.path(name)?;
+        let Some(contents) = self.get(name)? else {
+            return Ok(Vec::new());
+        };
+        let stored_at = path
+            .metadata()?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(vec![KeyVersion::describe(1, stored_at, &contents)])
+    }
+    fn check_permissions(&self, fix: bool) -> Result<Vec<String>, StoreErr> {
+        Ok(check_permissions(&self.dir, fix)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::secure_fs::test::unique_dir;
+
+    #[test]
+    fn dir_store() {
+        let dir = unique_dir("dir_store_test");
+        let store = DirKeyStore::new(&dir);
+        super::super::test::exercise(&store);
+        // one file per key, nothing else
+        assert!(dir.join("B").is_file());
+        assert!(store.check_permissions(false).unwrap().is_empty());
+        // planted links are neither listed nor read
+        std::os::unix::fs::symlink(dir.join("B"), dir.join("LINK")).unwrap();
+        assert!(!store.list().unwrap().contains(&"LINK".to_string()));
+        assert!(matches!(store.get("LINK"), Err(StoreErr::NotAFile)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
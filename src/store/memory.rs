@@ -0,0 +1,98 @@
+//! keeps everything in memory, for tests and throwaway servers
+use super::{check_name, KeyStore, KeyVersion, StoreErr};
+use crate::crypto::now_secs;
+use crate::server::is_valid_string_name;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use zeroize::Zeroize;
+
+/// oldest first
+type History = Vec<(KeyVersion, Vec<u8>)>;
+
+/// Every version of every key, gone when dropped.
+#[derive(Default)]
+pub struct MemKeyStore {
+    keys: Mutex<BTreeMap<String, History>>,
+}
+
+impl KeyStore for MemKeyStore {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+        check_name(name)?;
+        let keys = self.keys.lock().unwrap();
+        Ok(keys
+            .get(name)
+            .and_then(|versions| versions.last())
+            .map(|(_, contents)| contents.clone()))
+    }
+    fn put(&self, name: &str, contents: &[u8]) -> Result<(), StoreErr> {
+        check_name(name)?;
+        let mut keys = self.keys.lock().unwrap();
+        let versions = keys.entry(name.to_string()).or_default();
+        let version = versions.last().map(|(v, _)| v.version).unwrap_or(0) + 1;
+        if name.starts_with('.') {
+            versions.iter_mut().for_each(|(_, c)| c.zeroize());
+            versions.clear();
+        }
+        versions.push((
+            KeyVersion::describe(version, now_secs(), contents),
+            contents.to_vec(),
+        ));
+        Ok(())
+    }
+    fn list(&self) -> Result<Vec<String>, StoreErr> {
+        let keys = self.keys.lock().unwrap();
+        Ok(keys
+            .keys()
+            .filter(|name| is_valid_string_name(name))
+            .cloned()
+            .collect())
+    }
+    fn delete(&self, name: &str) -> Result<bool, StoreErr> {
+        check_name(name)?;
+        let mut keys = self.keys.lock().unwrap();
+        Ok(match keys.remove(name) {
+            Some(mut versions) => {
+                versions.iter_mut().for_each(|(_, c)| c.zeroize());
+                true
+            }
+            None => false,
+        })
+    }
+    fn versions(&self, name: &str) -> Result<Vec<KeyVersion>, StoreErr> {
+        check_name(name)?;
+        let keys = self.keys.lock().unwrap();
+        Ok(keys
+            .get(name)
+            .map(|versions| versions.iter().map(|(v, _)| v.clone()).collect())
+            .unwrap_or_default())
+    }
+    fn prune(&self, name: &str) -> Result<usize, StoreErr> {
+        check_name(name)?;
+        let mut keys = self.keys.lock().unwrap();
+        let Some(versions) = keys.get_mut(name) else {
+            return Ok(0);
+        };
+        let superseded = versions.len().saturating_sub(1);
+        for (_, mut contents) in versions.drain(..superseded) {
+            contents.zeroize();
+        }
+        Ok(superseded)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mem_store() {
+        let store = MemKeyStore::default();
+        super::super::test::exercise(&store);
+        store.put("B", b"b2").unwrap();
+        let versions = store.versions("B").unwrap();
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Where encrypted keystores live. `HotApi` only sees opaque keystore bytes through
+//! [`KeyStore`], the backend decides which engine it gets (see `BackendImpl::key_store`).
+use crate::crypto::{KeyMeta, Keystore};
+use crate::server::is_valid_string_name;
+use err_mac::create_err_with_impls;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+mod dir;
+mod memory;
+mod sqlite;
+pub use dir::DirKeyStore;
+pub use memory::MemKeyStore;
+pub use sqlite::{SqliteKeyStore, SQLITE_NAME};
+
+create_err_with_impls!(
+    #[derive(Debug)]
+    pub StoreErr,
+    IO(std::io::Error),
+    Sqlite(rusqlite::Error),
+    Serde(serde_json::Error),
+    InvalidName(String),
+    NotAFile
+    ;
+);
+
+/// One stored revision of a key, described from its keystore header without decrypting.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct KeyVersion {
+    /// counts up from 1 with every `put` of the same name
+    pub version: u64,
+    /// keystore format, 3 or 4, 0 if the contents don't parse as a keystore
+    pub format: u8,
+    /// v4 keystores only
+    pub meta: Option<KeyMeta>,
+    /// unix seconds
+    pub stored_at: u64,
+}
+
+impl KeyVersion {
+    pub fn describe(version: u64, stored_at: u64, contents: &[u8]) -> Self {
+        let keystore = Keystore::from_slice(contents).ok();
+        Self {
+            version,
+            format: keystore.as_ref().map(|k| k.version()).unwrap_or(0),
+            meta: keystore.as_ref().and_then(|k| k.meta().cloned()),
+            stored_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    /// unix seconds
+    pub at: u64,
+    pub action: String,
+    pub name: String,
+    /// version written by a `put`
+    pub version: Option<u64>,
+}
+
+/// Which [`KeyStore`] the server uses, `"engine"` in `cheese_config.json`.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    /// one file per key in the store directory
+    #[default]
+    Dir,
+    /// [`SQLITE_NAME`] in the store directory
+    Sqlite,
+}
+
+impl std::str::FromStr for Engine {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dir" => Ok(Self::Dir),
+            "sqlite" => Ok(Self::Sqlite),
+            other => Err(format!("unknown engine '{}'", other)),
+        }
+    }
+}
+
+impl Engine {
+    pub fn key_store(&self, dir: &Path) -> Box<dyn KeyStore> {
+        match self {
+            Self::Dir => Box::new(DirKeyStore::new(dir)),
+            Self::Sqlite => Box::new(SqliteKeyStore::new(dir.join(SQLITE_NAME))),
+        }
+    }
+}
+
+/// Storage engine for keystores. Names are key names, plus dot prefixed ones
+/// (like the manifest) that the store keeps but never lists, in their latest version only.
+pub trait KeyStore: Send + Sync {
+    /// contents of the latest version
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, StoreErr>;
+    /// stores a new version, a reader sees either the old or the new one in full
+    fn put(&self, name: &str, contents: &[u8]) -> Result<(), StoreErr>;
+    /// key names, sorted
+    fn list(&self) -> Result<Vec<String>, StoreErr>;
+    /// `false` if there was nothing to delete
+    fn delete(&self, name: &str) -> Result<bool, StoreErr>;
+    /// what the store remembers about `name`, oldest first, empty if it doesn't exist
+    fn versions(&self, name: &str) -> Result<Vec<KeyVersion>, StoreErr>;
+    /// forgets every version of `name` but the latest, e.g. copies sealed with a weaker
+    /// KDF once it was resealed. how many were dropped
+    fn prune(&self, _name: &str) -> Result<usize, StoreErr> {
+        Ok(0)
+    }
+
+    fn contains(&self, name: &str) -> Result<bool, StoreErr> {
+        Ok(self.get(name)?.is_some())
+    }
+    /// anything others could read, tightened when `fix` is set. one line per finding
+    fn check_permissions(&self, _fix: bool) -> Result<Vec<String>, StoreErr> {
+        Ok(Vec::new())
+    }
+    /// notes an access that changed nothing (e.g. a read), stores without an audit
+    /// trail ignore it
+    fn audit(&self, _action: &str, _name: &str) -> Result<(), StoreErr> {
+        Ok(())
+    }
+    /// oldest first
+    fn audit_log(&self) -> Result<Vec<AuditEntry>, StoreErr> {
+        Ok(Vec::new())
+    }
+}
+
+/// key names and dot prefixed internal ones, nothing that could leave the store
+pub(crate) fn check_name(name: &str) -> Result<(), StoreErr> {
+    let bare = name.strip_prefix('.').unwrap_or(name);
+    if bare.is_empty() || !is_valid_string_name(bare) {
+        return Err(StoreErr::InvalidName(name.into()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// the same contract for every engine
+    pub fn exercise(store: &dyn KeyStore) {
+        assert!(store.list().unwrap().is_empty());
+        assert_eq!(store.get("A").unwrap(), None);
+        assert!(!store.delete("A").unwrap());
+        assert!(store.versions("A").unwrap().is_empty());
+
+        store.put("B", b"b1").unwrap();
+        store.put("A", b"a1").unwrap();
+        store.put("A", b"a2").unwrap();
+        store.put(".internal", b"w").unwrap();
+        store.put(".internal", b"x").unwrap();
+        assert_eq!(store.list().unwrap(), vec!["A", "B"]);
+        assert_eq!(store.versions(".internal").unwrap().len(), 1);
+        assert_eq!(store.get("A").unwrap().unwrap(), b"a2");
+        assert_eq!(store.get(".internal").unwrap().unwrap(), b"x");
+        assert!(store.contains("B").unwrap());
+        let versions = store.versions("A").unwrap();
+        assert_eq!(versions.last().unwrap().format, 0);
+        assert!(versions.windows(2).all(|w| w[0].version < w[1].version));
+        store.prune("A").unwrap();
+        assert_eq!(store.versions("A").unwrap().len(), 1);
+        assert_eq!(store.get("A").unwrap().unwrap(), b"a2");
+
+        assert!(store.delete("A").unwrap());
+        assert_eq!(store.list().unwrap(), vec!["B"]);
+        assert!(matches!(
+            store.put("../A", b""),
+            Err(StoreErr::InvalidName(_))
+        ));
+        assert!(store.get("..").is_err());
+    }
+}
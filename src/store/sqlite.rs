@@ -0,0 +1,272 @@
+//! every key, all its versions and an audit trail in one SQLite file
+use super::{check_name, AuditEntry, KeyStore, KeyVersion, StoreErr};
+use crate::crypto::now_secs;
+use crate::secure_fs::{check_permissions, create_private_dir, FILE_MODE};
+use crate::server::is_valid_string_name;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::fs::{set_permissions, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// file name inside the store directory
+pub const SQLITE_NAME: &str = "hot_cheese.sqlite";
+
+const SCHEMA: &str = "
+PRAGMA secure_delete = ON;
+PRAGMA synchronous = FULL;
+CREATE TABLE IF NOT EXISTS keys (
+    name TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    contents BLOB NOT NULL,
+    format INTEGER NOT NULL,
+    meta TEXT,
+    stored_at INTEGER NOT NULL,
+    PRIMARY KEY (name, version)
+);
+CREATE TABLE IF NOT EXISTS audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    name TEXT NOT NULL,
+    version INTEGER
+);
+";
+
+/// Keeps every version of a key until it is deleted or pruned, internal files only in their
+/// latest version. Each write is one transaction together with its audit entry. Deleted
+/// rows are overwritten (`secure_delete`).
+pub struct SqliteKeyStore {
+    path: PathBuf,
+    /// opened on first use so a missing or broken file shows up as a `StoreErr`
+    conn: Mutex<Option<Connection>>,
+}
+
+impl SqliteKeyStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            conn: Mutex::new(None),
+        }
+    }
+
+    fn open(&self) -> Result<Connection, StoreErr> {
+        if let Some(dir) = self.path.parent() {
+            if !dir.as_os_str().is_empty() && !dir.exists() {
+                create_private_dir(dir)?;
+            }
+        }
+        let conn = Connection::open(&self.path)?;
+        // journal files inherit the mode of the database
+        set_permissions(&self.path, Permissions::from_mode(FILE_MODE))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(conn)
+    }
+
+    fn with_conn<T>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, StoreErr>,
+    ) -> Result<T, StoreErr> {
+        let mut conn = self.conn.lock().unwrap();
+        if conn.is_none() {
+            *conn = Some(self.open()?);
+        }
+        f(conn.as_mut().expect("opened above"))
+    }
+}
+
+fn insert_audit(
+    conn: &Connection,
+    action: &str,
+    name: &str,
+    version: Option<u64>,
+) -> Result<(), StoreErr> {
+    conn.execute(
+        "INSERT INTO audit (at, action, name, version) VALUES (?1, ?2, ?3, ?4)",
+        params![now_secs(), action, name, version],
+    )?;
+    Ok(())
+}
+
+impl KeyStore for SqliteKeyStore {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+        check_name(name)?;
+        self.with_conn(|conn| {
+            Ok(conn
+                .query_row(
+                    "SELECT contents FROM keys WHERE name = ?1 ORDER BY version DESC LIMIT 1",
+                    params![name],
+                    |row| row.get(0),
+                )
+                .optional()?)
+        })
+    }
+    fn put(&self, name: &str, contents: &[u8]) -> Result<(), StoreErr> {
+        check_name(name)?;
+        let described = KeyVersion::describe(0, now_secs(), contents);
+        let meta = described
+            .meta
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        self.with_conn(|conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let version: u64 = tx.query_row(
+                "SELECT COALESCE(MAX(version), 0) + 1 FROM keys WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )?;
+            tx.execute(
+                "INSERT INTO keys (name, version, contents, format, meta, stored_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    name,
+                    version,
+                    contents,
+                    described.format,
+                    meta,
+                    described.stored_at
+                ],
+            )?;
+            insert_audit(&tx, "put", name, Some(version))?;
+            // the manifest and the ledger are rewritten all the time, their history is noise
+            if name.starts_with('.') {
+                tx.execute(
+                    "DELETE FROM keys WHERE name = ?1 AND version < ?2",
+                    params![name, version],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+    fn list(&self) -> Result<Vec<String>, StoreErr> {
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare("SELECT DISTINCT name FROM keys ORDER BY name")?;
+            let names = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(names
+                .into_iter()
+                .filter(|name| is_valid_string_name(name))
+                .collect())
+        })
+    }
+    fn delete(&self, name: &str) -> Result<bool, StoreErr> {
+        check_name(name)?;
+        self.with_conn(|conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let removed = tx.execute("DELETE FROM keys WHERE name = ?1", params![name])?;
+            if removed > 0 {
+                insert_audit(&tx, "delete", name, None)?;
+            }
+            tx.commit()?;
+            Ok(removed > 0)
+        })
+    }
+    fn versions(&self, name: &str) -> Result<Vec<KeyVersion>, StoreErr> {
+        check_name(name)?;
+        self.with_conn(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT version, format, meta, stored_at FROM keys
+                 WHERE name = ?1 ORDER BY version",
+            )?;
+            let rows = stmt
+                .query_map(params![name], |row| {
+                    Ok((
+                        row.get::<_, u64>(0)?,
+                        row.get::<_, u8>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, u64>(3)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            rows.into_iter()
+                .map(|(version, format, meta, stored_at)| {
+                    Ok(KeyVersion {
+                        version,
+                        format,
+                        meta: meta.map(|m| serde_json::from_str(&m)).transpose()?,
+                        stored_at,
+                    })
+                })
+                .collect()
+        })
+    }
+    fn prune(&self, name: &str) -> Result<usize, StoreErr> {
+        check_name(name)?;
+        self.with_conn(|conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let removed = tx.execute(
+                "DELETE FROM keys WHERE name = ?1
+                 AND version < (SELECT MAX(version) FROM keys WHERE name = ?1)",
+                params![name],
+            )?;
+            if removed > 0 {
+                insert_audit(&tx, "prune", name, None)?;
+            }
+            tx.commit()?;
+            Ok(removed)
+        })
+    }
+    fn check_permissions(&self, fix: bool) -> Result<Vec<String>, StoreErr> {
+        match self.path.parent() {
+            Some(dir) if dir.exists() => Ok(check_permissions(dir, fix)?),
+            _ => Ok(Vec::new()),
+        }
+    }
+    fn audit(&self, action: &str, name: &str) -> Result<(), StoreErr> {
+        self.with_conn(|conn| insert_audit(conn, action, name, None))
+    }
+    fn audit_log(&self) -> Result<Vec<AuditEntry>, StoreErr> {
+        self.with_conn(|conn| {
+            let mut stmt =
+                conn.prepare("SELECT at, action, name, version FROM audit ORDER BY id")?;
+            let entries = stmt
+                .query_map([], |row| {
+                    Ok(AuditEntry {
+                        at: row.get(0)?,
+                        action: row.get(1)?,
+                        name: row.get(2)?,
+                        version: row.get(3)?,
+                    })
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(entries)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::secure_fs::test::unique_dir;
+
+    #[test]
+    fn sqlite_store() {
+        let dir = unique_dir("sqlite_store_test");
+        let store = SqliteKeyStore::new(dir.join(SQLITE_NAME));
+        super::super::test::exercise(&store);
+
+        store.put("B", b"b2").unwrap();
+        store.audit("read", "B").unwrap();
+        assert_eq!(
+            store
+                .versions("B")
+                .unwrap()
+                .iter()
+                .map(|v| v.version)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let log = store.audit_log().unwrap();
+        let last = log.last().unwrap();
+        assert_eq!((last.action.as_str(), last.name.as_str()), ("read", "B"));
+        assert!(log.iter().any(|e| e.action == "delete" && e.name == "A"));
+        // survives reopening, only the one file
+        let reopened = SqliteKeyStore::new(dir.join(SQLITE_NAME));
+        assert_eq!(reopened.get("B").unwrap().unwrap(), b"b2");
+        assert!(reopened.check_permissions(false).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}